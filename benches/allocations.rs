@@ -0,0 +1,133 @@
+//! Counts heap allocations per packet on the hot UDP paths.
+//!
+//! Run with `cargo bench --bench allocations`. "copied" is what the connections
+//! used to do on receive: copy the body out of the receive buffer, then decode an
+//! owned packet. "to_raw + header" is how they used to build frames to send.
+
+use ngmp_protocol_impl::framing::{datagram_frames, encode_frame, StreamDecoder};
+use ngmp_protocol_impl::server_launcher::gameplay::{VehicleTransformPacket, VehicleUpdatePacket};
+use ngmp_protocol_impl::server_launcher::{Packet, PacketRef};
+use ngmp_protocol_impl::{PacketHeader, PacketTrait};
+
+use bytes::BytesMut;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 100_000;
+
+fn datagram(packet: &Packet) -> Vec<u8> {
+    let (sig_a, sig_b, raw) = packet.to_raw().unwrap();
+    let header = PacketHeader {
+        sig_a,
+        sig_b,
+        packet_length: raw.len() as u32,
+    };
+    let mut bytes = header.to_bytes().to_vec();
+    bytes.extend_from_slice(&raw);
+    bytes
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up so lazily initialized statics don't show up in the count
+    f();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<32} {:>6.2} allocs/op {:>8.1} ns/op",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    let transform_packet = Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 76561198000000000,
+        vehicle_id: 3,
+        transform: r#"{"pos":[1.0,2.0,3.0],"rot":[0.0,0.0,0.0,1.0],"vel":[0.5,0.0,0.0]}"#
+            .to_string(),
+    });
+    let update_packet = Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id: 76561198000000000,
+        vehicle_id: 3,
+        ms: 16,
+        runtime_data: "x".repeat(512),
+    });
+    let transform = datagram(&transform_packet);
+    let update = datagram(&update_packet);
+
+    for (name, buf) in [("VT", &transform), ("VU", &update)] {
+        bench(&format!("{} copied + owned", name), || {
+            let body = buf[PacketHeader::SIZE..].to_vec();
+            black_box(Packet::from_raw(buf[0] as char, buf[1] as char, &body).unwrap());
+        });
+        bench(&format!("{} owned", name), || {
+            let frame = datagram_frames(buf).unwrap().next().unwrap();
+            black_box(frame.decode::<Packet>().unwrap());
+        });
+        bench(&format!("{} borrowed", name), || {
+            let frame = datagram_frames(buf).unwrap().next().unwrap();
+            black_box(PacketRef::from_frame(frame).unwrap());
+        });
+    }
+
+    // The TCP side: a steady stream of frames through a single decoder
+    let mut decoder = StreamDecoder::new();
+    bench("VT stream borrowed", || {
+        decoder.extend(&transform);
+        let frame = decoder.next_frame().unwrap().unwrap();
+        black_box(PacketRef::from_frame(frame).unwrap());
+    });
+
+    for (name, packet) in [("VT", &transform_packet), ("VU", &update_packet)] {
+        bench(&format!("{} to_raw + header", name), || {
+            let (sig_a, sig_b, mut raw) = packet.to_raw().unwrap();
+            let mut bytes = Vec::new();
+            bytes.push(sig_a as u8);
+            bytes.push(sig_b as u8);
+            bytes.extend_from_slice(&u32::to_le_bytes(raw.len() as u32));
+            bytes.append(&mut raw);
+            black_box(bytes);
+        });
+        let mut write_buf = BytesMut::new();
+        bench(&format!("{} encode_into reused", name), || {
+            write_buf.clear();
+            packet.encode_into(&mut write_buf).unwrap();
+            black_box(write_buf.split());
+        });
+        bench(&format!("{} encode_frame", name), || {
+            black_box(encode_frame(packet).unwrap());
+        });
+    }
+}
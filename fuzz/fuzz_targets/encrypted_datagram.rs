@@ -0,0 +1,71 @@
+//! Input: a series of operations on a sealer/opener pair sharing a key, split on
+//! `0xff` bytes. The first byte of each picks the operation:
+//!
+//! - seal the rest of the bytes as a frame
+//! - open the rest as a datagram, which must never authenticate
+//! - open one of the sealed frames, which must work at most once
+//! - flip a bit in one of the sealed frames and open it, which must fail
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use ngmp_protocol_impl::crypto::{Opener, Sealer, Side, UdpKey};
+use ngmp_protocol_impl::framing::datagram_frames;
+use ngmp_protocol_impl::PacketHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let key = UdpKey([7; UdpKey::SIZE]);
+    let sealer = Sealer::new(&key, Side::Launcher);
+    let mut opener = Opener::new(&key, Side::Server);
+    // (plaintext, sealed frame, opened yet)
+    let mut sealed: Vec<(Vec<u8>, Vec<u8>, bool)> = Vec::new();
+
+    for op in data.split(|&b| b == 0xff) {
+        let Some((&kind, rest)) = op.split_first() else {
+            continue;
+        };
+        match kind % 4 {
+            0 => {
+                let mut buf = BytesMut::new();
+                sealer.seal(rest, &mut buf).unwrap();
+                sealed.push((rest.to_vec(), buf.to_vec(), false));
+            }
+            1 => {
+                if let Ok(frames) = datagram_frames(rest) {
+                    for frame in frames {
+                        assert!(opener.open(&frame).is_err());
+                    }
+                }
+            }
+            2 => {
+                let Some(&index) = rest.first() else { continue };
+                let count = sealed.len();
+                let Some((plain, frame, opened)) = sealed.get_mut(index as usize % count.max(1))
+                else {
+                    continue;
+                };
+                let frame = datagram_frames(frame).unwrap().next().unwrap();
+                // Frames too far behind the newest one fail even if never opened
+                if let Ok(opened_plain) = opener.open(&frame) {
+                    assert!(!*opened);
+                    assert_eq!(&opened_plain, plain);
+                    *opened = true;
+                }
+            }
+            _ => {
+                let [index, position, ..] = rest else { continue };
+                let count = sealed.len();
+                let Some((_, frame, _)) = sealed.get(*index as usize % count.max(1)) else {
+                    continue;
+                };
+                // Only the body, a changed header might not be an EN frame anymore
+                let mut tampered = frame.clone();
+                let body_len = tampered.len() - PacketHeader::SIZE;
+                tampered[PacketHeader::SIZE + *position as usize % body_len] ^= 1;
+                let frame = datagram_frames(&tampered).unwrap().next().unwrap();
+                assert!(opener.open(&frame).is_err());
+            }
+        }
+    }
+});
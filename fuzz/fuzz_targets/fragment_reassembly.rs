@@ -0,0 +1,36 @@
+//! Input: a series of fragment bodies, each prefixed with the sender it comes
+//! from and its length. The reassembler must never go over its memory cap and
+//! anything it hands back has to be made of the chunks that went in.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngmp_protocol_impl::fragment::{self, Reassembler};
+
+use std::time::{Duration, Instant};
+
+const MAX_BUFFERED: usize = 4096;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reassembler = Reassembler::new(Duration::from_secs(2), MAX_BUFFERED);
+    let now = Instant::now();
+    let mut pushed = 0;
+
+    let mut rest = data;
+    while let [sender, len, tail @ ..] = rest {
+        let (body, tail) = tail.split_at((*len as usize).min(tail.len()));
+        rest = tail;
+
+        if let Ok(Some(frame)) = reassembler.push(*sender % 4, body, now) {
+            assert!(frame.len() <= pushed + body.len());
+            let _ = fragment::reassembled_frame(&frame);
+        }
+        pushed += body.len().saturating_sub(fragment::FRAGMENT_HEADER_SIZE);
+        assert!(reassembler.buffered() <= MAX_BUFFERED);
+    }
+
+    // Everything still pending times out
+    reassembler.expire(now + Duration::from_secs(3));
+    assert_eq!(reassembler.pending(), 0);
+    assert_eq!(reassembler.buffered(), 0);
+});
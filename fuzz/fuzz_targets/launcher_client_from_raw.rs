@@ -0,0 +1,16 @@
+//! Input: 2 signature bytes followed by the packet body.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngmp_protocol_impl::{launcher_client::Packet, PacketTrait};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    if let Ok(packet) = Packet::from_raw(data[0] as char, data[1] as char, &data[2..]) {
+        // Anything we accept we must be able to send back out
+        packet.to_raw().expect("decoded packet failed to encode");
+    }
+});
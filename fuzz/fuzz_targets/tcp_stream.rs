@@ -0,0 +1,39 @@
+//! Input: a byte stream as it would arrive over TCP. The first byte picks the
+//! chunk size the stream gets fed into the decoder with, to exercise frames
+//! split across reads. Frames over the size limit have to be rejected.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngmp_protocol_impl::framing::StreamDecoder;
+use ngmp_protocol_impl::ConnectionError;
+use ngmp_protocol_impl::server_launcher::Packet;
+
+const MAX_FRAME_LEN: usize = 1024;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_size, stream)) = data.split_first() else {
+        return;
+    };
+    let chunk_size = chunk_size.max(1) as usize;
+
+    let mut decoder = StreamDecoder::with_max_frame_len(MAX_FRAME_LEN);
+    for chunk in stream.chunks(chunk_size) {
+        decoder.extend(chunk);
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    assert!(frame.data.len() <= MAX_FRAME_LEN);
+                    let _ = frame.decode::<Packet>();
+                }
+                Ok(None) => break,
+                Err(ConnectionError::FrameTooLarge(len)) => {
+                    assert!(len > MAX_FRAME_LEN);
+                    return;
+                }
+                Err(e) => panic!("unexpected error {}", e),
+            }
+        }
+    }
+    assert!(decoder.buffered() <= stream.len());
+});
@@ -0,0 +1,19 @@
+//! Input: a single UDP datagram, possibly carrying several frames.
+//! The borrowed decode has to agree with the owned one.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngmp_protocol_impl::framing::{datagram_frames, decode_datagram};
+use ngmp_protocol_impl::server_launcher::{Packet, PacketRef};
+
+fuzz_target!(|data: &[u8]| {
+    let owned = decode_datagram::<Packet>(data).ok();
+    let borrowed = datagram_frames(data).ok().and_then(|frames| {
+        frames
+            .map(|frame| PacketRef::from_frame(frame).map(PacketRef::into_owned))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+    });
+    assert_eq!(owned, borrowed);
+});
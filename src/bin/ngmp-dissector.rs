@@ -0,0 +1,62 @@
+//! Writes the generated Wireshark Lua dissector to stdout or a file.
+
+use ngmp_protocol_impl::dissector::{generate_lua, DissectorConfig};
+
+const USAGE: &str = "\
+usage: ngmp-dissector [options]
+
+options:
+    --sl-tcp <port>    server <-> launcher TCP port, can be repeated
+    --sl-udp <port>    server <-> launcher UDP port, can be repeated
+    --lc-tcp <port>    launcher <-> client TCP port, can be repeated
+    -o <file>          write to a file instead of stdout";
+
+fn parse_args() -> Result<(DissectorConfig, Option<String>), String> {
+    let mut args = std::env::args().skip(1);
+    let mut config = DissectorConfig::default();
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {}", name));
+        let mut port = |name: &str| {
+            let raw = value(name)?;
+            raw.parse::<u16>()
+                .map_err(|_| format!("invalid port '{}'", raw))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--sl-tcp" => config.server_launcher_tcp_ports.push(port("--sl-tcp")?),
+            "--sl-udp" => config.server_launcher_udp_ports.push(port("--sl-udp")?),
+            "--lc-tcp" => config.launcher_client_tcp_ports.push(port("--lc-tcp")?),
+            "-o" => output = Some(value("-o")?),
+            other => return Err(format!("unknown argument {}", other)),
+        }
+    }
+
+    Ok((config, output))
+}
+
+fn main() {
+    let (config, output) = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let lua = generate_lua(&config);
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, lua) {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", lua),
+    }
+}
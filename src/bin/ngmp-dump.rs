@@ -0,0 +1,364 @@
+//! Prints the frames from a capture file or a hex string in a human readable form.
+
+use ngmp_protocol_impl::capture::{
+    CaptureReader, CaptureRecord, Direction, RecordDecoder, Transport,
+};
+use ngmp_protocol_impl::framing::{self, Frame};
+use ngmp_protocol_impl::{crypto, fragment};
+use ngmp_protocol_impl::{launcher_client, server_launcher, PacketHeader, PacketTrait};
+
+use serde::Serialize;
+
+const USAGE: &str = "\
+usage: ngmp-dump [options] <capture file>
+       ngmp-dump [options] --hex <hex bytes>
+
+options:
+    --family <server|client>   packet family to decode as: server_launcher (default)
+                               or launcher_client
+    --json                     print decoded packets as json instead of debug output
+    --sig <XY>                 only show frames with this signature, can be repeated
+    --player <id>              only show packets for this player/steam id
+    --vehicle <id>             only show packets for this vehicle id";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Family {
+    Server,
+    Client,
+}
+
+enum Input {
+    Capture(String),
+    Hex(String),
+}
+
+struct Options {
+    input: Input,
+    family: Family,
+    json: bool,
+    sigs: Vec<(char, char)>,
+    player: Option<String>,
+    vehicle: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut input = None;
+    let mut family = Family::Server;
+    let mut json = false;
+    let mut sigs = Vec::new();
+    let mut player = None;
+    let mut vehicle = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {}", name));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--hex" => input = Some(Input::Hex(value("--hex")?)),
+            "--json" => json = true,
+            "--family" => {
+                family = match value("--family")?.as_str() {
+                    "server" | "server_launcher" => Family::Server,
+                    "client" | "launcher_client" => Family::Client,
+                    other => return Err(format!("unknown packet family '{}'", other)),
+                }
+            }
+            "--sig" => {
+                let sig = value("--sig")?;
+                let mut chars = sig.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(a), Some(b), None) => sigs.push((a, b)),
+                    _ => return Err(format!("signature must be 2 characters, got '{}'", sig)),
+                }
+            }
+            "--player" => player = Some(value("--player")?),
+            "--vehicle" => vehicle = Some(value("--vehicle")?),
+            other if other.starts_with("--") => return Err(format!("unknown option {}", other)),
+            _ => input = Some(Input::Capture(arg)),
+        }
+    }
+
+    Ok(Options {
+        input: input.ok_or("no input given")?,
+        family,
+        json,
+        sigs,
+        player,
+        vehicle,
+    })
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits = hex
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect::<Vec<char>>();
+    if digits.len() % 2 != 0 {
+        return Err("hex input has an odd number of digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte = pair.iter().collect::<String>();
+            u8::from_str_radix(&byte, 16).map_err(|_| format!("invalid hex byte '{}'", byte))
+        })
+        .collect()
+}
+
+/// Looks for `key` in the packet body, which is the value inside the enum variant.
+fn field_matches(packet: &serde_json::Value, key: &str, expected: &str) -> bool {
+    let body = match packet {
+        serde_json::Value::Object(variant) => variant.values().next(),
+        _ => None,
+    };
+    match body.and_then(|body| body.get(key)) {
+        Some(serde_json::Value::String(s)) => s == expected,
+        Some(value) => expected
+            .parse::<serde_json::Value>()
+            .is_ok_and(|e| &e == value),
+        None => false,
+    }
+}
+
+impl Options {
+    fn has_field_filters(&self) -> bool {
+        self.player.is_some() || self.vehicle.is_some()
+    }
+
+    fn wants_sig(&self, sig_a: char, sig_b: char) -> bool {
+        self.sigs.is_empty() || self.sigs.contains(&(sig_a, sig_b))
+    }
+
+    fn wants_packet(&self, packet: &serde_json::Value) -> bool {
+        if let Some(player) = &self.player {
+            if !field_matches(packet, "player_id", player)
+                && !field_matches(packet, "steam_id", player)
+            {
+                return false;
+            }
+        }
+        if let Some(vehicle) = &self.vehicle {
+            if !field_matches(packet, "vehicle_id", vehicle) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Describes the frames the transport wraps packets in, `None` for anything else.
+fn describe_transport_frame(frame: &Frame) -> Option<String> {
+    if framing::is_heartbeat(frame) {
+        return Some("heartbeat".to_string());
+    }
+    if fragment::is_fragment(frame) {
+        let Some(header) = frame.data.get(..fragment::FRAGMENT_HEADER_SIZE) else {
+            return Some("error: truncated fragment header".to_string());
+        };
+        let id = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let index = u16::from_le_bytes([header[4], header[5]]);
+        let count = u16::from_le_bytes([header[6], header[7]]);
+        return Some(format!(
+            "fragment {} of {} (message {}, {} bytes)",
+            index as u32 + 1,
+            count,
+            id,
+            frame.data.len() - fragment::FRAGMENT_HEADER_SIZE
+        ));
+    }
+    if crypto::is_encrypted(frame) {
+        let Some(sequence) = frame.data.get(..crypto::SEQUENCE_SIZE) else {
+            return Some("error: truncated encrypted frame".to_string());
+        };
+        return Some(format!(
+            "encrypted (sequence {})",
+            u64::from_le_bytes(sequence.try_into().unwrap())
+        ));
+    }
+    None
+}
+
+/// Prints every frame in `bytes`, which come from `record`. Offsets are relative to the
+/// start of `bytes`. Fragments are shown as they are, followed by the reassembled frame
+/// once complete.
+fn dump_frames<T: PacketTrait + std::fmt::Debug + Serialize>(
+    opts: &Options,
+    decoder: &mut RecordDecoder,
+    record: &CaptureRecord,
+    prefix: &str,
+    bytes: &[u8],
+) {
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let Some(header_raw) = rest.get(..PacketHeader::SIZE) else {
+            println!(
+                "{}@{:<6} error: truncated header ({} of {} bytes)",
+                prefix,
+                offset,
+                rest.len(),
+                PacketHeader::SIZE
+            );
+            return;
+        };
+        let header = PacketHeader::from_bytes(header_raw.try_into().unwrap());
+        let frame_end = PacketHeader::SIZE + header.packet_length as usize;
+        let Some(data) = rest.get(PacketHeader::SIZE..frame_end) else {
+            println!(
+                "{}@{:<6} {}{} len={} error: truncated body ({} of {} bytes)",
+                prefix,
+                offset,
+                header.sig_a.escape_default(),
+                header.sig_b.escape_default(),
+                header.packet_length,
+                rest.len() - PacketHeader::SIZE,
+                header.packet_length
+            );
+            return;
+        };
+
+        let line = format!(
+            "{}@{:<6} {}{} len={}",
+            prefix,
+            offset,
+            header.sig_a.escape_default(),
+            header.sig_b.escape_default(),
+            header.packet_length
+        );
+        let frame = Frame {
+            sig_a: header.sig_a,
+            sig_b: header.sig_b,
+            data,
+        };
+
+        // Reassemble even if fragments are filtered out, the result might not be
+        if fragment::is_fragment(&frame) {
+            let wanted = opts.wants_sig(header.sig_a, header.sig_b) && !opts.has_field_filters();
+            match decoder.push_fragment(record, data) {
+                Ok(message) => {
+                    if wanted {
+                        // Can't be `None` for a fragment
+                        println!(
+                            "{} {}",
+                            line,
+                            describe_transport_frame(&frame).unwrap_or_default()
+                        );
+                    }
+                    if let Some(message) = message {
+                        let prefix = format!("{}reassembled ", prefix);
+                        dump_frames::<T>(opts, decoder, record, &prefix, &message);
+                    }
+                }
+                Err(e) => println!("{} error: {}", line, e),
+            }
+            offset += frame_end;
+            continue;
+        }
+
+        if opts.wants_sig(header.sig_a, header.sig_b) {
+            if let Some(description) = describe_transport_frame(&frame) {
+                // Not a packet, so none of the field filters can match
+                if !opts.has_field_filters() {
+                    println!("{} {}", line, description);
+                }
+                offset += frame_end;
+                continue;
+            }
+            match frame.decode::<T>() {
+                Ok(packet) => {
+                    let value = serde_json::to_value(&packet).unwrap_or_default();
+                    if opts.wants_packet(&value) {
+                        if opts.json {
+                            println!("{} {}", line, value);
+                        } else {
+                            println!("{} {:?}", line, packet);
+                        }
+                    }
+                }
+                Err(e) => {
+                    // Field filters can't apply to undecodable packets, show them anyway
+                    println!(
+                        "{} error: {} (body at offset {}..{})",
+                        line,
+                        e,
+                        offset + PacketHeader::SIZE,
+                        offset + frame_end
+                    );
+                }
+            }
+        }
+
+        offset += frame_end;
+    }
+}
+
+fn record_prefix(record: &CaptureRecord) -> String {
+    let peer = match record.peer {
+        Some(addr) => addr.to_string(),
+        None => "-".to_string(),
+    };
+    format!(
+        "{}.{:06} {:?} {:?} {} ",
+        record.timestamp_us / 1_000_000,
+        record.timestamp_us % 1_000_000,
+        record.direction,
+        record.transport,
+        peer
+    )
+}
+
+fn run<T: PacketTrait + std::fmt::Debug + Serialize>(opts: &Options) -> Result<(), String> {
+    match &opts.input {
+        Input::Hex(hex) => {
+            // Not from a capture, so there's no timestamp or peer to show
+            let record = CaptureRecord {
+                timestamp_us: 0,
+                direction: Direction::Inbound,
+                transport: Transport::Udp,
+                peer: None,
+                frame: parse_hex(hex)?,
+            };
+            let mut decoder = RecordDecoder::new();
+            dump_frames::<T>(opts, &mut decoder, &record, "", &record.frame);
+        }
+        Input::Capture(path) => {
+            let reader = CaptureReader::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            let mut decoder = RecordDecoder::new();
+            for (i, record) in reader.enumerate() {
+                let record = record.map_err(|e| format!("record {}: {}", i, e))?;
+                dump_frames::<T>(
+                    opts,
+                    &mut decoder,
+                    &record,
+                    &record_prefix(&record),
+                    &record.frame,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("error: {}\n", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let result = match opts.family {
+        Family::Server => run::<server_launcher::Packet>(&opts),
+        Family::Client => run::<launcher_client::Packet>(&opts),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
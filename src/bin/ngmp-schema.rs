@@ -0,0 +1,52 @@
+//! Exports the protocol schema of both packet families as JSON.
+
+const USAGE: &str = "\
+usage: ngmp-schema [options]
+
+options:
+    --compact    don't pretty print the json
+    -o <file>    write to a file instead of stdout";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut pretty = true;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--compact" => pretty = false,
+            "-o" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    eprintln!("error: missing value for -o\n\n{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => {
+                eprintln!("error: unknown argument: {}\n\n{}", other, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let schema = ngmp_protocol_impl::schema::export();
+    // Serializing a json Value can't fail
+    let json = if pretty {
+        serde_json::to_string_pretty(&schema).unwrap()
+    } else {
+        serde_json::to_string(&schema).unwrap()
+    };
+
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, json + "\n") {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => println!("{}", json),
+    }
+}
@@ -0,0 +1,373 @@
+//! Recording and reading of raw packet traffic.
+//!
+//! A capture file starts with the 7 byte magic `NGMPCAP` followed by a single
+//! version byte (currently `1`). After that it is a plain sequence of records,
+//! all integers little endian:
+//!
+//! | size      | field                                                   |
+//! |-----------|---------------------------------------------------------|
+//! | 8         | timestamp, microseconds since the UNIX epoch (u64)      |
+//! | 1         | direction (0 = inbound, 1 = outbound)                   |
+//! | 1         | transport (0 = tcp, 1 = udp)                            |
+//! | 1         | peer address family (0 = unknown, 4 = ipv4, 6 = ipv6)   |
+//! | 0, 4, 16  | peer ip octets                                          |
+//! | 0, 2      | peer port (u16, only present if the family is known)    |
+//! | 4         | frame length (u32)                                      |
+//! | n         | frame bytes, including the 6 byte packet header         |
+//!
+//! For UDP the frame bytes are the whole datagram as it was sent/received, which
+//! can hold several frames. Fragments of a message may be spread over several records,
+//! [`RecordDecoder`] puts them back together.
+
+use crate::fragment::{self, Reassembler};
+use crate::framing::{self, datagram_frames};
+use crate::*;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const CAPTURE_MAGIC: &[u8; 7] = b"NGMPCAP";
+pub const CAPTURE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// Microseconds since the UNIX epoch.
+    pub timestamp_us: u64,
+    pub direction: Direction,
+    pub transport: Transport,
+    pub peer: Option<SocketAddr>,
+    pub frame: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn header(&self) -> Option<PacketHeader> {
+        let header_raw = self.frame.get(..PacketHeader::SIZE)?;
+        Some(PacketHeader::from_bytes(header_raw.try_into().ok()?))
+    }
+
+    /// Decodes every packet in the record. Fragmented messages only come out if
+    /// all of their fragments are in this record, see [`RecordDecoder`].
+    pub fn decode<T: PacketTrait>(&self) -> Result<Vec<T>, CaptureError> {
+        RecordDecoder::new().decode(self)
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.timestamp_us.to_le_bytes())?;
+        w.write_all(&[
+            match self.direction {
+                Direction::Inbound => 0,
+                Direction::Outbound => 1,
+            },
+            match self.transport {
+                Transport::Tcp => 0,
+                Transport::Udp => 1,
+            },
+        ])?;
+        match self.peer {
+            None => w.write_all(&[0])?,
+            Some(addr) => {
+                match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        w.write_all(&[4])?;
+                        w.write_all(&ip.octets())?;
+                    }
+                    IpAddr::V6(ip) => {
+                        w.write_all(&[6])?;
+                        w.write_all(&ip.octets())?;
+                    }
+                }
+                w.write_all(&addr.port().to_le_bytes())?;
+            }
+        }
+        w.write_all(&(self.frame.len() as u32).to_le_bytes())?;
+        w.write_all(&self.frame)
+    }
+
+    /// Returns `Ok(None)` on a clean end of file at a record boundary.
+    fn read_from<R: Read>(r: &mut R) -> Result<Option<Self>, CaptureError> {
+        let mut timestamp = [0u8; 8];
+        // Distinguish a clean EOF from a truncated record
+        let first = loop {
+            match r.read(&mut timestamp[..1]) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if first == 0 {
+            return Ok(None);
+        }
+        read_exact(r, &mut timestamp[1..])?;
+        let timestamp_us = u64::from_le_bytes(timestamp);
+
+        let mut kind = [0u8; 3];
+        read_exact(r, &mut kind)?;
+        let direction = match kind[0] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            _ => return Err(CaptureError::CorruptRecord),
+        };
+        let transport = match kind[1] {
+            0 => Transport::Tcp,
+            1 => Transport::Udp,
+            _ => return Err(CaptureError::CorruptRecord),
+        };
+        let ip = match kind[2] {
+            0 => None,
+            4 => {
+                let mut octets = [0u8; 4];
+                read_exact(r, &mut octets)?;
+                Some(IpAddr::V4(Ipv4Addr::from(octets)))
+            }
+            6 => {
+                let mut octets = [0u8; 16];
+                read_exact(r, &mut octets)?;
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => return Err(CaptureError::CorruptRecord),
+        };
+        let peer = match ip {
+            Some(ip) => {
+                let mut port = [0u8; 2];
+                read_exact(r, &mut port)?;
+                Some(SocketAddr::new(ip, u16::from_le_bytes(port)))
+            }
+            None => None,
+        };
+
+        let mut frame_len = [0u8; 4];
+        read_exact(r, &mut frame_len)?;
+        // Read through `take` so a corrupt length can't make us allocate gigabytes upfront
+        let frame_len = u32::from_le_bytes(frame_len) as u64;
+        let mut frame = Vec::new();
+        r.take(frame_len).read_to_end(&mut frame)?;
+        if frame.len() as u64 != frame_len {
+            return Err(CaptureError::CorruptRecord);
+        }
+
+        Ok(Some(Self {
+            timestamp_us,
+            direction,
+            transport,
+            peer,
+            frame,
+        }))
+    }
+}
+
+fn read_exact<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<(), CaptureError> {
+    r.read_exact(buf).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            CaptureError::CorruptRecord
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Decodes the packets of a series of records, reassembling fragments across them.
+/// Fragments are timed out by the recorded timestamps, not the time of decoding.
+#[derive(Debug, Default)]
+pub struct RecordDecoder {
+    reassembler: Reassembler<(Direction, Transport, Option<SocketAddr>)>,
+    /// The first record's timestamp and when it was decoded.
+    started: Option<(u64, Instant)>,
+}
+
+impl RecordDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes every packet in `record`. A fragment that completes a message
+    /// yields its packet, other fragments and heartbeats yield nothing.
+    pub fn decode<T: PacketTrait>(
+        &mut self,
+        record: &CaptureRecord,
+    ) -> Result<Vec<T>, CaptureError> {
+        let mut packets = Vec::new();
+        for frame in datagram_frames(&record.frame)? {
+            if framing::is_heartbeat(&frame) {
+                continue;
+            }
+            if fragment::is_fragment(&frame) {
+                if let Some(message) = self.push_fragment(record, frame.data)? {
+                    packets.push(fragment::reassembled_frame(&message)?.decode()?);
+                }
+                continue;
+            }
+            packets.push(frame.decode()?);
+        }
+        Ok(packets)
+    }
+
+    /// Adds the body of a fragment frame found in `record`. Returns the reassembled
+    /// frame, header included, once all of its fragments are in.
+    pub fn push_fragment(
+        &mut self,
+        record: &CaptureRecord,
+        fragment: &[u8],
+    ) -> Result<Option<Vec<u8>>, ConnectionError> {
+        let (first_us, started) = *self
+            .started
+            .get_or_insert_with(|| (record.timestamp_us, Instant::now()));
+        let now = started + Duration::from_micros(record.timestamp_us.saturating_sub(first_us));
+        let sender = (record.direction, record.transport, record.peer);
+        self.reassembler.push(sender, fragment, now)
+    }
+}
+
+/// Hook that gets called with every frame a connection sends or receives.
+pub trait Recorder: Send + Sync {
+    fn record(
+        &self,
+        direction: Direction,
+        transport: Transport,
+        peer: Option<SocketAddr>,
+        frame: &[u8],
+    );
+}
+
+/// Writes frames into a capture file, see the module docs for the format.
+pub struct CaptureWriter<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> CaptureWriter<W> {
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_all(&[CAPTURE_VERSION])?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn write_record(&self, record: &CaptureRecord) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        record.write_to(&mut *writer)
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write + Send> Recorder for CaptureWriter<W> {
+    fn record(
+        &self,
+        direction: Direction,
+        transport: Transport,
+        peer: Option<SocketAddr>,
+        frame: &[u8],
+    ) {
+        let timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        let record = CaptureRecord {
+            timestamp_us,
+            direction,
+            transport,
+            peer,
+            frame: frame.to_vec(),
+        };
+        if let Err(e) = self.write_record(&record) {
+            error!("failed to write capture record: {}", e);
+        }
+    }
+}
+
+/// Reads records back from a capture file.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    failed: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CaptureError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                CaptureError::InvalidMagic
+            } else {
+                e.into()
+            }
+        })?;
+        if &magic[..7] != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidMagic);
+        }
+        if magic[7] != CAPTURE_VERSION {
+            return Err(CaptureError::UnsupportedVersion(magic[7]));
+        }
+        Ok(Self {
+            reader,
+            failed: false,
+        })
+    }
+
+    pub fn read_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        CaptureRecord::read_from(&mut self.reader)
+    }
+
+    /// Yields every record together with the packets decoded from it, see [`RecordDecoder`].
+    pub fn packets<T: PacketTrait>(
+        self,
+    ) -> impl Iterator<Item = Result<(CaptureRecord, Vec<T>), CaptureError>> {
+        let mut decoder = RecordDecoder::new();
+        self.map(move |record| {
+            let record = record?;
+            let packets = decoder.decode(&record)?;
+            Ok((record, packets))
+        })
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Once a record is corrupt we can't find the next record boundary anymore
+        if self.failed {
+            return None;
+        }
+        let record = self.read_record();
+        self.failed = record.is_err();
+        record.transpose()
+    }
+}
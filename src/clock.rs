@@ -0,0 +1,175 @@
+//! Round trip time and clock offset estimation from ping/pong exchanges.
+//!
+//! Every pong carries three timestamps: when the ping left (echoed back), the
+//! responder's clock when it answered, and implicitly when the pong arrived. Assuming
+//! both directions take equally long, the responder's clock was `ping_ms + rtt / 2`
+//! on our clock when it answered. Queueing delays break that assumption, so the
+//! offset is taken from the recent exchange with the lowest round trip time.
+
+use crate::server_launcher::generic::{PingPacket, PongPacket};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Lower bound for [`RttEstimator::rto`]. Far below the 1s of RFC 6298, game
+/// traffic would rather resend a little too early than stall.
+pub const MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Pongs claiming a longer round trip than this are assumed to be stale or bogus.
+pub const MAX_RTT: Duration = Duration::from_secs(30);
+
+/// Milliseconds since the clock was created, wrapping around like
+/// [`crate::server_launcher::gameplay::VehicleUpdatePacket::ms`].
+#[derive(Debug, Clone, Copy)]
+pub struct LocalClock {
+    epoch: Instant,
+}
+
+impl Default for LocalClock {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl LocalClock {
+    pub fn new(epoch: Instant) -> Self {
+        Self { epoch }
+    }
+
+    pub fn ms_at(&self, at: Instant) -> u32 {
+        at.saturating_duration_since(self.epoch).as_millis() as u32
+    }
+
+    /// The instant `ms` refers to, taking the one closest to `now` when it wrapped around.
+    pub fn instant_at(&self, ms: u32, now: Instant) -> Instant {
+        let diff = ms.wrapping_sub(self.ms_at(now)) as i32;
+        let offset = Duration::from_millis(diff.unsigned_abs() as u64);
+        if diff >= 0 {
+            now + offset
+        } else {
+            now.checked_sub(offset).unwrap_or(self.epoch)
+        }
+    }
+}
+
+/// Smoothed round trip time as in RFC 6298.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    min_rtt: Option<Duration>,
+}
+
+impl RttEstimator {
+    pub fn update(&mut self, sample: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.abs_diff(sample);
+                self.rttvar = (self.rttvar * 3 + deviation) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+        self.min_rtt = Some(self.min_rtt.map_or(sample, |min| min.min(sample)));
+    }
+
+    /// `None` until the first sample.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+
+    /// How long to wait for an answer before considering a packet lost.
+    pub fn rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + self.rttvar * 4).max(MIN_RTO),
+            None => Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    rtt_ms: u32,
+    offset_ms: i32,
+}
+
+/// Keeps track of the round trip time and clock offset towards a single peer.
+#[derive(Debug)]
+pub struct ClockSync {
+    clock: LocalClock,
+    rtt: RttEstimator,
+    samples: VecDeque<Sample>,
+    window: usize,
+}
+
+impl ClockSync {
+    /// Offsets are estimated from the last `window` exchanges.
+    pub fn new(clock: LocalClock, window: usize) -> Self {
+        Self {
+            clock,
+            rtt: RttEstimator::default(),
+            samples: VecDeque::with_capacity(window),
+            window: window.max(1),
+        }
+    }
+
+    pub fn clock(&self) -> &LocalClock {
+        &self.clock
+    }
+
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    pub fn ping(&self, now: Instant) -> PingPacket {
+        PingPacket {
+            ms: self.clock.ms_at(now),
+        }
+    }
+
+    /// Returns the round trip time of this exchange, `None` if the pong was ignored.
+    pub fn on_pong(&mut self, pong: &PongPacket, now: Instant) -> Option<Duration> {
+        let rtt_ms = self.clock.ms_at(now).wrapping_sub(pong.ping_ms);
+        let rtt = Duration::from_millis(rtt_ms as u64);
+        if rtt > MAX_RTT {
+            debug!("ignoring pong with a round trip time of {rtt_ms}ms");
+            return None;
+        }
+        let offset_ms = pong.ms.wrapping_sub(pong.ping_ms.wrapping_add(rtt_ms / 2)) as i32;
+
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { rtt_ms, offset_ms });
+        self.rtt.update(rtt);
+        Some(rtt)
+    }
+
+    /// How far the remote clock is ahead of ours, in milliseconds.
+    pub fn offset_ms(&self) -> Option<i32> {
+        self.samples
+            .iter()
+            .min_by_key(|sample| sample.rtt_ms)
+            .map(|sample| sample.offset_ms)
+    }
+
+    /// Converts a timestamp of the remote clock, e.g. from a vehicle update, into ours.
+    pub fn to_local_ms(&self, remote_ms: u32) -> Option<u32> {
+        Some(remote_ms.wrapping_sub(self.offset_ms()? as u32))
+    }
+
+    pub fn to_local_instant(&self, remote_ms: u32, now: Instant) -> Option<Instant> {
+        Some(self.clock.instant_at(self.to_local_ms(remote_ms)?, now))
+    }
+}
@@ -1,129 +1,289 @@
 // TODO: Replace anyhow::Result<> with proper error handling everywhere
 //       in this file.
 
+use crate::capture::{Direction, Recorder, Transport};
+use crate::crypto::{self, Opener, Sealer, Side, UdpKey};
+use crate::fragment::{self, Reassembler};
+use crate::framing::{
+    self, datagram_frames, DatagramBatch, DatagramFrames, Frame, StreamDecoder, DEFAULT_MTU,
+};
+use crate::interest::InterestManager;
 use crate::*;
 
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use bytes::BytesMut;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs, UdpSocket};
+
+/// When to send heartbeats and when to give up on a silent peer.
+/// Both are off by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// Send a heartbeat whenever nothing else was sent for this long.
+    pub heartbeat_interval: Option<Duration>,
+    /// Fail with [`ConnectionError::Timeout`] once nothing, not even a heartbeat,
+    /// was received for this long. Only makes sense if the peer sends heartbeats.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Heartbeat and idle timeout bookkeeping of a single connection.
+#[derive(Debug)]
+struct Keepalive {
+    config: KeepaliveConfig,
+    last_read: tokio::time::Instant,
+    last_write: tokio::time::Instant,
+}
+
+impl Keepalive {
+    fn new(config: KeepaliveConfig) -> Self {
+        let now = tokio::time::Instant::now();
+        Self {
+            config,
+            last_read: now,
+            last_write: now,
+        }
+    }
+
+    fn heartbeat_at(&self) -> Option<tokio::time::Instant> {
+        Some(self.last_write + self.config.heartbeat_interval?)
+    }
+
+    fn timeout_at(&self) -> Option<tokio::time::Instant> {
+        Some(self.last_read + self.config.idle_timeout?)
+    }
+
+    /// When `wait_for_packet` has to stop waiting to send a heartbeat or time out.
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        match (self.heartbeat_at(), self.timeout_at()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Whether a heartbeat has to be sent now, fails if the peer timed out.
+    fn check(&self) -> Result<bool, ConnectionError> {
+        let now = tokio::time::Instant::now();
+        if self.timeout_at().is_some_and(|at| at <= now) {
+            return Err(ConnectionError::Timeout);
+        }
+        Ok(self.heartbeat_at().is_some_and(|at| at <= now))
+    }
+}
+
+/// A reliable byte stream [`TcpConnection`] can run over, e.g. TCP, TLS, a Unix socket
+/// or an in-memory [`tokio::io::duplex`] pipe. Other streams, like WebSocket adapters,
+/// only need an empty impl, or go through [`TcpConnection::from_io`] instead.
+pub trait StreamTransport: AsyncRead + AsyncWrite + Unpin {
+    /// The remote end if it's an IP connection, only used in captures.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl StreamTransport for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl StreamTransport for tokio::net::UnixStream {}
+
+impl StreamTransport for DuplexStream {}
+
+impl<S: StreamTransport + ?Sized> StreamTransport for Box<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        (**self).peer_addr()
+    }
+}
 
 /// A generic connection to be used anywhere it's needed.
-/// Purely handles sending/receiving packets.
-pub struct TcpConnection<T: PacketTrait> {
+/// Purely handles sending/receiving packets, over a plain [`TcpStream`] unless
+/// `S` says otherwise, see [`StreamTransport`].
+pub struct TcpConnection<T: PacketTrait, S = TcpStream> {
     packet_type: std::marker::PhantomData<T>,
-    tcp: TcpStream,
-    buf: Vec<u8>,
+    tcp: S,
+    peer_addr: Option<SocketAddr>,
+    decoder: StreamDecoder,
+    write_buf: BytesMut,
+    keepalive: Keepalive,
+    recorder: Option<Arc<dyn Recorder>>,
 }
 
 impl<T: PacketTrait> TcpConnection<T> {
     pub fn from_stream(tcp: TcpStream) -> Self {
+        Self::new(tcp)
+    }
+}
+
+impl<T: PacketTrait, S: StreamTransport> TcpConnection<T, S> {
+    pub fn new(stream: S) -> Self {
+        let peer_addr = stream.peer_addr();
+        Self::from_io(stream, peer_addr)
+    }
+}
+
+impl<T: PacketTrait, S: AsyncRead + AsyncWrite + Unpin> TcpConnection<T, S> {
+    /// Wraps any byte stream, even one that isn't a [`StreamTransport`].
+    /// `peer_addr` only ends up in captures.
+    pub fn from_io(tcp: S, peer_addr: Option<SocketAddr>) -> Self {
         Self {
             packet_type: std::marker::PhantomData,
             tcp,
-            buf: Vec::new(),
+            peer_addr,
+            decoder: StreamDecoder::new(),
+            write_buf: BytesMut::new(),
+            keepalive: Keepalive::new(KeepaliveConfig::default()),
+            recorder: None,
         }
     }
 
-    fn read_to_buf(&mut self) -> anyhow::Result<usize> {
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.tcp
+    }
+
+    /// Resets the idle timer.
+    pub fn set_keepalive(&mut self, config: KeepaliveConfig) {
+        self.keepalive = Keepalive::new(config);
+    }
+
+    /// Every frame sent or received from now on gets passed to the recorder.
+    pub fn set_recorder(&mut self, recorder: Arc<dyn Recorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Incoming frames with a longer body fail with [`ConnectionError::FrameTooLarge`],
+    /// defaults to [`DEFAULT_MAX_FRAME_LEN`](crate::framing::DEFAULT_MAX_FRAME_LEN).
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.decoder.set_max_frame_len(max_frame_len);
+    }
+
+    fn record(&self, direction: Direction, frame: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(direction, Transport::Tcp, self.peer_addr, frame);
+        }
+    }
+
+    async fn read_to_buf(&mut self) -> anyhow::Result<usize> {
         // TODO: Figure out an appropriate length, maybe 4096 is too short
         let mut big_buf = [0u8; 4096];
 
-        let read = match self.tcp.try_read(&mut big_buf) {
-            Ok(n) => n,
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::WouldBlock {
-                    return Ok(0);
-                }
-                return Err(e.into());
+        // Only takes what's already there, like `TcpStream::try_read` but for any stream
+        let mut read_buf = ReadBuf::new(&mut big_buf);
+        let polled = std::future::poll_fn(|cx| {
+            Poll::Ready(Pin::new(&mut self.tcp).poll_read(cx, &mut read_buf))
+        })
+        .await;
+        let read = match polled {
+            Poll::Ready(result) => {
+                result?;
+                read_buf.filled().len()
             }
+            Poll::Pending => return Ok(0),
         };
 
-        self.buf.extend_from_slice(&big_buf[..read]);
+        if read > 0 {
+            self.keepalive.last_read = tokio::time::Instant::now();
+        }
+        self.decoder.extend(&big_buf[..read]);
 
         Ok(read)
     }
 
-    /// Assumes the socket is already readable.
-    /// NOTE: This function WILL and SHOULD block until all bytes can be read from the buffer
-    async fn read_bytes(&mut self, bytes: usize) -> anyhow::Result<Vec<u8>> {
-        if bytes > self.buf.len() {
-            let left_to_read = bytes - self.buf.len();
-            let mut buf = vec![0u8; left_to_read];
-            self.tcp.read_exact(&mut buf).await?;
-            self.buf.append(&mut buf);
-        }
-
-        Ok(self.buf.drain(..bytes).collect::<Vec<u8>>())
+    /// Writes already encoded frames, e.g. from [`framing::encode_frame`](crate::framing::encode_frame).
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.tcp.write_all(bytes).await?;
+        // Buffering streams like TLS may otherwise hold on to the tail until the next write
+        self.tcp.flush().await?;
+        self.keepalive.last_write = tokio::time::Instant::now();
+        self.record(Direction::Outbound, bytes);
+        Ok(())
     }
 
-    async fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
-        let mut bytes = std::io::Cursor::new(bytes);
-        self.tcp.write_all_buf(&mut bytes).await?;
+    /// Sends a heartbeat if one is due and fails with [`ConnectionError::Timeout`] if the
+    /// peer went silent, see [`KeepaliveConfig`]. `wait_for_packet` takes care of this
+    /// by itself, call it regularly when polling with `try_read_packet` instead.
+    pub async fn keepalive(&mut self) -> anyhow::Result<()> {
+        if self.keepalive.check()? {
+            self.write_bytes(&framing::HEARTBEAT).await?;
+        }
         Ok(())
     }
 
-    async fn read_packet_header(&mut self) -> anyhow::Result<PacketHeader> {
-        let header_raw = self.read_bytes(6).await?;
-        let sig_a = header_raw[0] as char;
-        let sig_b = header_raw[1] as char;
-        let packet_length =
-            u32::from_le_bytes([header_raw[2], header_raw[3], header_raw[4], header_raw[5]]);
-        Ok(PacketHeader {
-            sig_a,
-            sig_b,
-            packet_length,
-        })
+    fn next_buffered_packet(&mut self) -> anyhow::Result<Option<T>> {
+        while let Some(frame) = self.decoder.next_frame()? {
+            if let Some(recorder) = &self.recorder {
+                let mut bytes = frame.header().to_bytes().to_vec();
+                bytes.extend_from_slice(frame.data);
+                recorder.record(Direction::Inbound, Transport::Tcp, self.peer_addr, &bytes);
+            }
+            if framing::is_heartbeat(&frame) {
+                continue;
+            }
+            return Ok(Some(frame.decode()?));
+        }
+        Ok(None)
     }
 
     /// TODO: Check if socket is readable?
     pub async fn wait_for_packet(&mut self) -> anyhow::Result<T> {
-        let packet_header = self.read_packet_header().await?;
-        let packet_data = self
-            .read_bytes(packet_header.packet_length as usize)
-            .await?;
-        Ok(T::from_raw(
-            packet_header.sig_a,
-            packet_header.sig_b,
-            packet_data,
-        )?)
-    }
-
-    pub async fn try_read_packet(&mut self) -> anyhow::Result<Option<T>> {
-        self.read_to_buf()?;
-
-        // Read potential packet from self.buf now
-        if self.buf.len() < 6 {
-            return Ok(None);
-        }
-        let header_buf = &self.buf[..6];
-        let sig_a = header_buf[0] as char;
-        let sig_b = header_buf[1] as char;
-        let packet_length =
-            u32::from_le_bytes([header_buf[2], header_buf[3], header_buf[4], header_buf[5]]);
+        loop {
+            if let Some(packet) = self.next_buffered_packet()? {
+                return Ok(packet);
+            }
 
-        // Once we know we have enough data to also read the packet data, we can start draining
-        if self.buf.len() < packet_length as usize {
-            return Ok(None);
+            let mut big_buf = [0u8; 4096];
+            let read = match self.keepalive.deadline() {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline, self.tcp.read(&mut big_buf)).await {
+                        Ok(read) => read?,
+                        Err(_) => {
+                            self.keepalive().await?;
+                            continue;
+                        }
+                    }
+                }
+                None => self.tcp.read(&mut big_buf).await?,
+            };
+            if read == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            self.keepalive.last_read = tokio::time::Instant::now();
+            self.decoder.extend(&big_buf[..read]);
         }
-        self.buf.drain(..6);
-        let data_buf = self
-            .buf
-            .drain(..(packet_length as usize))
-            .collect::<Vec<u8>>();
+    }
 
-        Ok(Some(T::from_raw(sig_a, sig_b, data_buf)?))
+    pub async fn try_read_packet(&mut self) -> anyhow::Result<Option<T>> {
+        self.read_to_buf().await?;
+        self.next_buffered_packet()
     }
 
     pub async fn write_packet(&mut self, packet: &T) -> anyhow::Result<()> {
-        let (sig_a, sig_b, mut raw) = packet.to_raw()?;
-        let mut bytes = Vec::new(); // TODO: with_capacity for less allocations and more performance
-        bytes.push(sig_a as u8);
-        bytes.push(sig_b as u8);
-        bytes.extend_from_slice(&mut u32::to_le_bytes(raw.len() as u32));
-        bytes.append(&mut raw);
-        self.write_bytes(&bytes).await?;
+        // The buffer gets reused, once the frame is dropped its memory is reclaimed
+        // by the next `encode_into`
+        self.write_buf.clear();
+        packet.encode_into(&mut self.write_buf)?;
+        let frame = self.write_buf.split();
+        self.write_bytes(&frame).await
+    }
+
+    /// Sends a last packet, usually a disconnect, and shuts the connection down
+    /// once it has been flushed out.
+    pub async fn close_with(mut self, packet: &T) -> anyhow::Result<()> {
+        self.write_packet(packet).await?;
+        self.tcp.shutdown().await?;
         Ok(())
     }
 }
@@ -133,6 +293,29 @@ pub struct UdpListener<T: PacketTrait> {
 
     udp_socket: Arc<UdpSocket>,
     recv_buf: Vec<u8>,
+    /// Packets from a datagram that carried more than one frame.
+    pending: VecDeque<(T, SocketAddr)>,
+    reassembler: Reassembler<SocketAddr>,
+    write_buf: BytesMut,
+    /// Datagrams of the packet currently being sent by `write_packet`/`broadcast`.
+    write_batch: DatagramBatch,
+    /// Frames queued per target until the next `flush`.
+    batches: HashMap<SocketAddr, DatagramBatch>,
+    /// Datagrams of a broadcast to a peer with a key.
+    seal_batch: DatagramBatch,
+    seal_buf: BytesMut,
+    /// Peers that have to encrypt, see [`Self::issue_key`].
+    openers: HashMap<SocketAddr, Opener>,
+    sealers: Sealers,
+    mtu: usize,
+    recorder: Option<Arc<dyn Recorder>>,
+}
+
+/// Shared between a listener and its senders, so they encrypt for peers added later on.
+type Sealers = Arc<RwLock<HashMap<SocketAddr, Arc<Sealer>>>>;
+
+fn sealer_for(sealers: &Sealers, target: SocketAddr) -> Option<Arc<Sealer>> {
+    sealers.read().unwrap().get(&target).cloned()
 }
 
 impl<T: PacketTrait> UdpListener<T> {
@@ -141,49 +324,164 @@ impl<T: PacketTrait> UdpListener<T> {
             packet_type: std::marker::PhantomData,
             udp_socket: Arc::new(UdpSocket::bind(addr).await?),
             recv_buf: vec![0u8; 65535],
+            pending: VecDeque::new(),
+            reassembler: Reassembler::default(),
+            write_buf: BytesMut::new(),
+            write_batch: DatagramBatch::default(),
+            batches: HashMap::new(),
+            seal_batch: DatagramBatch::default(),
+            seal_buf: BytesMut::new(),
+            openers: HashMap::new(),
+            sealers: Sealers::default(),
+            mtu: DEFAULT_MTU,
+            recorder: None,
         })
     }
 
-    fn packet_from_buf(&self, addr: SocketAddr, buf: &[u8]) -> anyhow::Result<(T, SocketAddr)> {
-        if buf.len() < 6 {
-            return Err(ConnectionError::InvalidPacketSize.into());
+    /// Every datagram sent or received from now on gets passed to the recorder.
+    /// With a key, frames are recorded one by one in plaintext instead, outgoing ones
+    /// as they get sealed.
+    pub fn set_recorder(&mut self, recorder: Arc<dyn Recorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Maximum size of the datagrams packets get packed into, anything bigger
+    /// gets fragmented. See [`DatagramBatch`].
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+        self.write_batch.set_mtu(mtu);
+        self.seal_batch.set_mtu(mtu);
+        for batch in self.batches.values_mut() {
+            batch.set_mtu(mtu);
         }
+    }
 
-        let sig_a = buf[0] as char;
-        let sig_b = buf[1] as char;
-        let packet_length = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+    /// Limits for putting fragmented packets back together, shared by all peers.
+    /// Drops everything that is currently only partially received.
+    pub fn set_reassembly_limits(&mut self, timeout: Duration, max_buffered: usize) {
+        self.reassembler = Reassembler::new(timeout, max_buffered);
+    }
 
-        if buf.len() < 6 + packet_length as usize {
-            return Err(ConnectionError::InvalidPacketSize.into());
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.udp_socket.local_addr()
+    }
+
+    /// Generates a new key for `peer`, to be sent to it in a
+    /// [`UdpKeyPacket`](crate::server_launcher::handshake::UdpKeyPacket). Seals every
+    /// frame to `peer` with it from now on, and rejects anything from it that isn't
+    /// sealed. See [`crate::crypto`].
+    pub fn issue_key(&mut self, peer: SocketAddr) -> UdpKey {
+        let key = UdpKey::generate();
+        self.openers.insert(peer, Opener::new(&key, Side::Server));
+        self.sealers
+            .write()
+            .unwrap()
+            .insert(peer, Arc::new(Sealer::new(&key, Side::Server)));
+        key
+    }
+
+    /// Goes back to plaintext for `peer`.
+    pub fn remove_key(&mut self, peer: SocketAddr) {
+        self.openers.remove(&peer);
+        self.sealers.write().unwrap().remove(&peer);
+    }
+
+    /// Returns a handle that can send from other tasks while this listener waits
+    /// for packets. It uses the recorder and MTU set at the time it gets created,
+    /// keys follow [`Self::issue_key`] at any time.
+    pub fn sender(&self) -> UdpSender<T> {
+        UdpSender {
+            packet_type: std::marker::PhantomData,
+            udp_socket: self.udp_socket.clone(),
+            mtu: self.mtu,
+            sealers: self.sealers.clone(),
+            recorder: self.recorder.clone(),
         }
+    }
 
-        Ok((T::from_raw(sig_a, sig_b, buf[6..].to_vec())?, addr))
+    fn frames_from_buf(
+        &self,
+        addr: SocketAddr,
+        bytes_read: usize,
+    ) -> anyhow::Result<DatagramFrames<'_>> {
+        let buf = &self.recv_buf[..bytes_read];
+        if !is_sealed(buf) {
+            record_udp(self.recorder.as_ref(), Direction::Inbound, Some(addr), buf);
+        }
+        Ok(datagram_frames(buf)?)
     }
 
-    pub async fn wait_for_packet(&mut self) -> anyhow::Result<(T, SocketAddr)> {
-        let (bytes_read, addr) = self.udp_socket.recv_from(&mut self.recv_buf).await?;
+    fn queue_packets(&mut self, addr: SocketAddr, bytes_read: usize) -> anyhow::Result<()> {
         let buf = &self.recv_buf[..bytes_read];
-        self.packet_from_buf(addr, buf)
+        queue_datagram(
+            buf,
+            &mut self.reassembler,
+            addr,
+            self.openers.get_mut(&addr),
+            &mut self.pending,
+            |p| (p, addr),
+            |bytes| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Inbound,
+                    Some(addr),
+                    bytes,
+                )
+            },
+        )?;
+        Ok(())
     }
 
-    pub fn try_read_packet(&mut self) -> anyhow::Result<Option<(T, SocketAddr)>> {
+    /// Like [`Self::wait_for_packet`], but the frames borrow the receive buffer
+    /// until the next read, so hot packets can be decoded without copying.
+    /// Fragments, heartbeats and sealed frames are handed out as they are, feed sealed
+    /// frames to an [`Opener`] and then fragments to a [`Reassembler`] if needed.
+    /// Datagrams with sealed frames aren't recorded, since they don't get opened here.
+    /// Don't mix this with `wait_for_packet`, packets it still has queued would be skipped.
+    pub async fn wait_for_frames(&mut self) -> anyhow::Result<(DatagramFrames<'_>, SocketAddr)> {
+        let (bytes_read, addr) = self.udp_socket.recv_from(&mut self.recv_buf).await?;
+        Ok((self.frames_from_buf(addr, bytes_read)?, addr))
+    }
+
+    pub fn try_read_frames(&mut self) -> anyhow::Result<Option<(DatagramFrames<'_>, SocketAddr)>> {
         match self.udp_socket.try_recv_from(&mut self.recv_buf) {
-            Ok((bytes_read, addr)) => {
-                let buf = &self.recv_buf[..bytes_read];
-                Ok(Some(self.packet_from_buf(addr, buf)?))
-            }
+            Ok((bytes_read, addr)) => Ok(Some((self.frames_from_buf(addr, bytes_read)?, addr))),
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
 
+    /// Datagrams can carry several frames, these are returned one at a time.
+    /// Fragmented packets are only returned once all of their fragments arrived.
+    pub async fn wait_for_packet(&mut self) -> anyhow::Result<(T, SocketAddr)> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(packet);
+            }
+            let (bytes_read, addr) = self.udp_socket.recv_from(&mut self.recv_buf).await?;
+            self.queue_packets(addr, bytes_read)?;
+        }
+    }
+
+    pub fn try_read_packet(&mut self) -> anyhow::Result<Option<(T, SocketAddr)>> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(Some(packet));
+            }
+            match self.udp_socket.try_recv_from(&mut self.recv_buf) {
+                Ok((bytes_read, addr)) => self.queue_packets(addr, bytes_read)?,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
     pub async fn write_bytes<A: ToSocketAddrs>(
         &mut self,
         target: &A,
         bytes: &[u8],
     ) -> anyhow::Result<()> {
-        self.udp_socket.send_to(bytes, target).await?;
-        Ok(())
+        send_to(&self.udp_socket, self.recorder.as_ref(), target, bytes).await
     }
 
     pub async fn write_packet<A: ToSocketAddrs>(
@@ -191,15 +489,556 @@ impl<T: PacketTrait> UdpListener<T> {
         target: A,
         packet: T,
     ) -> anyhow::Result<()> {
-        let (sig_a, sig_b, mut raw) = packet.to_raw()?;
-        let mut bytes = Vec::new(); // TODO: with_capacity for less allocations and more performance
-        bytes.push(sig_a as u8);
-        bytes.push(sig_b as u8);
-        bytes.extend_from_slice(&mut u32::to_le_bytes(raw.len() as u32));
-        bytes.append(&mut raw);
-        self.write_bytes(&target, &bytes).await?;
+        let target = resolve(target).await?;
+        self.write_batch.clear();
+        push_packet(
+            &mut self.write_batch,
+            &packet,
+            sealer_for(&self.sealers, target).as_deref(),
+            &mut self.write_buf,
+            &mut self.seal_buf,
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    Some(target),
+                    frame,
+                )
+            },
+        )?;
+        let mut report = SendReport::default();
+        send_datagrams(
+            &self.udp_socket,
+            self.recorder.as_ref(),
+            target,
+            &self.write_batch,
+            &mut report,
+        )
+        .await;
+        report.into_result()
+    }
+
+    /// Sends the same packet to every target, encoding it only once.
+    /// A failed send doesn't stop the broadcast, it gets reported in the result instead.
+    ///
+    /// Every datagram is a separate `send_to`, there's no sendmmsg batching yet.
+    pub async fn broadcast<I: IntoIterator<Item = SocketAddr>>(
+        &mut self,
+        packet: &T,
+        targets: I,
+    ) -> anyhow::Result<SendReport> {
+        self.write_buf.clear();
+        packet.encode_into(&mut self.write_buf)?;
+        let frame = self.write_buf.split();
+        self.write_batch.clear();
+        self.write_batch.push_frame(&frame)?;
+        let mut report = SendReport::default();
+        for target in targets {
+            // Every peer with a key gets its own sealed copy
+            let batch = match sealer_for(&self.sealers, target) {
+                Some(sealer) => {
+                    self.seal_batch.clear();
+                    push_frame(
+                        &mut self.seal_batch,
+                        &frame,
+                        Some(&sealer),
+                        &mut self.seal_buf,
+                        |frame| {
+                            record_udp(
+                                self.recorder.as_ref(),
+                                Direction::Outbound,
+                                Some(target),
+                                frame,
+                            )
+                        },
+                    )?;
+                    &self.seal_batch
+                }
+                None => &self.write_batch,
+            };
+            send_datagrams(
+                &self.udp_socket,
+                self.recorder.as_ref(),
+                target,
+                batch,
+                &mut report,
+            )
+            .await;
+        }
+        Ok(report)
+    }
+
+    fn batch(
+        batches: &mut HashMap<SocketAddr, DatagramBatch>,
+        mtu: usize,
+        target: SocketAddr,
+    ) -> &mut DatagramBatch {
+        batches
+            .entry(target)
+            .or_insert_with(|| DatagramBatch::new(mtu))
+    }
+
+    /// Queues a packet to be sent on the next [`Self::flush`], packed together
+    /// with everything else queued for the same target.
+    pub fn queue_packet(&mut self, target: SocketAddr, packet: &T) -> anyhow::Result<()> {
+        push_packet(
+            Self::batch(&mut self.batches, self.mtu, target),
+            packet,
+            sealer_for(&self.sealers, target).as_deref(),
+            &mut self.write_buf,
+            &mut self.seal_buf,
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    Some(target),
+                    frame,
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Queues the same packet for every target, encoding it only once.
+    pub fn queue_broadcast<I: IntoIterator<Item = SocketAddr>>(
+        &mut self,
+        packet: &T,
+        targets: I,
+    ) -> anyhow::Result<()> {
+        self.write_buf.clear();
+        packet.encode_into(&mut self.write_buf)?;
+        let frame = self.write_buf.split();
+        for target in targets {
+            push_frame(
+                Self::batch(&mut self.batches, self.mtu, target),
+                &frame,
+                sealer_for(&self.sealers, target).as_deref(),
+                &mut self.seal_buf,
+                |frame| {
+                    record_udp(
+                        self.recorder.as_ref(),
+                        Direction::Outbound,
+                        Some(target),
+                        frame,
+                    )
+                },
+            )?;
+        }
         Ok(())
     }
+
+    /// Relays a vehicle's update to the players interested in it, see [`InterestManager::targets`].
+    pub async fn broadcast_interested<V: Hash + Eq + Clone>(
+        &mut self,
+        packet: &T,
+        interest: &mut InterestManager<SocketAddr, V>,
+        vehicle: &V,
+    ) -> anyhow::Result<SendReport> {
+        let targets = interest.targets(vehicle);
+        self.broadcast(packet, targets).await
+    }
+
+    /// Like [`Self::broadcast_interested`], but queued until the next [`Self::flush`].
+    pub fn queue_interested<V: Hash + Eq + Clone>(
+        &mut self,
+        packet: &T,
+        interest: &mut InterestManager<SocketAddr, V>,
+        vehicle: &V,
+    ) -> anyhow::Result<()> {
+        let targets = interest.targets(vehicle);
+        self.queue_broadcast(packet, targets)
+    }
+
+    /// Sends a last packet, usually a disconnect, right away, after everything
+    /// still queued for the target. Forgets about the target afterwards, its key included.
+    pub async fn disconnect(&mut self, target: SocketAddr, packet: &T) -> anyhow::Result<()> {
+        let mut batch = self
+            .batches
+            .remove(&target)
+            .unwrap_or_else(|| DatagramBatch::new(self.mtu));
+        self.reassembler.remove_sender(&target);
+        let sealer = sealer_for(&self.sealers, target);
+        self.remove_key(target);
+        push_packet(
+            &mut batch,
+            packet,
+            sealer.as_deref(),
+            &mut self.write_buf,
+            &mut self.seal_buf,
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    Some(target),
+                    frame,
+                )
+            },
+        )?;
+        let mut report = SendReport::default();
+        send_datagrams(
+            &self.udp_socket,
+            self.recorder.as_ref(),
+            target,
+            &batch,
+            &mut report,
+        )
+        .await;
+        report.into_result()
+    }
+
+    /// Sends everything queued since the last flush, usually once per tick.
+    /// Queued frames are dropped even if sending them failed.
+    pub async fn flush(&mut self) -> SendReport {
+        let mut report = SendReport::default();
+        // Targets that got nothing since the last flush are most likely gone
+        self.batches.retain(|_, batch| !batch.is_empty());
+        for (&target, batch) in self.batches.iter_mut() {
+            send_datagrams(
+                &self.udp_socket,
+                self.recorder.as_ref(),
+                target,
+                batch,
+                &mut report,
+            )
+            .await;
+            batch.clear();
+        }
+        report
+    }
+}
+
+/// Send half of a [`UdpListener`], created through [`UdpListener::sender`].
+/// Every method only needs `&self`, clone it to hand it to more tasks.
+pub struct UdpSender<T: PacketTrait> {
+    packet_type: std::marker::PhantomData<T>,
+
+    udp_socket: Arc<UdpSocket>,
+    mtu: usize,
+    sealers: Sealers,
+    recorder: Option<Arc<dyn Recorder>>,
+}
+
+impl<T: PacketTrait> Clone for UdpSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            packet_type: std::marker::PhantomData,
+            udp_socket: self.udp_socket.clone(),
+            mtu: self.mtu,
+            sealers: self.sealers.clone(),
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+impl<T: PacketTrait> UdpSender<T> {
+    pub async fn write_bytes<A: ToSocketAddrs>(
+        &self,
+        target: &A,
+        bytes: &[u8],
+    ) -> anyhow::Result<()> {
+        send_to(&self.udp_socket, self.recorder.as_ref(), target, bytes).await
+    }
+
+    /// Unlike the listener the sender has no buffer of its own to reuse,
+    /// so this allocates one per call.
+    pub async fn write_packet<A: ToSocketAddrs>(
+        &self,
+        target: A,
+        packet: &T,
+    ) -> anyhow::Result<()> {
+        let target = resolve(target).await?;
+        let mut batch = DatagramBatch::new(self.mtu);
+        push_packet(
+            &mut batch,
+            packet,
+            sealer_for(&self.sealers, target).as_deref(),
+            &mut BytesMut::new(),
+            &mut BytesMut::new(),
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    Some(target),
+                    frame,
+                )
+            },
+        )?;
+        let mut report = SendReport::default();
+        send_datagrams(
+            &self.udp_socket,
+            self.recorder.as_ref(),
+            target,
+            &batch,
+            &mut report,
+        )
+        .await;
+        report.into_result()
+    }
+
+    /// See [`UdpListener::broadcast`].
+    pub async fn broadcast<I: IntoIterator<Item = SocketAddr>>(
+        &self,
+        packet: &T,
+        targets: I,
+    ) -> anyhow::Result<SendReport> {
+        let frame = framing::encode_frame(packet)?;
+        let mut batch = DatagramBatch::new(self.mtu);
+        batch.push_frame(&frame)?;
+        let mut sealed = DatagramBatch::new(self.mtu);
+        let mut seal_buf = BytesMut::new();
+        let mut report = SendReport::default();
+        for target in targets {
+            let batch = match sealer_for(&self.sealers, target) {
+                Some(sealer) => {
+                    sealed.clear();
+                    push_frame(&mut sealed, &frame, Some(&sealer), &mut seal_buf, |frame| {
+                        record_udp(
+                            self.recorder.as_ref(),
+                            Direction::Outbound,
+                            Some(target),
+                            frame,
+                        )
+                    })?;
+                    &sealed
+                }
+                None => &batch,
+            };
+            send_datagrams(
+                &self.udp_socket,
+                self.recorder.as_ref(),
+                target,
+                batch,
+                &mut report,
+            )
+            .await;
+        }
+        Ok(report)
+    }
+}
+
+async fn resolve<A: ToSocketAddrs>(target: A) -> anyhow::Result<SocketAddr> {
+    Ok(lookup_host(target)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::AddrNotAvailable))?)
+}
+
+async fn send_to<A: ToSocketAddrs>(
+    udp_socket: &UdpSocket,
+    recorder: Option<&Arc<dyn Recorder>>,
+    target: &A,
+    bytes: &[u8],
+) -> anyhow::Result<()> {
+    match recorder {
+        Some(recorder) => {
+            // Resolve upfront so the recorded peer is the address we actually sent to
+            let addr = resolve(target).await?;
+            udp_socket.send_to(bytes, addr).await?;
+            if !is_sealed(bytes) {
+                recorder.record(Direction::Outbound, Transport::Udp, Some(addr), bytes);
+            }
+        }
+        None => {
+            udp_socket.send_to(bytes, target).await?;
+        }
+    }
+    Ok(())
+}
+
+fn record_udp(
+    recorder: Option<&Arc<dyn Recorder>>,
+    direction: Direction,
+    peer: Option<SocketAddr>,
+    bytes: &[u8],
+) {
+    if let Some(recorder) = recorder {
+        recorder.record(direction, Transport::Udp, peer, bytes);
+    }
+}
+
+/// Sealed datagrams get recorded in plaintext instead, frame by frame as they're
+/// sealed or opened, so captures can be decoded without the key.
+fn is_sealed(datagram: &[u8]) -> bool {
+    datagram.starts_with(&[crypto::SIG_A as u8, crypto::SIG_B as u8])
+}
+
+/// Sends every datagram in the batch to `target`, giving up on the target at the first error.
+async fn send_datagrams(
+    udp_socket: &UdpSocket,
+    recorder: Option<&Arc<dyn Recorder>>,
+    target: SocketAddr,
+    batch: &DatagramBatch,
+    report: &mut SendReport,
+) {
+    for datagram in batch.datagrams() {
+        match udp_socket.send_to(datagram, target).await {
+            Ok(_) => {
+                report.sent += 1;
+                if !is_sealed(datagram) {
+                    record_udp(recorder, Direction::Outbound, Some(target), datagram);
+                }
+            }
+            Err(e) => {
+                report.failed.push((target, e));
+                return;
+            }
+        }
+    }
+}
+
+/// Adds an encoded frame to the batch, sealed if there's a sealer. Frames to be sealed
+/// are passed to `record` first. Sealed frames too big for a datagram get fragmented first and every fragment
+/// sealed on its own, so the receiver can authenticate each before reassembling.
+fn push_frame(
+    batch: &mut DatagramBatch,
+    frame: &[u8],
+    sealer: Option<&Sealer>,
+    seal_buf: &mut BytesMut,
+    record: impl FnOnce(&[u8]),
+) -> Result<(), PacketEncodeError> {
+    let Some(sealer) = sealer else {
+        return batch.push_frame(frame);
+    };
+    record(frame);
+    let mtu = batch.mtu().saturating_sub(crypto::OVERHEAD);
+    if frame.len() <= mtu {
+        seal_buf.clear();
+        sealer.seal(frame, seal_buf)?;
+        return batch.push_frame(seal_buf);
+    }
+
+    let chunks = fragment::chunks(frame, mtu)?;
+    let count = chunks.len() as u16;
+    let id = fragment::next_id();
+    let mut fragment = BytesMut::with_capacity(mtu);
+    for (index, chunk) in chunks.enumerate() {
+        fragment.clear();
+        fragment::write_fragment(&mut fragment, id, index as u16, count, chunk);
+        seal_buf.clear();
+        sealer.seal(&fragment, seal_buf)?;
+        batch.push_frame(seal_buf)?;
+    }
+    Ok(())
+}
+
+/// Adds a packet to the batch, sealed if there's a sealer.
+fn push_packet<T: PacketTrait>(
+    batch: &mut DatagramBatch,
+    packet: &T,
+    sealer: Option<&Sealer>,
+    write_buf: &mut BytesMut,
+    seal_buf: &mut BytesMut,
+    record: impl FnOnce(&[u8]),
+) -> Result<(), PacketEncodeError> {
+    if sealer.is_none() {
+        return batch.push(packet);
+    }
+    write_buf.clear();
+    packet.encode_into(write_buf)?;
+    push_frame(batch, write_buf, sealer, seal_buf, record)
+}
+
+/// Decodes every frame of a datagram into `pending`, putting fragments back together,
+/// opening sealed frames and skipping heartbeats along the way. With an opener, every
+/// frame has to be sealed. Nothing gets queued if any of the frames is invalid.
+/// The datagram is passed to `record`, or every frame once opened if there's an opener.
+/// Returns whether any frame got through, i.e. was authenticated and not a replay.
+fn queue_datagram<T: PacketTrait, K: Hash + Eq + Clone, P>(
+    datagram: &[u8],
+    reassembler: &mut Reassembler<K>,
+    sender: K,
+    mut opener: Option<&mut Opener>,
+    pending: &mut VecDeque<P>,
+    wrap: impl Fn(T) -> P,
+    mut record: impl FnMut(&[u8]),
+) -> anyhow::Result<bool> {
+    if opener.is_none() {
+        record(datagram);
+    }
+    let queued = pending.len();
+    let now = Instant::now();
+    let mut accepted = false;
+    for frame in datagram_frames(datagram)? {
+        let opener = opener.as_deref_mut();
+        match decode_frame(&frame, reassembler, &sender, opener, &mut record, now) {
+            Ok(packet) => {
+                accepted = true;
+                pending.extend(packet.map(&wrap));
+            }
+            Err(e) => match e.downcast_ref() {
+                // Duplicated on the way or replayed by someone else, nothing the caller could act on
+                Some(ConnectionError::Replayed(sequence)) => {
+                    trace!("dropping replayed frame {sequence}");
+                }
+                _ => {
+                    pending.truncate(queued);
+                    return Err(e);
+                }
+            },
+        }
+    }
+    Ok(accepted)
+}
+
+/// `None` for heartbeats and fragments of messages that aren't complete yet. Sealed frames are opened before anything else, fragments included.
+fn decode_frame<T: PacketTrait, K: Hash + Eq + Clone>(
+    frame: &Frame,
+    reassembler: &mut Reassembler<K>,
+    sender: &K,
+    opener: Option<&mut Opener>,
+    record: &mut impl FnMut(&[u8]),
+    now: Instant,
+) -> anyhow::Result<Option<T>> {
+    let opened;
+    let frame = match opener {
+        Some(opener) if crypto::is_encrypted(frame) => {
+            opened = opener.open(frame)?;
+            record(&opened);
+            crypto::sealed_frame(&opened)?
+        }
+        Some(_) => return Err(ConnectionError::Unencrypted.into()),
+        None => *frame,
+    };
+
+    let reassembled;
+    let frame = if fragment::is_fragment(&frame) {
+        match reassembler.push(sender.clone(), frame.data, now)? {
+            Some(message) => {
+                reassembled = message;
+                fragment::reassembled_frame(&reassembled)?
+            }
+            None => return Ok(None),
+        }
+    } else {
+        frame
+    };
+
+    if framing::is_heartbeat(&frame) {
+        return Ok(None);
+    }
+    Ok(Some(frame.decode()?))
+}
+
+/// Outcome of sending to several targets at once,
+/// through [`UdpListener::broadcast`] or [`UdpListener::flush`].
+#[derive(Debug, Default)]
+pub struct SendReport {
+    /// Number of datagrams that went out.
+    pub sent: usize,
+    pub failed: Vec<(SocketAddr, std::io::Error)>,
+}
+
+impl SendReport {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    fn into_result(mut self) -> anyhow::Result<()> {
+        match self.failed.pop() {
+            Some((_, e)) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
 }
 
 /// A generic connection to be used anywhere it's needed.
@@ -208,7 +1047,24 @@ pub struct UdpClient<T: PacketTrait> {
     packet_type: std::marker::PhantomData<T>,
 
     udp_socket: UdpSocket,
+    /// Only ends up in captures.
+    peer_addr: Option<SocketAddr>,
     recv_buf: Vec<u8>,
+    /// Packets from a datagram that carried more than one frame.
+    pending: VecDeque<T>,
+    reassembler: Reassembler<()>,
+    /// Datagrams of the packet currently being sent by `write_packet`.
+    write_batch: DatagramBatch,
+    /// Frames queued until the next `flush`.
+    batch: DatagramBatch,
+    write_buf: BytesMut,
+    seal_buf: BytesMut,
+    /// Set along with the opener by [`Self::set_key`].
+    sealer: Option<Sealer>,
+    opener: Option<Opener>,
+    key: Option<UdpKey>,
+    keepalive: Keepalive,
+    recorder: Option<Arc<dyn Recorder>>,
 }
 
 impl<T: PacketTrait> UdpClient<T> {
@@ -217,46 +1073,237 @@ impl<T: PacketTrait> UdpClient<T> {
         target: A,
     ) -> anyhow::Result<Self> {
         udp_socket.connect(target).await?;
-        trace!("peer_addr: {:?}", udp_socket.peer_addr());
+        let peer_addr = udp_socket.peer_addr().ok();
+        trace!("peer_addr: {:?}", peer_addr);
         Ok(Self {
             packet_type: std::marker::PhantomData,
             udp_socket,
+            peer_addr,
             recv_buf: vec![0u8; 65535],
+            pending: VecDeque::new(),
+            reassembler: Reassembler::default(),
+            write_batch: DatagramBatch::default(),
+            batch: DatagramBatch::default(),
+            write_buf: BytesMut::new(),
+            seal_buf: BytesMut::new(),
+            sealer: None,
+            opener: None,
+            key: None,
+            keepalive: Keepalive::new(KeepaliveConfig::default()),
+            recorder: None,
         })
     }
 
-    pub async fn wait_for_packet(&mut self) -> anyhow::Result<T> {
+    /// Resets the idle timer.
+    pub fn set_keepalive(&mut self, config: KeepaliveConfig) {
+        self.keepalive = Keepalive::new(config);
+    }
+
+    /// See [`TcpConnection::keepalive`].
+    pub async fn keepalive(&mut self) -> anyhow::Result<()> {
+        if !self.keepalive.check()? {
+            return Ok(());
+        }
+        match &self.sealer {
+            Some(sealer) => {
+                self.record(Direction::Outbound, &framing::HEARTBEAT);
+                self.seal_buf.clear();
+                sealer.seal(&framing::HEARTBEAT, &mut self.seal_buf)?;
+                let heartbeat = self.seal_buf.split();
+                self.write_bytes(&heartbeat).await
+            }
+            None => self.write_bytes(&framing::HEARTBEAT).await,
+        }
+    }
+
+    /// Seals every frame with the key from now on, and rejects anything from the
+    /// server that isn't sealed. See [`crate::crypto`].
+    /// Fails with [`ConnectionError::KeyReused`] if it's the key already in use.
+    pub fn set_key(&mut self, key: &UdpKey) -> Result<(), ConnectionError> {
+        if self.key.as_ref() == Some(key) {
+            return Err(ConnectionError::KeyReused);
+        }
+        self.sealer = Some(Sealer::new(key, Side::Launcher));
+        self.opener = Some(Opener::new(key, Side::Launcher));
+        self.key = Some(key.clone());
+        Ok(())
+    }
+
+    /// Every datagram sent or received from now on gets passed to the recorder.
+    /// With a key, frames are recorded one by one in plaintext instead, outgoing ones
+    /// as they get sealed.
+    pub fn set_recorder(&mut self, recorder: Arc<dyn Recorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Maximum size of the datagrams packets get packed into, anything bigger
+    /// gets fragmented. See [`DatagramBatch`].
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.write_batch.set_mtu(mtu);
+        self.batch.set_mtu(mtu);
+    }
+
+    /// Limits for putting fragmented packets back together.
+    /// Drops everything that is currently only partially received.
+    pub fn set_reassembly_limits(&mut self, timeout: Duration, max_buffered: usize) {
+        self.reassembler = Reassembler::new(timeout, max_buffered);
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.udp_socket.local_addr()
+    }
+
+    /// Sealed datagrams are skipped, see [`is_sealed`].
+    fn record(&self, direction: Direction, datagram: &[u8]) {
+        if !is_sealed(datagram) {
+            record_udp(self.recorder.as_ref(), direction, self.peer_addr, datagram);
+        }
+    }
+
+    /// Like [`Self::wait_for_packet`], but the frames borrow the receive buffer
+    /// until the next read, so hot packets can be decoded without copying.
+    /// Fragments and heartbeats are handed out as they are, feed fragments to a
+    /// [`Reassembler`] if needed. Heartbeats aren't sent or checked for while waiting here.
+    /// With a key, frames aren't authenticated here, so they don't reset the idle timer.
+    /// Don't mix this with `wait_for_packet`, packets it still has queued would be skipped.
+    pub async fn wait_for_frames(&mut self) -> anyhow::Result<DatagramFrames<'_>> {
         let bytes_read = self.udp_socket.recv(&mut self.recv_buf).await?;
         let buf = &self.recv_buf[..bytes_read];
+        self.record(Direction::Inbound, buf);
 
-        if buf.len() < 6 {
-            return Err(ConnectionError::InvalidPacketSize.into());
+        let frames = datagram_frames(buf)?;
+        if self.opener.is_none() {
+            self.keepalive.last_read = tokio::time::Instant::now();
         }
+        Ok(frames)
+    }
 
-        let sig_a = buf[0] as char;
-        let sig_b = buf[1] as char;
-        let packet_length = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+    /// Datagrams can carry several frames, these are returned one at a time.
+    /// Fragmented packets are only returned once all of their fragments arrived.
+    pub async fn wait_for_packet(&mut self) -> anyhow::Result<T> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(packet);
+            }
 
-        if buf.len() < 6 + packet_length as usize {
-            return Err(ConnectionError::InvalidPacketSize.into());
+            let bytes_read = match self.keepalive.deadline() {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(
+                        deadline,
+                        self.udp_socket.recv(&mut self.recv_buf),
+                    )
+                    .await
+                    {
+                        Ok(bytes_read) => bytes_read?,
+                        Err(_) => {
+                            self.keepalive().await?;
+                            continue;
+                        }
+                    }
+                }
+                None => self.udp_socket.recv(&mut self.recv_buf).await?,
+            };
+            let accepted = queue_datagram(
+                &self.recv_buf[..bytes_read],
+                &mut self.reassembler,
+                (),
+                self.opener.as_mut(),
+                &mut self.pending,
+                |p| p,
+                |bytes| {
+                    record_udp(
+                        self.recorder.as_ref(),
+                        Direction::Inbound,
+                        self.peer_addr,
+                        bytes,
+                    )
+                },
+            )?;
+            // Only frames that got through show the server is still there
+            if accepted {
+                self.keepalive.last_read = tokio::time::Instant::now();
+            }
         }
-
-        Ok(T::from_raw(sig_a, sig_b, buf[6..].to_vec())?)
     }
 
     pub async fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
         self.udp_socket.send(bytes).await?;
+        self.keepalive.last_write = tokio::time::Instant::now();
+        self.record(Direction::Outbound, bytes);
         Ok(())
     }
 
     pub async fn write_packet(&mut self, packet: T) -> anyhow::Result<()> {
-        let (sig_a, sig_b, mut raw) = packet.to_raw()?;
-        let mut bytes = Vec::new(); // TODO: with_capacity for less allocations and more performance
-        bytes.push(sig_a as u8);
-        bytes.push(sig_b as u8);
-        bytes.extend_from_slice(&mut u32::to_le_bytes(raw.len() as u32));
-        bytes.append(&mut raw);
-        self.write_bytes(&bytes).await?;
+        self.write_batch.clear();
+        push_packet(
+            &mut self.write_batch,
+            &packet,
+            self.sealer.as_ref(),
+            &mut self.write_buf,
+            &mut self.seal_buf,
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    self.peer_addr,
+                    frame,
+                )
+            },
+        )?;
+        for datagram in self.write_batch.datagrams() {
+            self.udp_socket.send(datagram).await?;
+            self.record(Direction::Outbound, datagram);
+        }
+        self.keepalive.last_write = tokio::time::Instant::now();
+        Ok(())
+    }
+
+    /// Queues a packet to be sent on the next [`Self::flush`], packed together
+    /// with everything else queued.
+    pub fn queue_packet(&mut self, packet: &T) -> anyhow::Result<()> {
+        push_packet(
+            &mut self.batch,
+            packet,
+            self.sealer.as_ref(),
+            &mut self.write_buf,
+            &mut self.seal_buf,
+            |frame| {
+                record_udp(
+                    self.recorder.as_ref(),
+                    Direction::Outbound,
+                    self.peer_addr,
+                    frame,
+                )
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Sends everything queued since the last flush and returns the number of datagrams.
+    /// Queued frames are dropped even if sending them failed.
+    pub async fn flush(&mut self) -> anyhow::Result<usize> {
+        let mut sent = 0;
+        let mut result = Ok(());
+        for datagram in self.batch.datagrams() {
+            if let Err(e) = self.udp_socket.send(datagram).await {
+                result = Err(e);
+                break;
+            }
+            self.record(Direction::Outbound, datagram);
+            sent += 1;
+        }
+        if sent > 0 {
+            self.keepalive.last_write = tokio::time::Instant::now();
+        }
+        self.batch.clear();
+        result?;
+        Ok(sent)
+    }
+
+    /// Sends a last packet, usually a disconnect, along with everything still queued.
+    pub async fn close_with(mut self, packet: &T) -> anyhow::Result<()> {
+        self.queue_packet(packet)?;
+        self.flush().await?;
         Ok(())
     }
 }
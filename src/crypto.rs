@@ -0,0 +1,260 @@
+//! Encrypting and authenticating UDP frames with a per-session key.
+//!
+//! The server hands the key to the launcher over TCP in a
+//! [`UdpKeyPacket`](crate::server_launcher::handshake::UdpKeyPacket), once both sides
+//! agreed on [`Capabilities::UDP_ENCRYPTION`](crate::server_launcher::handshake::Capabilities).
+//! From then on every frame, heartbeats included, is sealed on its own with
+//! ChaCha20-Poly1305 and sent as an `EN` frame, with all integers little endian:
+//!
+//! | size | field                                          |
+//! |------|------------------------------------------------|
+//! | 8    | sequence number (u64), counting up per sender  |
+//! | n    | the original frame, header included, encrypted |
+//! | 16   | authentication tag                             |
+//!
+//! The nonce is the direction (0 towards the server, 1 towards the launcher)
+//! followed by 3 zero bytes and the sequence number, the `EN` header is the
+//! associated data. Frames that don't fit into a datagram once sealed get fragmented
+//! first and every `FG` frame is sealed on its own, so nothing gets buffered for
+//! reassembly before it's authenticated.
+//! Receivers drop frames that fail authentication, replays and anything too far
+//! behind the newest sequence number, see [`ReplayWindow`].
+//!
+//! The key itself travels in cleartext on a plain TCP link, so sealing only keeps
+//! UDP private from someone who can't read the TCP side as well, i.e. with the
+//! `tls` feature in use.
+//!
+//! Sequence numbers start over whenever a key gets installed, so installing the
+//! same key twice would repeat nonces. The server mints a new key every time with
+//! [`UdpListener::issue_key`](crate::connection::UdpListener::issue_key) and
+//! [`UdpClient::set_key`](crate::connection::UdpClient::set_key) refuses the key
+//! it already has.
+
+use crate::framing::{datagram_frames, Frame};
+use crate::redact::redacted;
+use crate::*;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::{BufMut, BytesMut};
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, Tag};
+
+pub const SIG_A: char = 'E';
+pub const SIG_B: char = 'N';
+
+pub const SEQUENCE_SIZE: usize = 8;
+pub const TAG_SIZE: usize = 16;
+/// How much bigger a frame gets by sealing it.
+pub const OVERHEAD: usize = PacketHeader::SIZE + SEQUENCE_SIZE + TAG_SIZE;
+
+pub fn is_encrypted(frame: &Frame) -> bool {
+    (frame.sig_a, frame.sig_b) == (SIG_A, SIG_B)
+}
+
+/// Symmetric key of a single session, used in both directions.
+#[derive(Clone, PartialEq, Eq)]
+pub struct UdpKey(pub [u8; UdpKey::SIZE]);
+
+impl UdpKey {
+    pub const SIZE: usize = 32;
+
+    pub fn generate() -> Self {
+        let mut key = [0u8; Self::SIZE];
+        getrandom::fill(&mut key).expect("no randomness available");
+        Self(key)
+    }
+
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let key = packet_data
+            .try_into()
+            .map_err(|_| PacketDecodeError::InvalidDataSize {
+                expected: Self::SIZE,
+                actual: packet_data.len(),
+            })?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+redacted!(UdpKey);
+
+/// Which end of the session a [`Sealer`] or [`Opener`] is used on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Server,
+    Launcher,
+}
+
+impl Side {
+    /// First byte of the nonce of frames sent by this side.
+    fn direction(self) -> u8 {
+        match self {
+            Self::Launcher => 0,
+            Self::Server => 1,
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Self::Server => Self::Launcher,
+            Self::Launcher => Self::Server,
+        }
+    }
+}
+
+fn nonce(direction: u8, sequence: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce.into()
+}
+
+/// Seals outgoing frames. Only needs `&self`, so it can be shared between tasks.
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    direction: u8,
+    next_sequence: AtomicU64,
+}
+
+impl Sealer {
+    pub fn new(key: &UdpKey, side: Side) -> Self {
+        Self {
+            cipher: key.cipher(),
+            direction: side.direction(),
+            next_sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Appends `frame`, header included, to `buf` as an `EN` frame.
+    pub fn seal(&self, frame: &[u8], buf: &mut BytesMut) -> Result<(), PacketEncodeError> {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        let packet_length = u32::try_from(SEQUENCE_SIZE + frame.len() + TAG_SIZE)
+            .map_err(|_| PacketEncodeError::FrameTooLarge(frame.len()))?;
+        let header = PacketHeader {
+            sig_a: SIG_A,
+            sig_b: SIG_B,
+            packet_length,
+        }
+        .to_bytes();
+
+        buf.reserve(OVERHEAD + frame.len());
+        buf.put_slice(&header);
+        buf.put_u64_le(sequence);
+        let start = buf.len();
+        buf.put_slice(frame);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(self.direction, sequence), &header, &mut buf[start..])
+            .map_err(|_| PacketEncodeError::FrameTooLarge(frame.len()))?;
+        buf.put_slice(&tag);
+        Ok(())
+    }
+}
+
+/// Sliding window over the sequence numbers received so far, as in RFC 4303.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayWindow {
+    newest: Option<u64>,
+    /// Bit `n` is set if `newest - n` was received.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Sequence numbers this far behind the newest one are always rejected.
+    pub const SIZE: u64 = 64;
+
+    /// Whether `sequence` wasn't received yet and is recent enough to tell.
+    pub fn check(&self, sequence: u64) -> bool {
+        let Some(newest) = self.newest else {
+            return true;
+        };
+        if sequence > newest {
+            return true;
+        }
+        let behind = newest - sequence;
+        behind < Self::SIZE && self.seen & (1 << behind) == 0
+    }
+
+    /// Only call this once the frame passed authentication, so forged sequence
+    /// numbers can't move the window.
+    pub fn mark(&mut self, sequence: u64) {
+        match self.newest {
+            Some(newest) if sequence <= newest => {
+                let behind = newest - sequence;
+                if behind < Self::SIZE {
+                    self.seen |= 1 << behind;
+                }
+            }
+            Some(newest) => {
+                let ahead = sequence - newest;
+                self.seen = if ahead < Self::SIZE {
+                    self.seen << ahead
+                } else {
+                    0
+                };
+                self.seen |= 1;
+                self.newest = Some(sequence);
+            }
+            None => {
+                self.seen = 1;
+                self.newest = Some(sequence);
+            }
+        }
+    }
+}
+
+/// Opens frames sealed by the peer.
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    direction: u8,
+    window: ReplayWindow,
+}
+
+impl Opener {
+    /// `side` is the side opening, i.e. the frames were sealed by the other one.
+    pub fn new(key: &UdpKey, side: Side) -> Self {
+        Self {
+            cipher: key.cipher(),
+            direction: side.peer().direction(),
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the frame sealed in an `EN` frame, header included.
+    pub fn open(&mut self, frame: &Frame) -> Result<Vec<u8>, ConnectionError> {
+        if !is_encrypted(frame) || frame.data.len() < SEQUENCE_SIZE + TAG_SIZE {
+            return Err(ConnectionError::Unauthenticated);
+        }
+        let (sequence, rest) = frame.data.split_at(SEQUENCE_SIZE);
+        let sequence = u64::from_le_bytes(sequence.try_into().unwrap());
+        if !self.window.check(sequence) {
+            return Err(ConnectionError::Replayed(sequence));
+        }
+
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+        let mut sealed = ciphertext.to_vec();
+        self.cipher
+            .decrypt_in_place_detached(
+                &nonce(self.direction, sequence),
+                &frame.header().to_bytes(),
+                &mut sealed,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| ConnectionError::Unauthenticated)?;
+        self.window.mark(sequence);
+        Ok(sealed)
+    }
+}
+
+/// The single frame in an opened `EN` frame, possibly a fragment.
+pub fn sealed_frame(opened: &[u8]) -> Result<Frame<'_>, ConnectionError> {
+    let mut frames = datagram_frames(opened)?;
+    match (frames.next(), frames.next()) {
+        (Some(frame), None) if !is_encrypted(&frame) => Ok(frame),
+        _ => Err(ConnectionError::InvalidPacketSize),
+    }
+}
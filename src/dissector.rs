@@ -0,0 +1,298 @@
+//! Wireshark Lua dissector generation from the packet [`schema`](crate::schema).
+
+use crate::schema::{self, Body, FieldType, PacketDef};
+
+use std::fmt::Write;
+
+/// Ports the generated dissector registers itself on.
+/// Both protocols can also be picked manually through "Decode As...".
+#[derive(Debug, Clone, Default)]
+pub struct DissectorConfig {
+    pub server_launcher_tcp_ports: Vec<u16>,
+    pub server_launcher_udp_ports: Vec<u16>,
+    pub launcher_client_tcp_ports: Vec<u16>,
+}
+
+const PRELUDE: &str = r#"-- NGMP protocol dissector.
+-- Generated by ngmp_protocol_impl, do not edit by hand.
+
+local json_dissector = Dissector.get("json")
+local tcp_port = DissectorTable.get("tcp.port")
+local udp_port = DissectorTable.get("udp.port")
+
+-- Frames added by the transport around packets: heartbeats, fragments and encrypted frames.
+-- Returns nil for anything else.
+local function dissect_transport_frame(fields, sig, body, len, subtree)
+    if sig == "HB" then
+        return "Heartbeat"
+    elseif sig == "FG" then
+        if len < 8 then
+            subtree:add_expert_info(PI_MALFORMED, PI_ERROR, "Truncated fragment header")
+            return "Fragment"
+        end
+        subtree:add_le(fields.fragment_id, body(0, 4))
+        subtree:add_le(fields.fragment_index, body(4, 2))
+        subtree:add_le(fields.fragment_count, body(6, 2))
+        if len > 8 then
+            subtree:add(fields.fragment_chunk, body(8))
+        end
+        return string.format("Fragment %d/%d", body(4, 2):le_uint() + 1, body(6, 2):le_uint())
+    elseif sig == "EN" then
+        if len < 24 then
+            subtree:add_expert_info(PI_MALFORMED, PI_ERROR, "Truncated encrypted frame")
+            return "Encrypted"
+        end
+        subtree:add_le(fields.encrypted_sequence, body(0, 8))
+        if len > 24 then
+            subtree:add(fields.encrypted_data, body(8, len - 24))
+        end
+        subtree:add(fields.encrypted_tag, body(len - 16, 16))
+        return "Encrypted"
+    end
+    return nil
+end
+
+local function dissect_packet(fields, def, body, len, pinfo, subtree)
+    if len == 0 then
+        return
+    end
+    if def == nil or def.kind == "empty" then
+        subtree:add(fields.body, body)
+    elseif def.kind == "json" then
+        json_dissector:call(body:tvb(), pinfo, subtree)
+    else
+        local pos = 0
+        for _, f in ipairs(def.fields) do
+            if pos >= len then
+                break
+            end
+            if f.size == nil then
+                -- Strings run until the end of the packet
+                subtree:add(f.field, body(pos))
+                pos = len
+            elseif pos + f.size > len then
+                subtree:add_expert_info(PI_MALFORMED, PI_ERROR, "Truncated field")
+                break
+            else
+                subtree:add_le(f.field, body(pos, f.size))
+                pos = pos + f.size
+            end
+        end
+    end
+end
+
+local function dissect_frame(proto, fields, packets, tvb, pinfo, tree, offset)
+    local sig = tvb(offset, 2):string()
+    local len = tvb(offset + 2, 4):le_uint()
+    local subtree = tree:add(proto, tvb(offset, 6 + len))
+    subtree:add(fields.sig, tvb(offset, 2))
+    subtree:add_le(fields.len, tvb(offset + 2, 4))
+    local body = nil
+    if len > 0 then
+        body = tvb(offset + 6, len)
+    end
+
+    local name = dissect_transport_frame(fields, sig, body, len, subtree)
+    if name == nil then
+        local def = packets[sig]
+        name = def and def.name or ("Unknown(" .. sig .. ")")
+        dissect_packet(fields, def, body, len, pinfo, subtree)
+    end
+    subtree:set_text(name)
+    return name
+end
+
+-- TCP segments get reassembled through desegmentation, UDP datagrams may carry several frames.
+local function dissect_frames(proto, fields, packets, tvb, pinfo, tree)
+    local names = {}
+    local offset = 0
+    local total = tvb:len()
+    while offset < total do
+        local remaining = total - offset
+        local frame_len = nil
+        if remaining >= 6 then
+            frame_len = 6 + tvb(offset + 2, 4):le_uint()
+        end
+        if frame_len == nil or remaining < frame_len then
+            if pinfo.can_desegment > 0 then
+                pinfo.desegment_offset = offset
+                pinfo.desegment_len = frame_len and (frame_len - remaining) or DESEGMENT_ONE_MORE_SEGMENT
+            else
+                local item = tree:add(proto, tvb(offset), "Truncated frame")
+                item:add_expert_info(PI_MALFORMED, PI_ERROR, "Truncated frame")
+            end
+            break
+        end
+        table.insert(names, dissect_frame(proto, fields, packets, tvb, pinfo, tree, offset))
+        offset = offset + frame_len
+    end
+    if #names > 0 then
+        pinfo.cols.protocol = "NGMP"
+        pinfo.cols.info = table.concat(names, ", ")
+    end
+    return total
+end
+"#;
+
+/// ProtoField constructor for a field of this type.
+fn lua_field_type(ty: &FieldType) -> &'static str {
+    match ty {
+        FieldType::Bool | FieldType::U8 => "uint8",
+        FieldType::U16 => "uint16",
+        FieldType::U32 => "uint32",
+        FieldType::U64 => "uint64",
+        FieldType::F32 => "float",
+        FieldType::String => "string",
+        FieldType::Enum { repr, .. } => lua_field_type(repr),
+        FieldType::Array { .. } | FieldType::List { .. } | FieldType::Object { .. } => "bytes",
+    }
+}
+
+/// ProtoFields of the transport frames, see `dissect_transport_frame`.
+const TRANSPORT_FIELDS: &[(&str, &str, &str)] = &[
+    ("fragment_id", "uint32", "Message id"),
+    ("fragment_index", "uint16", "Fragment index"),
+    ("fragment_count", "uint16", "Fragment count"),
+    ("fragment_chunk", "bytes", "Chunk"),
+    ("encrypted_sequence", "uint64", "Sequence"),
+    ("encrypted_data", "bytes", "Encrypted frame"),
+    ("encrypted_tag", "bytes", "Authentication tag"),
+];
+
+fn write_protocol(
+    lua: &mut String,
+    proto: &str,
+    description: &str,
+    defs: &[PacketDef],
+    tcp_ports: &[u16],
+    udp_ports: &[u16],
+) -> std::fmt::Result {
+    writeln!(lua)?;
+    writeln!(lua, "local {proto} = Proto(\"{proto}\", \"{description}\")")?;
+    writeln!(lua, "local {proto}_fields = {{")?;
+    writeln!(
+        lua,
+        "    sig = ProtoField.string(\"{proto}.sig\", \"Signature\"),"
+    )?;
+    writeln!(
+        lua,
+        "    len = ProtoField.uint32(\"{proto}.len\", \"Length\"),"
+    )?;
+    writeln!(
+        lua,
+        "    body = ProtoField.bytes(\"{proto}.body\", \"Body\"),"
+    )?;
+    for (name, ctor, label) in TRANSPORT_FIELDS {
+        writeln!(
+            lua,
+            "    {name} = ProtoField.{ctor}(\"{proto}.{name}\", \"{label}\"),"
+        )?;
+    }
+    writeln!(lua, "}}")?;
+    writeln!(lua, "local {proto}_packets = {{}}")?;
+
+    for def in defs {
+        let sig = format!("{}{}", def.sig_a, def.sig_b);
+        let packet = def.name.to_lowercase();
+        match def.body {
+            Body::Empty => writeln!(
+                lua,
+                "{proto}_packets[\"{sig}\"] = {{ name = \"{}\", kind = \"empty\" }}",
+                def.name
+            )?,
+            Body::Json(_) => writeln!(
+                lua,
+                "{proto}_packets[\"{sig}\"] = {{ name = \"{}\", kind = \"json\" }}",
+                def.name
+            )?,
+            Body::Binary(fields) => {
+                for field in fields {
+                    let ctor = lua_field_type(&field.ty);
+                    // Show the names of enum codes next to the number
+                    let value_names = match field.ty {
+                        FieldType::Enum { values, .. } if field.ty.binary_size().is_some() => {
+                            let names = values
+                                .iter()
+                                .map(|v| format!("[{}] = \"{}\"", v.code, v.name))
+                                .collect::<Vec<_>>();
+                            format!(", base.DEC, {{ {} }}", names.join(", "))
+                        }
+                        _ => String::new(),
+                    };
+                    writeln!(
+                        lua,
+                        "{proto}_fields[\"{sig}.{name}\"] = ProtoField.{ctor}(\"{proto}.{packet}.{name}\", \"{name}\"{value_names})",
+                        name = field.name
+                    )?;
+                }
+                writeln!(
+                    lua,
+                    "{proto}_packets[\"{sig}\"] = {{ name = \"{}\", kind = \"binary\", fields = {{",
+                    def.name
+                )?;
+                for field in fields {
+                    // Fields without a fixed size run until the end of the packet
+                    let size = match field.ty.binary_size() {
+                        Some(size) => format!("size = {size}, "),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        lua,
+                        "    {{ {size}field = {proto}_fields[\"{sig}.{}\"] }},",
+                        field.name
+                    )?;
+                }
+                writeln!(lua, "}} }}")?;
+            }
+        }
+    }
+
+    writeln!(lua, "local {proto}_field_list = {{}}")?;
+    writeln!(
+        lua,
+        "for _, f in pairs({proto}_fields) do table.insert({proto}_field_list, f) end"
+    )?;
+    writeln!(lua, "{proto}.fields = {proto}_field_list")?;
+    writeln!(lua, "function {proto}.dissector(tvb, pinfo, tree)")?;
+    writeln!(
+        lua,
+        "    return dissect_frames({proto}, {proto}_fields, {proto}_packets, tvb, pinfo, tree)"
+    )?;
+    writeln!(lua, "end")?;
+
+    writeln!(lua, "tcp_port:add_for_decode_as({proto})")?;
+    for port in tcp_ports {
+        writeln!(lua, "tcp_port:add({port}, {proto})")?;
+    }
+    writeln!(lua, "udp_port:add_for_decode_as({proto})")?;
+    for port in udp_ports {
+        writeln!(lua, "udp_port:add({port}, {proto})")?;
+    }
+    Ok(())
+}
+
+/// Generates a Lua dissector covering both packet families.
+/// Load it by dropping the output into Wireshark's personal plugins folder.
+pub fn generate_lua(config: &DissectorConfig) -> String {
+    let mut lua = PRELUDE.to_string();
+    // Writing into a String can't fail
+    write_protocol(
+        &mut lua,
+        "ngmp_sl",
+        "NGMP server <-> launcher",
+        schema::SERVER_LAUNCHER,
+        &config.server_launcher_tcp_ports,
+        &config.server_launcher_udp_ports,
+    )
+    .unwrap();
+    write_protocol(
+        &mut lua,
+        "ngmp_lc",
+        "NGMP launcher <-> client",
+        schema::LAUNCHER_CLIENT,
+        &config.launcher_client_tcp_ports,
+        &[],
+    )
+    .unwrap();
+    lua
+}
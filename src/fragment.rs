@@ -0,0 +1,229 @@
+//! Splitting frames that don't fit into a single datagram and putting them back together.
+//!
+//! A frame bigger than the MTU goes out as a series of `FG` frames instead, with
+//! all integers little endian:
+//!
+//! | size | field                                          |
+//! |------|------------------------------------------------|
+//! | 4    | message id (u32), unique per sender            |
+//! | 2    | fragment index (u16)                           |
+//! | 2    | fragment count (u16)                           |
+//! | n    | chunk of the original frame, header included   |
+//!
+//! Fragments can arrive in any order. A message that isn't complete within the
+//! timeout gets dropped, as does anything that would push the reassembly buffers
+//! over their memory cap.
+
+use crate::framing::{datagram_frames, Frame};
+use crate::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+pub const SIG_A: char = 'F';
+pub const SIG_B: char = 'G';
+
+pub const FRAGMENT_HEADER_SIZE: usize = 8;
+/// Bytes a fragment frame adds on top of its chunk.
+pub const OVERHEAD: usize = PacketHeader::SIZE + FRAGMENT_HEADER_SIZE;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_MAX_BUFFERED: usize = 4 * 1024 * 1024;
+
+// Process wide, so ids are unique no matter which socket or batch a message goes through
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn is_fragment(frame: &Frame) -> bool {
+    (frame.sig_a, frame.sig_b) == (SIG_A, SIG_B)
+}
+
+/// Splits `frame` into the chunks that make every fragment frame fit into `mtu` bytes.
+pub fn chunks(frame: &[u8], mtu: usize) -> Result<std::slice::Chunks<'_, u8>, PacketEncodeError> {
+    let chunk_size = mtu.saturating_sub(OVERHEAD);
+    if chunk_size == 0 || frame.len().div_ceil(chunk_size) > u16::MAX as usize {
+        return Err(PacketEncodeError::FrameTooLarge(frame.len()));
+    }
+    Ok(frame.chunks(chunk_size))
+}
+
+/// Writes a complete fragment frame, header included.
+pub fn write_fragment(buf: &mut impl BufMut, id: u32, index: u16, count: u16, chunk: &[u8]) {
+    let header = PacketHeader {
+        sig_a: SIG_A,
+        sig_b: SIG_B,
+        packet_length: (FRAGMENT_HEADER_SIZE + chunk.len()) as u32,
+    };
+    buf.put_slice(&header.to_bytes());
+    buf.put_u32_le(id);
+    buf.put_u16_le(index);
+    buf.put_u16_le(count);
+    buf.put_slice(chunk);
+}
+
+/// Decodes a frame returned by [`Reassembler::push`].
+pub fn decode_reassembled<T: PacketTrait>(frame: &[u8]) -> anyhow::Result<T> {
+    Ok(reassembled_frame(frame)?.decode()?)
+}
+
+/// Splits the header off a frame returned by [`Reassembler::push`].
+pub fn reassembled_frame(frame: &[u8]) -> Result<Frame<'_>, ConnectionError> {
+    let mut frames = datagram_frames(frame)?;
+    match (frames.next(), frames.next()) {
+        (Some(frame), None) if !is_fragment(&frame) => Ok(frame),
+        _ => Err(ConnectionError::InvalidFragment),
+    }
+}
+
+#[derive(Debug)]
+struct Message {
+    started: Instant,
+    count: u16,
+    chunks: BTreeMap<u16, Vec<u8>>,
+    size: usize,
+}
+
+/// Collects fragments until their message is complete.
+/// `K` tells the senders apart, since message ids are only unique per sender.
+#[derive(Debug)]
+pub struct Reassembler<K> {
+    timeout: Duration,
+    max_buffered: usize,
+    buffered: usize,
+    messages: HashMap<(K, u32), Message>,
+}
+
+impl<K: Hash + Eq + Clone> Default for Reassembler<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT, DEFAULT_MAX_BUFFERED)
+    }
+}
+
+impl<K: Hash + Eq + Clone> Reassembler<K> {
+    pub fn new(timeout: Duration, max_buffered: usize) -> Self {
+        Self {
+            timeout,
+            max_buffered,
+            buffered: 0,
+            messages: HashMap::new(),
+        }
+    }
+
+    /// Bytes held by incomplete messages.
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// Number of incomplete messages.
+    pub fn pending(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Drops every message that didn't complete within the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut dropped = 0;
+        self.messages.retain(|_, message| {
+            let keep = now.saturating_duration_since(message.started) < timeout;
+            if !keep {
+                dropped += message.size;
+            }
+            keep
+        });
+        self.buffered -= dropped;
+    }
+
+    /// Drops everything buffered from `sender`, e.g. once they disconnected.
+    pub fn remove_sender(&mut self, sender: &K) {
+        let mut dropped = 0;
+        self.messages.retain(|(key, _), message| {
+            let keep = key != sender;
+            if !keep {
+                dropped += message.size;
+            }
+            keep
+        });
+        self.buffered -= dropped;
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .messages
+            .iter()
+            .min_by_key(|(_, message)| message.started)
+            .map(|(key, _)| key.clone());
+        if let Some(message) = oldest.and_then(|key| self.messages.remove(&key)) {
+            debug!(
+                "reassembly buffer full, dropping a message of {} fragments",
+                message.count
+            );
+            self.buffered -= message.size;
+        }
+    }
+
+    /// Takes the body of a `FG` frame and returns the original frame once
+    /// the last of its fragments arrives.
+    pub fn push(
+        &mut self,
+        sender: K,
+        fragment: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, ConnectionError> {
+        if fragment.len() <= FRAGMENT_HEADER_SIZE {
+            return Err(ConnectionError::InvalidFragment);
+        }
+        let id = u32::from_le_bytes(fragment[0..4].try_into().unwrap());
+        let index = u16::from_le_bytes([fragment[4], fragment[5]]);
+        let count = u16::from_le_bytes([fragment[6], fragment[7]]);
+        let chunk = &fragment[FRAGMENT_HEADER_SIZE..];
+        if index >= count {
+            return Err(ConnectionError::InvalidFragment);
+        }
+
+        self.expire(now);
+        if chunk.len() > self.max_buffered {
+            debug!("dropping a fragment bigger than the reassembly buffer");
+            return Ok(None);
+        }
+        while self.buffered + chunk.len() > self.max_buffered {
+            self.evict_oldest();
+        }
+
+        let key = (sender, id);
+        let message = self.messages.entry(key.clone()).or_insert(Message {
+            started: now,
+            count,
+            chunks: BTreeMap::new(),
+            size: 0,
+        });
+        if message.count != count {
+            let size = message.size;
+            self.messages.remove(&key);
+            self.buffered -= size;
+            return Err(ConnectionError::InvalidFragment);
+        }
+        if message.chunks.contains_key(&index) {
+            // Duplicated in transit
+            return Ok(None);
+        }
+        message.chunks.insert(index, chunk.to_vec());
+        message.size += chunk.len();
+        self.buffered += chunk.len();
+
+        if message.chunks.len() < count as usize {
+            return Ok(None);
+        }
+        let message = self.messages.remove(&key).unwrap();
+        self.buffered -= message.size;
+        let mut frame = Vec::with_capacity(message.size);
+        for chunk in message.chunks.into_values() {
+            frame.extend_from_slice(&chunk);
+        }
+        Ok(Some(frame))
+    }
+}
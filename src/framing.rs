@@ -0,0 +1,301 @@
+//! Splitting raw bytes into packet frames, independent of any socket.
+
+use crate::fragment;
+use crate::*;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// A single frame, borrowing its body from the buffer it was received into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub sig_a: char,
+    pub sig_b: char,
+    pub data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn header(&self) -> PacketHeader {
+        PacketHeader {
+            sig_a: self.sig_a,
+            sig_b: self.sig_b,
+            packet_length: self.data.len() as u32,
+        }
+    }
+
+    pub fn decode<T: PacketTrait>(&self) -> Result<T, PacketDecodeError> {
+        T::from_raw(self.sig_a, self.sig_b, self.data)
+    }
+}
+
+/// Empty frame sent to keep an otherwise idle connection alive. The connections
+/// handle it themselves, it never gets decoded into a packet of either family.
+pub const HEARTBEAT: [u8; PacketHeader::SIZE] = [b'H', b'B', 0, 0, 0, 0];
+
+pub fn is_heartbeat(frame: &Frame) -> bool {
+    (frame.sig_a, frame.sig_b) == ('H', 'B')
+}
+
+/// Largest frame body a [`StreamDecoder`] accepts unless configured otherwise.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Reassembles frames from a byte stream that arrives in arbitrarily sized chunks.
+#[derive(Debug)]
+pub struct StreamDecoder {
+    buf: Vec<u8>,
+    /// Bytes at the start of `buf` that were already handed out as frames.
+    /// They get dropped on the next `extend`, so returned frames can borrow `buf`.
+    consumed: usize,
+    max_frame_len: usize,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames with a body longer than `max_frame_len` bytes are rejected instead of
+    /// buffered until complete.
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            consumed: 0,
+            max_frame_len,
+        }
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        if self.consumed > 0 {
+            self.buf.drain(..self.consumed);
+            self.consumed = 0;
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes buffered that haven't been returned as part of a frame yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.consumed
+    }
+
+    fn peek_header(&self) -> Option<PacketHeader> {
+        let header_raw = self.buf[self.consumed..].get(..PacketHeader::SIZE)?;
+        Some(PacketHeader::from_bytes(header_raw.try_into().ok()?))
+    }
+
+    /// Returns the next complete frame, if one has been buffered.
+    /// A frame over the size limit fails this and every later call, the stream
+    /// can't be resynchronised after it.
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'_>>, ConnectionError> {
+        let Some(header) = self.peek_header() else {
+            return Ok(None);
+        };
+        if header.packet_length as usize > self.max_frame_len {
+            return Err(ConnectionError::FrameTooLarge(
+                header.packet_length as usize,
+            ));
+        }
+        // Only ever buffer what the peer actually sent, never preallocate based on
+        // the untrusted length field
+        let frame_len = PacketHeader::SIZE + header.packet_length as usize;
+        if self.buffered() < frame_len {
+            return Ok(None);
+        }
+        let start = self.consumed + PacketHeader::SIZE;
+        self.consumed += frame_len;
+        Ok(Some(Frame {
+            sig_a: header.sig_a,
+            sig_b: header.sig_b,
+            data: &self.buf[start..self.consumed],
+        }))
+    }
+}
+
+/// The frames coalesced into a single UDP datagram, borrowing its bytes.
+#[derive(Debug, Clone)]
+pub struct DatagramFrames<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for DatagramFrames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        // Already validated by `datagram_frames`
+        let header =
+            PacketHeader::from_bytes(self.rest.get(..PacketHeader::SIZE)?.try_into().ok()?);
+        let end = PacketHeader::SIZE + header.packet_length as usize;
+        let data = &self.rest[PacketHeader::SIZE..end];
+        self.rest = &self.rest[end..];
+        Some(Frame {
+            sig_a: header.sig_a,
+            sig_b: header.sig_b,
+            data,
+        })
+    }
+}
+
+/// Splits a datagram into its frames. The whole datagram is checked upfront:
+/// every byte has to belong to a frame, otherwise none of them are returned.
+pub fn datagram_frames(buf: &[u8]) -> Result<DatagramFrames<'_>, ConnectionError> {
+    if buf.is_empty() {
+        return Err(ConnectionError::InvalidPacketSize);
+    }
+
+    let mut rest = buf;
+    while !rest.is_empty() {
+        let Some(header_raw) = rest.get(..PacketHeader::SIZE) else {
+            return Err(ConnectionError::TrailingData(rest.len()));
+        };
+        let header = PacketHeader::from_bytes(header_raw.try_into().unwrap());
+        let frame_len = PacketHeader::SIZE + header.packet_length as usize;
+        if rest.len() < frame_len {
+            return Err(ConnectionError::InvalidPacketSize);
+        }
+        rest = &rest[frame_len..];
+    }
+
+    Ok(DatagramFrames { rest: buf })
+}
+
+/// Decodes every packet in a UDP datagram, failing if any frame can't be decoded.
+pub fn decode_datagram<T: PacketTrait>(buf: &[u8]) -> anyhow::Result<Vec<T>> {
+    let mut packets = Vec::new();
+    for frame in datagram_frames(buf)? {
+        packets.push(frame.decode()?);
+    }
+    Ok(packets)
+}
+
+/// Encodes a packet once into a frame that can be handed to any number of connections.
+pub fn encode_frame<T: PacketTrait>(packet: &T) -> Result<Bytes, PacketEncodeError> {
+    let mut buf = BytesMut::with_capacity(packet.encoded_len());
+    packet.encode_into(&mut buf)?;
+    Ok(buf.freeze())
+}
+
+/// Conservative UDP payload size that fits into a single IP packet on practically every path.
+pub const DEFAULT_MTU: usize = 1200;
+
+/// Packs frames into as few datagrams as possible, each at most `mtu` bytes.
+/// Frames bigger than the MTU get split up, see [`fragment`](crate::fragment).
+#[derive(Debug)]
+pub struct DatagramBatch {
+    mtu: usize,
+    buf: BytesMut,
+    /// Where each datagram starts in `buf`, the last one is still being filled.
+    starts: Vec<usize>,
+    /// Packets too big for a datagram get encoded here before being fragmented.
+    oversized: BytesMut,
+}
+
+impl Default for DatagramBatch {
+    fn default() -> Self {
+        Self::new(DEFAULT_MTU)
+    }
+}
+
+impl DatagramBatch {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            buf: BytesMut::new(),
+            starts: Vec::new(),
+            oversized: BytesMut::new(),
+        }
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Only affects datagrams started after this call.
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    fn reserve_frame(&mut self, frame_len: usize) {
+        let current_len = match self.starts.last() {
+            Some(start) => self.buf.len() - start,
+            None => 0,
+        };
+        if self.starts.is_empty() || current_len + frame_len > self.mtu {
+            self.starts.push(self.buf.len());
+        }
+    }
+
+    pub fn push<T: PacketTrait>(&mut self, packet: &T) -> Result<(), PacketEncodeError> {
+        let encoded_len = packet.encoded_len();
+        if encoded_len > self.mtu {
+            let mut oversized = std::mem::take(&mut self.oversized);
+            oversized.clear();
+            let result = packet
+                .encode_into(&mut oversized)
+                .and_then(|()| self.push_frame(&oversized));
+            self.oversized = oversized;
+            return result;
+        }
+
+        let (len, datagrams) = (self.buf.len(), self.starts.len());
+        self.reserve_frame(encoded_len);
+        if let Err(e) = packet.encode_into(&mut self.buf) {
+            self.buf.truncate(len);
+            self.starts.truncate(datagrams);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Adds an already encoded frame, e.g. from [`encode_frame`].
+    pub fn push_frame(&mut self, frame: &[u8]) -> Result<(), PacketEncodeError> {
+        if frame.len() <= self.mtu {
+            self.reserve_frame(frame.len());
+            self.buf.put_slice(frame);
+            return Ok(());
+        }
+
+        let chunks = fragment::chunks(frame, self.mtu)?;
+        let count = chunks.len() as u16;
+        let id = fragment::next_id();
+        for (index, chunk) in chunks.enumerate() {
+            self.reserve_frame(fragment::OVERHEAD + chunk.len());
+            fragment::write_fragment(&mut self.buf, id, index as u16, count, chunk);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Number of datagrams the queued frames take up.
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn datagrams(&self) -> impl Iterator<Item = &[u8]> {
+        let ends = self.starts.iter().skip(1).copied().chain([self.buf.len()]);
+        self.starts
+            .iter()
+            .zip(ends)
+            .map(|(&start, end)| &self.buf[start..end])
+    }
+
+    /// Drops every queued frame, keeping the memory around for the next tick.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.starts.clear();
+    }
+}
@@ -0,0 +1,214 @@
+//! Deciding which players get updates about which vehicles.
+//!
+//! Relaying every vehicle to every player scales quadratically, so players only
+//! get full rate updates for vehicles close to them, every few updates for those
+//! further out and nothing at all beyond that. Players are kept in a grid of
+//! square cells on the horizontal plane, so finding the players around a vehicle
+//! only looks at the cells within range.
+
+use crate::ConfigError;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterestLevel {
+    /// Every update.
+    Full,
+    /// Every [`InterestConfig::reduced_every`]th update.
+    Reduced,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterestConfig {
+    /// Edge length of a grid cell, in metres.
+    pub cell_size: f32,
+    pub full_radius: f32,
+    pub reduced_radius: f32,
+    pub reduced_every: u32,
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 100.0,
+            full_radius: 300.0,
+            reduced_radius: 1000.0,
+            reduced_every: 4,
+        }
+    }
+}
+
+type Cell = (i32, i32);
+
+struct Vehicle<P> {
+    owner: P,
+    pos: [f32; 3],
+    /// Number of updates passed through `targets` so far.
+    updates: u32,
+}
+
+/// Tracks where players and vehicles are. `P` identifies a player, e.g. their
+/// `SocketAddr`, `V` a vehicle, e.g. `(player_id, vehicle_id)`.
+pub struct InterestManager<P, V> {
+    config: InterestConfig,
+    players: HashMap<P, [f32; 3]>,
+    cells: HashMap<Cell, Vec<P>>,
+    vehicles: HashMap<V, Vehicle<P>>,
+}
+
+impl<P: Hash + Eq + Clone, V: Hash + Eq + Clone> Default for InterestManager<P, V> {
+    fn default() -> Self {
+        Self::new(InterestConfig::default()).expect("the default config is valid")
+    }
+}
+
+impl<P: Hash + Eq + Clone, V: Hash + Eq + Clone> InterestManager<P, V> {
+    /// Fails if `cell_size` isn't a positive, finite number, or the radii aren't
+    /// finite with `0 <= full_radius <= reduced_radius`.
+    pub fn new(config: InterestConfig) -> Result<Self, ConfigError> {
+        if !(config.cell_size.is_finite() && config.cell_size > 0.0) {
+            return Err(ConfigError::InvalidCellSize(config.cell_size));
+        }
+        let (full, reduced) = (config.full_radius, config.reduced_radius);
+        if !(full.is_finite() && reduced.is_finite() && 0.0 <= full && full <= reduced) {
+            return Err(ConfigError::InvalidRadii { full, reduced });
+        }
+        Ok(Self {
+            config,
+            players: HashMap::new(),
+            cells: HashMap::new(),
+            vehicles: HashMap::new(),
+        })
+    }
+
+    fn cell(&self, pos: [f32; 3]) -> Cell {
+        (
+            (pos[0] / self.config.cell_size).floor() as i32,
+            (pos[1] / self.config.cell_size).floor() as i32,
+        )
+    }
+
+    fn remove_from_cell(&mut self, player: &P, cell: Cell) {
+        if let Some(players) = self.cells.get_mut(&cell) {
+            players.retain(|p| p != player);
+            if players.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// Where the player is looking from, usually their camera or own vehicle.
+    /// Players that never had a position set don't receive anything.
+    pub fn set_player(&mut self, player: P, pos: [f32; 3]) {
+        let cell = self.cell(pos);
+        if let Some(old) = self.players.insert(player.clone(), pos) {
+            let old_cell = self.cell(old);
+            if old_cell == cell {
+                return;
+            }
+            self.remove_from_cell(&player, old_cell);
+        }
+        self.cells.entry(cell).or_default().push(player);
+    }
+
+    /// Also forgets every vehicle the player owns.
+    pub fn remove_player(&mut self, player: &P) {
+        if let Some(pos) = self.players.remove(player) {
+            self.remove_from_cell(player, self.cell(pos));
+        }
+        self.vehicles.retain(|_, vehicle| &vehicle.owner != player);
+    }
+
+    /// Its owner never gets updates about it, they already know where it is.
+    pub fn update_vehicle(&mut self, vehicle: V, owner: P, pos: [f32; 3]) {
+        let entry = self.vehicles.entry(vehicle).or_insert(Vehicle {
+            owner: owner.clone(),
+            pos,
+            updates: 0,
+        });
+        entry.owner = owner;
+        entry.pos = pos;
+    }
+
+    pub fn remove_vehicle(&mut self, vehicle: &V) {
+        self.vehicles.remove(vehicle);
+    }
+
+    fn level_at(&self, distance: f32) -> InterestLevel {
+        if distance <= self.config.full_radius {
+            InterestLevel::Full
+        } else if distance <= self.config.reduced_radius {
+            InterestLevel::Reduced
+        } else {
+            InterestLevel::None
+        }
+    }
+
+    pub fn level(&self, player: &P, vehicle: &V) -> InterestLevel {
+        match (self.players.get(player), self.vehicles.get(vehicle)) {
+            (Some(player_pos), Some(vehicle)) if &vehicle.owner != player => {
+                self.level_at(distance(*player_pos, vehicle.pos))
+            }
+            _ => InterestLevel::None,
+        }
+    }
+
+    /// Every player interested in the vehicle, along with how much.
+    pub fn recipients(&self, vehicle: &V) -> Vec<(P, InterestLevel)> {
+        let Some(vehicle) = self.vehicles.get(vehicle) else {
+            return Vec::new();
+        };
+        let radius = self.config.reduced_radius;
+        let (min_x, min_y) = self.cell([vehicle.pos[0] - radius, vehicle.pos[1] - radius, 0.0]);
+        let (max_x, max_y) = self.cell([vehicle.pos[0] + radius, vehicle.pos[1] + radius, 0.0]);
+
+        let mut recipients = Vec::new();
+        let mut consider = |player: &P| {
+            if player == &vehicle.owner {
+                return;
+            }
+            let level = self.level_at(distance(self.players[player], vehicle.pos));
+            if level != InterestLevel::None {
+                recipients.push((player.clone(), level));
+            }
+        };
+
+        // Far away or huge positions can span more cells than there are players
+        let cell_count = (max_x as i64 - min_x as i64 + 1) * (max_y as i64 - min_y as i64 + 1);
+        if cell_count > self.players.len() as i64 {
+            self.players.keys().for_each(consider);
+        } else {
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    if let Some(players) = self.cells.get(&(x, y)) {
+                        players.iter().for_each(&mut consider);
+                    }
+                }
+            }
+        }
+        recipients
+    }
+
+    /// The players that should get the vehicle's current update. Every call counts
+    /// as one update, so call it exactly once per update that gets relayed.
+    pub fn targets(&mut self, vehicle: &V) -> Vec<P> {
+        let recipients = self.recipients(vehicle);
+        let Some(vehicle) = self.vehicles.get_mut(vehicle) else {
+            return Vec::new();
+        };
+        let reduced_due = vehicle.updates % self.config.reduced_every.max(1) == 0;
+        vehicle.updates = vehicle.updates.wrapping_add(1);
+        recipients
+            .into_iter()
+            .filter(|(_, level)| *level == InterestLevel::Full || reduced_due)
+            .map(|(player, _)| player)
+            .collect()
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let (dx, dy, dz) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+    (dx * dx + dy * dy + dz * dz).sqrt()
+}
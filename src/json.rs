@@ -0,0 +1,37 @@
+//! Encoding of the JSON packet bodies straight into the output buffer.
+
+use crate::PacketEncodeError;
+
+use bytes::BufMut;
+use serde::Serialize;
+
+struct Counter(usize);
+
+impl std::io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Exact size of the serialized value. Serializes without keeping the output,
+/// so the frame header can be written before the body.
+pub(crate) fn encoded_len<T: Serialize>(value: &T) -> usize {
+    let mut counter = Counter(0);
+    // A failure shows up again in `encode_into`, this is only used as a length
+    match serde_json::to_writer(&mut counter, value) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
+
+pub(crate) fn encode_into<T: Serialize>(
+    buf: &mut impl BufMut,
+    value: &T,
+) -> Result<(), PacketEncodeError> {
+    serde_json::to_writer(buf.writer(), value).map_err(|_| PacketEncodeError::CannotSerializeJson)
+}
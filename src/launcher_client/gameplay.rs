@@ -1,33 +1,45 @@
 use super::{PacketDecodeError, PacketEncodeError};
+use crate::json;
+
+use bytes::BufMut;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerData {
     pub name: String,
     pub steam_id: String,
     pub avatar_hash: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerDataPacket {
     pub players: Vec<PlayerData>,
 }
 
 impl PlayerDataPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
-            .map_err(|e| PacketDecodeError::InvalidJson("VehicleSpawnPacket", e))
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
+            .map_err(|e| PacketDecodeError::InvalidJson("PlayerDataPacket", e))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
     }
 
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleData {
     #[serde(rename = "Jbeam")]
     pub jbeam: String,
@@ -39,7 +51,7 @@ pub struct VehicleData {
     pub rot: [f32; 4],
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleSpawnPacket {
     pub confirm_id: u16,
     pub steam_id: String,
@@ -48,20 +60,29 @@ pub struct VehicleSpawnPacket {
 }
 
 impl VehicleSpawnPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleSpawnPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleConfirmPacket {
     pub confirm_id: u16,
     pub vehicle_id: u16,
@@ -69,40 +90,58 @@ pub struct VehicleConfirmPacket {
 }
 
 impl VehicleConfirmPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleConfirmPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleDeletePacket {
     pub steam_id: String,
     pub vehicle_id: u16,
 }
 
 impl VehicleDeletePacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleDeletePacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleTransformPacket {
     pub steam_id: String,
     pub vehicle_id: u16,
@@ -110,20 +149,29 @@ pub struct VehicleTransformPacket {
 }
 
 impl VehicleTransformPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleTransformPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleUpdatePacket {
     pub steam_id: String,
     pub vehicle_id: u16,
@@ -131,15 +179,24 @@ pub struct VehicleUpdatePacket {
 }
 
 impl VehicleUpdatePacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleUpdatePacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
@@ -1,78 +1,149 @@
-use super::{PacketDecodeError, PacketEncodeError};
+use super::{DisconnectReason, PacketDecodeError, PacketEncodeError};
+use crate::json;
+
+use bytes::BufMut;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ConfirmationPacket {
     pub confirm_id: u16,
 }
 
 impl ConfirmationPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("ConfirmationPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct JoinServerPacket {
     pub ip_address: String,
 }
 
 impl JoinServerPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("JoinServerPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ConnectionErrorPacket {
     pub error: String,
 }
 
 impl ConnectionErrorPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("ConnectionErrorPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Forwarded to the game when the launcher's server connection closes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DisconnectPacket {
+    pub reason: DisconnectReason,
+    /// Extra detail for the player, may be empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message: String,
+}
+
+impl DisconnectPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
+            .map_err(|e| PacketDecodeError::InvalidJson("DisconnectPacket", e))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct LoadMapPacket {
     pub confirm_id: u16,
     pub map_string: String,
 }
 
 impl LoadMapPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json).map_err(|e| PacketDecodeError::InvalidJson("LoadMapPacket", e))
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json).map_err(|e| PacketDecodeError::InvalidJson("LoadMapPacket", e))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
     }
 
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
@@ -1,45 +1,66 @@
 use super::{PacketDecodeError, PacketEncodeError};
+use crate::json;
+
+use bytes::BufMut;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VersionPacket {
     pub protocol_version: u16,
 }
 
 impl VersionPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json).map_err(|e| PacketDecodeError::InvalidJson("VersionPacket", e))
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json).map_err(|e| PacketDecodeError::InvalidJson("VersionPacket", e))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
     }
 
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ClientInfoPacket {
     pub userfolder: String,
     pub client_version: u16,
 }
 
 impl ClientInfoPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("ClientInfoPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AuthenticationInfoPacket {
     pub success: bool,
     pub player_name: String,
@@ -48,15 +69,24 @@ pub struct AuthenticationInfoPacket {
 }
 
 impl AuthenticationInfoPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("AuthenticationInfoPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
@@ -3,17 +3,22 @@ use crate::*;
 pub mod gameplay;
 pub mod generic;
 pub mod handshake;
+pub mod reload;
 
 use gameplay::*;
 use generic::*;
 use handshake::*;
 
-#[derive(Debug)]
+use bytes::BufMut;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub enum Packet {
     ReloadLauncherConnection,
 
     Confirmation(ConfirmationPacket),
     ConnectionError(ConnectionErrorPacket),
+    Disconnect(DisconnectPacket),
 
     Version(VersionPacket),
     ClientInfo(ClientInfoPacket),
@@ -33,8 +38,94 @@ pub enum Packet {
     VehicleUpdate(VehicleUpdatePacket),
 }
 
+impl Packet {
+    pub fn signature(&self) -> (char, char) {
+        match self {
+            Self::ReloadLauncherConnection => ('R', 'L'),
+
+            Self::Confirmation(_) => ('C', 'C'),
+            Self::ConnectionError(_) => ('C', 'E'),
+            Self::Disconnect(_) => ('D', 'C'),
+
+            Self::Version(_) => ('V', 'C'),
+
+            Self::ClientInfo(_) => ('C', 'I'),
+            Self::AuthenticationInfo(_) => ('A', 'I'),
+            Self::LoginRequest => ('L', 'R'),
+
+            Self::JoinServer(_) => ('H', 'J'),
+            Self::LoadMap(_) => ('L', 'M'),
+
+            Self::PlayerData(_) => ('P', 'D'),
+
+            Self::VehicleSpawn(_) => ('V', 'S'),
+            Self::VehicleConfirm(_) => ('V', 'A'),
+            Self::VehicleDelete(_) => ('V', 'D'),
+
+            Self::VehicleTransform(_) => ('V', 'T'),
+            Self::VehicleUpdate(_) => ('V', 'U'),
+        }
+    }
+
+    fn body_len(&self) -> usize {
+        match self {
+            Self::ReloadLauncherConnection => 0,
+
+            Self::Confirmation(p) => p.encoded_len(),
+            Self::ConnectionError(p) => p.encoded_len(),
+            Self::Disconnect(p) => p.encoded_len(),
+
+            Self::Version(p) => p.encoded_len(),
+
+            Self::ClientInfo(p) => p.encoded_len(),
+            Self::AuthenticationInfo(p) => p.encoded_len(),
+            Self::LoginRequest => 0,
+
+            Self::JoinServer(p) => p.encoded_len(),
+            Self::LoadMap(p) => p.encoded_len(),
+
+            Self::PlayerData(p) => p.encoded_len(),
+
+            Self::VehicleSpawn(p) => p.encoded_len(),
+            Self::VehicleConfirm(p) => p.encoded_len(),
+            Self::VehicleDelete(p) => p.encoded_len(),
+
+            Self::VehicleTransform(p) => p.encoded_len(),
+            Self::VehicleUpdate(p) => p.encoded_len(),
+        }
+    }
+
+    fn encode_body(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        match self {
+            Self::ReloadLauncherConnection => Ok(()),
+
+            Self::Confirmation(p) => p.encode_into(buf),
+            Self::ConnectionError(p) => p.encode_into(buf),
+            Self::Disconnect(p) => p.encode_into(buf),
+
+            Self::Version(p) => p.encode_into(buf),
+
+            Self::ClientInfo(p) => p.encode_into(buf),
+            Self::AuthenticationInfo(p) => p.encode_into(buf),
+            Self::LoginRequest => Ok(()),
+
+            Self::JoinServer(p) => p.encode_into(buf),
+            Self::LoadMap(p) => p.encode_into(buf),
+
+            Self::PlayerData(p) => p.encode_into(buf),
+
+            Self::VehicleSpawn(p) => p.encode_into(buf),
+            Self::VehicleConfirm(p) => p.encode_into(buf),
+            Self::VehicleDelete(p) => p.encode_into(buf),
+
+            Self::VehicleTransform(p) => p.encode_into(buf),
+            Self::VehicleUpdate(p) => p.encode_into(buf),
+        }
+    }
+}
+
 impl PacketTrait for Packet {
-    fn from_raw(sig_a: char, sig_b: char, packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    fn from_raw(sig_a: char, sig_b: char, packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         match (sig_a, sig_b) {
             ('R', 'L') => Ok(Self::ReloadLauncherConnection),
 
@@ -44,6 +135,7 @@ impl PacketTrait for Packet {
             ('C', 'E') => Ok(Self::ConnectionError(ConnectionErrorPacket::from_raw(
                 packet_data,
             )?)),
+            ('D', 'C') => Ok(Self::Disconnect(DisconnectPacket::from_raw(packet_data)?)),
 
             ('V', 'C') => Ok(Self::Version(VersionPacket::from_raw(packet_data)?)),
 
@@ -80,29 +172,24 @@ impl PacketTrait for Packet {
     }
 
     fn to_raw(&self) -> Result<(char, char, Vec<u8>), PacketEncodeError> {
-        match self {
-            Self::ReloadLauncherConnection => Ok(('R', 'L', Vec::new())),
-
-            Self::Confirmation(p) => Ok(('C', 'C', p.to_raw()?)),
-            Self::ConnectionError(p) => Ok(('C', 'E', p.to_raw()?)),
-
-            Self::Version(p) => Ok(('V', 'C', p.to_raw()?)),
-
-            Self::ClientInfo(p) => Ok(('C', 'I', p.to_raw()?)),
-            Self::AuthenticationInfo(p) => Ok(('A', 'I', p.to_raw()?)),
-            Self::LoginRequest => Ok(('L', 'R', Vec::new())),
-
-            Self::JoinServer(p) => Ok(('H', 'J', p.to_raw()?)),
-            Self::LoadMap(p) => Ok(('L', 'M', p.to_raw()?)),
-
-            Self::PlayerData(p) => Ok(('P', 'D', p.to_raw()?)),
+        let (sig_a, sig_b) = self.signature();
+        let mut raw = Vec::with_capacity(self.body_len());
+        self.encode_body(&mut raw)?;
+        Ok((sig_a, sig_b, raw))
+    }
 
-            Self::VehicleSpawn(p) => Ok(('V', 'S', p.to_raw()?)),
-            Self::VehicleConfirm(p) => Ok(('V', 'A', p.to_raw()?)),
-            Self::VehicleDelete(p) => Ok(('V', 'D', p.to_raw()?)),
+    fn encoded_len(&self) -> usize {
+        PacketHeader::SIZE + self.body_len()
+    }
 
-            Self::VehicleTransform(p) => Ok(('V', 'T', p.to_raw()?)),
-            Self::VehicleUpdate(p) => Ok(('V', 'U', p.to_raw()?)),
-        }
+    fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        let (sig_a, sig_b) = self.signature();
+        let header = PacketHeader {
+            sig_a,
+            sig_b,
+            packet_length: self.body_len() as u32,
+        };
+        buf.put_slice(&header.to_bytes());
+        self.encode_body(buf)
     }
 }
@@ -0,0 +1,188 @@
+//! Tearing down and re-establishing the connection between the game and the launcher.
+//!
+//! The game asks for it with `ReloadLauncherConnection` ('R', 'L'), e.g. after its
+//! scripts got reloaded. The launcher waits for the game to connect again, drops the
+//! old connection, redoes the version and `ClientInfo` exchange and then replays
+//! everything the game has to know about the current server session.
+//! The server connection is left alone the whole time.
+
+use super::gameplay::*;
+use super::generic::*;
+use super::handshake::*;
+use super::Packet;
+use crate::connection::TcpConnection;
+use crate::*;
+
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+/// What the launcher told the game about the current server session.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClientSessionState {
+    pub authentication: Option<AuthenticationInfoPacket>,
+    pub map: Option<LoadMapPacket>,
+    pub players: Option<PlayerDataPacket>,
+    /// Spawned vehicles in spawn order, along with their latest transform.
+    pub vehicles: Vec<(VehicleSpawnPacket, Option<VehicleTransformPacket>)>,
+}
+
+impl ClientSessionState {
+    /// Updates the state with a packet that was sent to the game.
+    pub fn observe(&mut self, packet: &Packet) {
+        match packet {
+            Packet::AuthenticationInfo(p) => self.authentication = Some(p.clone()),
+            Packet::LoadMap(p) => {
+                // Nothing from the previous map carries over
+                self.map = Some(p.clone());
+                self.vehicles.clear();
+            }
+            Packet::PlayerData(p) => self.players = Some(p.clone()),
+            Packet::VehicleSpawn(p) => {
+                self.vehicles
+                    .retain(|(v, _)| (&v.steam_id, v.vehicle_id) != (&p.steam_id, p.vehicle_id));
+                self.vehicles.push((p.clone(), None));
+            }
+            Packet::VehicleDelete(p) => self
+                .vehicles
+                .retain(|(v, _)| (&v.steam_id, v.vehicle_id) != (&p.steam_id, p.vehicle_id)),
+            Packet::VehicleTransform(p) => {
+                if let Some((_, transform)) = self
+                    .vehicles
+                    .iter_mut()
+                    .find(|(v, _)| (&v.steam_id, v.vehicle_id) == (&p.steam_id, p.vehicle_id))
+                {
+                    *transform = Some(p.clone());
+                }
+            }
+            Packet::Disconnect(_) => *self = Self::default(),
+            _ => {}
+        }
+    }
+
+    /// The packets that bring a freshly connected game up to date, in the order to send them.
+    pub fn restore_packets(&self) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        packets.extend(self.authentication.clone().map(Packet::AuthenticationInfo));
+        packets.extend(self.map.clone().map(Packet::LoadMap));
+        packets.extend(self.players.clone().map(Packet::PlayerData));
+        for (spawn, transform) in &self.vehicles {
+            packets.push(Packet::VehicleSpawn(spawn.clone()));
+            packets.extend(transform.clone().map(Packet::VehicleTransform));
+        }
+        packets
+    }
+}
+
+pub const DEFAULT_RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Launcher side of the connection to the game, handling reload requests on its own.
+pub struct GameConnection {
+    listener: TcpListener,
+    conn: TcpConnection<Packet>,
+    protocol_version: u16,
+    client_info: ClientInfoPacket,
+    state: ClientSessionState,
+    reload_timeout: Duration,
+    reloads: u32,
+}
+
+impl GameConnection {
+    /// Waits for the game to connect and does the version and `ClientInfo` exchange.
+    /// The listener is kept to accept the game again after a reload.
+    pub async fn accept(listener: TcpListener, protocol_version: u16) -> anyhow::Result<Self> {
+        let (conn, client_info) = Self::accept_game(&listener, protocol_version).await?;
+        Ok(Self {
+            listener,
+            conn,
+            protocol_version,
+            client_info,
+            state: ClientSessionState::default(),
+            reload_timeout: DEFAULT_RELOAD_TIMEOUT,
+            reloads: 0,
+        })
+    }
+
+    async fn accept_game(
+        listener: &TcpListener,
+        protocol_version: u16,
+    ) -> anyhow::Result<(TcpConnection<Packet>, ClientInfoPacket)> {
+        let (stream, addr) = listener.accept().await?;
+        debug!("game connected from {addr}");
+        let mut conn = TcpConnection::from_stream(stream);
+
+        let version = match conn.wait_for_packet().await? {
+            Packet::Version(version) => version,
+            packet => anyhow::bail!("expected a version, got {:?}", packet.signature()),
+        };
+        if version.protocol_version != protocol_version {
+            conn.close_with(&Packet::Disconnect(DisconnectPacket {
+                reason: DisconnectReason::VersionMismatch,
+                message: format!("launcher speaks protocol version {protocol_version}"),
+            }))
+            .await?;
+            anyhow::bail!(
+                "game speaks protocol version {}, expected {protocol_version}",
+                version.protocol_version
+            );
+        }
+        conn.write_packet(&Packet::Version(VersionPacket { protocol_version }))
+            .await?;
+
+        match conn.wait_for_packet().await? {
+            Packet::ClientInfo(client_info) => Ok((conn, client_info)),
+            packet => anyhow::bail!("expected client info, got {:?}", packet.signature()),
+        }
+    }
+
+    /// How long to wait for the game to come back after it asked for a reload.
+    /// Waiting any longer fails with [`ConnectionError::Timeout`].
+    pub fn set_reload_timeout(&mut self, timeout: Duration) {
+        self.reload_timeout = timeout;
+    }
+
+    /// From the latest handshake.
+    pub fn client_info(&self) -> &ClientInfoPacket {
+        &self.client_info
+    }
+
+    pub fn state(&self) -> &ClientSessionState {
+        &self.state
+    }
+
+    pub fn reloads(&self) -> u32 {
+        self.reloads
+    }
+
+    /// Reload requests are handled here and never returned.
+    pub async fn wait_for_packet(&mut self) -> anyhow::Result<Packet> {
+        loop {
+            match self.conn.wait_for_packet().await? {
+                Packet::ReloadLauncherConnection => self.reload().await?,
+                packet => return Ok(packet),
+            }
+        }
+    }
+
+    /// Everything sent through here is remembered to be replayed after a reload.
+    pub async fn write_packet(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        self.state.observe(packet);
+        self.conn.write_packet(packet).await
+    }
+
+    async fn reload(&mut self) -> anyhow::Result<()> {
+        debug!("game requested a reload of the launcher connection");
+        let accept = Self::accept_game(&self.listener, self.protocol_version);
+        let (conn, client_info) = tokio::time::timeout(self.reload_timeout, accept)
+            .await
+            .map_err(|_| ConnectionError::Timeout)??;
+        // Dropping the old connection closes it
+        self.conn = conn;
+        self.client_info = client_info;
+        for packet in self.state.restore_packets() {
+            self.conn.write_packet(&packet).await?;
+        }
+        self.reloads += 1;
+        Ok(())
+    }
+}
@@ -1,10 +1,26 @@
 #[macro_use]
 extern crate log;
 
+pub mod capture;
+pub mod clock;
 pub mod connection;
+pub mod crypto;
+pub mod dissector;
+pub mod fragment;
+pub mod framing;
+pub mod interest;
+mod json;
 pub mod launcher_client;
+pub mod rate;
+mod redact;
+pub mod replay;
+pub mod schema;
 pub mod server_launcher;
+pub mod session;
+#[cfg(feature = "tls")]
+pub mod tls;
 
+use bytes::BufMut;
 use thiserror::Error;
 
 pub struct PacketHeader {
@@ -13,10 +29,80 @@ pub struct PacketHeader {
     pub packet_length: u32,
 }
 
+impl PacketHeader {
+    pub const SIZE: usize = 6;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            sig_a: bytes[0] as char,
+            sig_b: bytes[1] as char,
+            packet_length: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let len = self.packet_length.to_le_bytes();
+        [
+            self.sig_a as u8,
+            self.sig_b as u8,
+            len[0],
+            len[1],
+            len[2],
+            len[3],
+        ]
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ConnectionError {
     #[error("invalid packet size")]
     InvalidPacketSize,
+    #[error("trailing data after the last frame ({0} bytes)")]
+    TrailingData(usize),
+    #[error("invalid fragment")]
+    InvalidFragment,
+    #[error("nothing received from the peer within the idle timeout")]
+    Timeout,
+    #[error("server refused to resume the session ({0:?})")]
+    ResumeRefused(DisconnectReason),
+    #[error("encrypted frame failed authentication")]
+    Unauthenticated,
+    #[error("replayed or outdated encrypted frame (sequence {0})")]
+    Replayed(u64),
+    #[error("unencrypted frame from a peer that has to encrypt")]
+    Unencrypted,
+    #[error("key is already installed, every installation needs a new one")]
+    KeyReused,
+    #[error("frame over the size limit ({0} bytes)")]
+    FrameTooLarge(usize),
+}
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("not a capture file")]
+    InvalidMagic,
+    #[error("unsupported capture version {0}")]
+    UnsupportedVersion(u8),
+    #[error("corrupt capture record")]
+    CorruptRecord,
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot decode captured frame: {0}")]
+    Decode(#[from] PacketDecodeError),
+    #[error("invalid captured frames: {0}")]
+    InvalidFrame(#[from] ConnectionError),
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("cell size has to be a positive number of metres (got {0})")]
+    InvalidCellSize(f32),
+    #[error("falloff distance has to be a positive number of metres (got {0})")]
+    InvalidFalloffDistance(f32),
+    #[error(
+        "interest radii have to be finite with 0 <= full <= reduced (got {full} and {reduced})"
+    )]
+    InvalidRadii { full: f32, reduced: f32 },
 }
 
 #[derive(Error, Debug)]
@@ -39,9 +125,61 @@ pub enum PacketDecodeError {
 pub enum PacketEncodeError {
     #[error("cannot serialize to json")]
     CannotSerializeJson,
+    #[error("frame too large to fragment ({0} bytes)")]
+    FrameTooLarge(usize),
+}
+
+/// Why a connection gets closed, sent in the disconnect packet of either family so
+/// the receiving side can react to it or show a localised message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    Kicked,
+    Banned,
+    ServerShutdown,
+    VersionMismatch,
+    AuthFailed,
+    Timeout,
+    /// The session to resume expired or never existed.
+    SessionExpired,
+}
+
+impl DisconnectReason {
+    /// Encoding in binary bodies.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Kicked => 0,
+            Self::Banned => 1,
+            Self::ServerShutdown => 2,
+            Self::VersionMismatch => 3,
+            Self::AuthFailed => 4,
+            Self::Timeout => 5,
+            Self::SessionExpired => 6,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<Self, PacketDecodeError> {
+        match code {
+            0 => Ok(Self::Kicked),
+            1 => Ok(Self::Banned),
+            2 => Ok(Self::ServerShutdown),
+            3 => Ok(Self::VersionMismatch),
+            4 => Ok(Self::AuthFailed),
+            5 => Ok(Self::Timeout),
+            6 => Ok(Self::SessionExpired),
+            _ => Err(PacketDecodeError::InvalidNumber),
+        }
+    }
 }
 
 pub trait PacketTrait: Sized {
-    fn from_raw(sig_a: char, sig_b: char, packet_data: Vec<u8>) -> Result<Self, PacketDecodeError>;
+    fn from_raw(sig_a: char, sig_b: char, packet_data: &[u8]) -> Result<Self, PacketDecodeError>;
     fn to_raw(&self) -> Result<(char, char, Vec<u8>), PacketEncodeError>;
+
+    /// Size of the whole frame [`encode_into`](Self::encode_into) writes, header included.
+    fn encoded_len(&self) -> usize;
+
+    /// Writes the whole frame, header included, straight into `buf`.
+    /// On error `buf` may be left holding a partial frame.
+    fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError>;
 }
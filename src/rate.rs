@@ -0,0 +1,322 @@
+//! Throttling gameplay traffic to what the path to a peer can actually carry.
+//!
+//! The protocol has no acknowledgements of its own for UDP, so the caller reports
+//! which bytes made it (or didn't) through [`RateController::on_ack`] and
+//! [`RateController::on_loss`], e.g. based on the `ms` echoed in vehicle updates.
+//! The send rate grows additively while nothing gets lost and backs off
+//! multiplicatively once loss crosses a threshold.
+
+use crate::connection::UdpClient;
+use crate::*;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateConfig {
+    /// Bytes per second to start out with.
+    pub initial_rate: f64,
+    pub min_rate: f64,
+    pub max_rate: f64,
+    /// Bytes per second added after every interval without significant loss.
+    pub increase: f64,
+    /// Fraction of lost bytes in an interval above which the rate backs off.
+    pub loss_threshold: f64,
+    /// How often the rate gets adjusted.
+    pub interval: Duration,
+    /// How much unused budget may pile up, as time at the current rate.
+    pub burst: Duration,
+}
+
+impl Default for RateConfig {
+    fn default() -> Self {
+        Self {
+            initial_rate: 64.0 * 1024.0,
+            min_rate: 8.0 * 1024.0,
+            max_rate: 1024.0 * 1024.0,
+            increase: 8.0 * 1024.0,
+            loss_threshold: 0.05,
+            interval: Duration::from_millis(250),
+            burst: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Estimates bandwidth and loss towards a single peer and hands out a
+/// token bucket budget accordingly.
+#[derive(Debug)]
+pub struct RateController {
+    config: RateConfig,
+    rate: f64,
+    /// Highest rate reached so far.
+    peak_rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+    interval_start: Instant,
+    acked: usize,
+    lost: usize,
+    /// Smoothed fraction of bytes lost.
+    loss: f64,
+    /// Smoothed acknowledged bytes per second.
+    bandwidth: f64,
+}
+
+impl RateController {
+    pub fn new(config: RateConfig, now: Instant) -> Self {
+        let rate = config.initial_rate.clamp(config.min_rate, config.max_rate);
+        Self {
+            config,
+            rate,
+            peak_rate: rate,
+            tokens: rate * config.burst.as_secs_f64(),
+            last_refill: now,
+            interval_start: now,
+            acked: 0,
+            lost: 0,
+            loss: 0.0,
+            bandwidth: 0.0,
+        }
+    }
+
+    /// Current send rate in bytes per second.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    /// Acknowledged bytes per second, as far as the caller reported them.
+    pub fn estimated_bandwidth(&self) -> f64 {
+        self.bandwidth
+    }
+
+    /// How far the rate backed off from the highest it reached, `1.0` while it isn't.
+    pub fn congestion(&self) -> f64 {
+        self.peak_rate / self.rate
+    }
+
+    pub fn on_ack(&mut self, bytes: usize, now: Instant) {
+        self.acked += bytes;
+        self.update(now);
+    }
+
+    pub fn on_loss(&mut self, bytes: usize, now: Instant) {
+        self.lost += bytes;
+        self.update(now);
+    }
+
+    /// Adjusts the rate once per interval, called by everything taking a `now`.
+    pub fn update(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.interval_start);
+        if elapsed < self.config.interval {
+            return;
+        }
+        let total = self.acked + self.lost;
+        if total > 0 {
+            let loss = self.lost as f64 / total as f64;
+            self.loss = 0.75 * self.loss + 0.25 * loss;
+            let bandwidth = self.acked as f64 / elapsed.as_secs_f64();
+            self.bandwidth = 0.75 * self.bandwidth + 0.25 * bandwidth;
+
+            if loss > self.config.loss_threshold {
+                self.rate *= (1.0 - loss).max(0.5);
+            } else {
+                self.rate += self.config.increase;
+            }
+            self.rate = self.rate.clamp(self.config.min_rate, self.config.max_rate);
+            self.peak_rate = self.peak_rate.max(self.rate);
+        }
+        self.acked = 0;
+        self.lost = 0;
+        self.interval_start = now;
+    }
+
+    /// Most budget that may pile up at the current rate.
+    fn capacity(&self) -> f64 {
+        self.rate * self.config.burst.as_secs_f64()
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + self.rate * elapsed).min(self.capacity());
+        self.last_refill = now;
+    }
+
+    /// Bytes that may be sent right now.
+    pub fn budget(&mut self, now: Instant) -> usize {
+        self.update(now);
+        self.refill(now);
+        self.tokens.max(0.0) as usize
+    }
+
+    /// Takes `bytes` out of the budget if there's enough left. More than the
+    /// budget can ever hold still goes once it's full, and the debt is paid off
+    /// by later refills.
+    pub fn try_consume(&mut self, bytes: usize, now: Instant) -> bool {
+        if self.budget(now) < bytes && self.tokens < self.capacity() {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+}
+
+/// How often a vehicle gets updated, depending on how far away it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateSchedule {
+    /// Interval for a vehicle right next to the receiver on an uncongested path.
+    pub base_interval: Duration,
+    /// Every this many metres the interval grows by another `base_interval`.
+    /// Has to be positive and finite.
+    pub falloff_distance: f32,
+    /// Upper bound for the interval, no matter the distance or congestion.
+    pub max_interval: Duration,
+}
+
+impl Default for UpdateSchedule {
+    fn default() -> Self {
+        Self {
+            base_interval: Duration::from_millis(33),
+            falloff_distance: 100.0,
+            max_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl UpdateSchedule {
+    /// `congestion` as returned by [`RateController::congestion`].
+    /// Vehicles at an unknown (non-finite) distance get `max_interval`.
+    pub fn interval(&self, distance: f32, congestion: f64) -> Duration {
+        if !distance.is_finite() {
+            return self.max_interval;
+        }
+        let distance_factor = 1.0 + (distance.max(0.0) / self.falloff_distance) as f64;
+        let secs = self.base_interval.as_secs_f64() * distance_factor * congestion.max(1.0);
+        Duration::try_from_secs_f64(secs).map_or(self.max_interval, |interval| {
+            interval.min(self.max_interval)
+        })
+    }
+}
+
+struct Vehicle<T> {
+    /// Latest state that hasn't been sent yet, older ones are simply replaced.
+    packet: Option<T>,
+    distance: f32,
+    last_sent: Option<Instant>,
+}
+
+/// Sends the latest state of every vehicle through a [`UdpClient`], nearby ones
+/// more often than distant ones, without going over the rate the path allows.
+/// `K` identifies a vehicle, e.g. `(player_id, vehicle_id)`.
+pub struct RateLimitedClient<T: PacketTrait, K> {
+    client: UdpClient<T>,
+    controller: RateController,
+    schedule: UpdateSchedule,
+    vehicles: HashMap<K, Vehicle<T>>,
+}
+
+impl<T: PacketTrait, K: Hash + Eq + Clone> RateLimitedClient<T, K> {
+    /// Fails if the schedule's `falloff_distance` isn't a positive, finite number.
+    pub fn new(
+        client: UdpClient<T>,
+        config: RateConfig,
+        schedule: UpdateSchedule,
+    ) -> Result<Self, ConfigError> {
+        if !(schedule.falloff_distance.is_finite() && schedule.falloff_distance > 0.0) {
+            return Err(ConfigError::InvalidFalloffDistance(
+                schedule.falloff_distance,
+            ));
+        }
+        Ok(Self {
+            client,
+            controller: RateController::new(config, Instant::now()),
+            schedule,
+            vehicles: HashMap::new(),
+        })
+    }
+
+    pub fn client(&self) -> &UdpClient<T> {
+        &self.client
+    }
+
+    /// For receiving, or sending packets that shouldn't be throttled.
+    pub fn client_mut(&mut self) -> &mut UdpClient<T> {
+        &mut self.client
+    }
+
+    pub fn controller(&self) -> &RateController {
+        &self.controller
+    }
+
+    pub fn controller_mut(&mut self) -> &mut RateController {
+        &mut self.controller
+    }
+
+    pub fn into_inner(self) -> UdpClient<T> {
+        self.client
+    }
+
+    /// Replaces whatever state of the vehicle hasn't been sent yet.
+    /// `distance` is from the receiver's point of view, in metres.
+    pub fn update_vehicle(&mut self, key: K, packet: T, distance: f32) {
+        let vehicle = self.vehicles.entry(key).or_insert(Vehicle {
+            packet: None,
+            distance,
+            last_sent: None,
+        });
+        vehicle.packet = Some(packet);
+        vehicle.distance = distance;
+    }
+
+    pub fn remove_vehicle(&mut self, key: &K) {
+        self.vehicles.remove(key);
+    }
+
+    /// Sends every vehicle that is due, nearest and most overdue first, as far as
+    /// the budget goes. Whatever doesn't fit waits for the next tick.
+    /// Returns the number of vehicles updated.
+    pub async fn tick(&mut self, now: Instant) -> anyhow::Result<usize> {
+        let congestion = self.controller.congestion();
+        let mut due: Vec<(f64, f32, K)> = Vec::new();
+        for (key, vehicle) in &self.vehicles {
+            if vehicle.packet.is_none() {
+                continue;
+            }
+            let interval = self.schedule.interval(vehicle.distance, congestion);
+            let overdue = match vehicle.last_sent {
+                Some(last_sent) => {
+                    now.saturating_duration_since(last_sent).as_secs_f64() / interval.as_secs_f64()
+                }
+                // Never sent, so as urgent as it gets
+                None => f64::INFINITY,
+            };
+            if overdue >= 1.0 {
+                let priority = overdue / (1.0 + (vehicle.distance.max(0.0) as f64));
+                due.push((priority, vehicle.distance, key.clone()));
+            }
+        }
+        due.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.total_cmp(&b.1)));
+
+        let mut sent = 0;
+        for (_, _, key) in due {
+            let vehicle = self.vehicles.get_mut(&key).unwrap();
+            let packet = vehicle.packet.as_ref().unwrap();
+            if !self.controller.try_consume(packet.encoded_len(), now) {
+                continue;
+            }
+            self.client.queue_packet(packet)?;
+            vehicle.packet = None;
+            vehicle.last_sent = Some(now);
+            sent += 1;
+        }
+        if sent > 0 {
+            self.client.flush().await?;
+        }
+        Ok(sent)
+    }
+}
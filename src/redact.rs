@@ -0,0 +1,21 @@
+//! Keeping secrets like session tokens and keys out of logs and dumps.
+
+/// Implements `Debug` as `Name(..)` and `Serialize` as `".."` for a secret, so it
+/// shows up in neither logs nor dumps like `ngmp-dump --json`.
+macro_rules! redacted {
+    ($name:ident) => {
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(concat!(stringify!($name), "(..)"))
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str("..")
+            }
+        }
+    };
+}
+
+pub(crate) use redacted;
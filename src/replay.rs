@@ -0,0 +1,118 @@
+//! Replaying captured traffic against a live connection.
+
+use crate::capture::{CaptureReader, Direction, RecordDecoder, Transport};
+use crate::connection::{TcpConnection, UdpClient};
+use crate::*;
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+
+/// Longest wait between two packets, so absurd speed factors can't overflow the deadline.
+const MAX_OFFSET: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the gaps between packets exactly as they were recorded.
+    Realtime,
+    /// Divide the recorded gaps by the given factor, `2.0` replays twice as fast.
+    Scaled(f64),
+    /// Send everything back to back without waiting.
+    Instant,
+}
+
+/// Re-emits recorded packets with their original timing.
+pub struct Replayer<T: PacketTrait> {
+    packets: VecDeque<(u64, T)>,
+    speed: ReplaySpeed,
+    first_timestamp_us: Option<u64>,
+    started: Option<Instant>,
+}
+
+impl<T: PacketTrait> Replayer<T> {
+    /// Takes `(timestamp in microseconds, packet)` pairs in the order they should be sent.
+    pub fn new<I: IntoIterator<Item = (u64, T)>>(packets: I, speed: ReplaySpeed) -> Self {
+        let packets: VecDeque<(u64, T)> = packets.into_iter().collect();
+        Self {
+            first_timestamp_us: packets.front().map(|(ts, _)| *ts),
+            packets,
+            speed,
+            started: None,
+        }
+    }
+
+    /// Loads the packets of all records matching `direction` and `transport` from a capture,
+    /// with fragmented packets put back together and heartbeats left out.
+    /// Fails if the capture is corrupt or any of the matching frames can't be decoded.
+    pub fn from_capture<R: Read>(
+        reader: CaptureReader<R>,
+        direction: Direction,
+        transport: Transport,
+        speed: ReplaySpeed,
+    ) -> Result<Self, CaptureError> {
+        let mut decoder = RecordDecoder::new();
+        let mut packets = Vec::new();
+        for record in reader {
+            let record = record?;
+            if record.direction != direction || record.transport != transport {
+                continue;
+            }
+            for packet in decoder.decode::<T>(&record)? {
+                packets.push((record.timestamp_us, packet));
+            }
+        }
+        Ok(Self::new(packets, speed))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Waits until the next packet is due and returns it.
+    /// The schedule is anchored to the first call, so slow sends don't accumulate drift.
+    pub async fn next_packet(&mut self) -> Option<T> {
+        let (timestamp_us, _) = self.packets.front()?;
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let offset_us = timestamp_us.saturating_sub(self.first_timestamp_us.unwrap_or(0));
+
+        let offset = match self.speed {
+            ReplaySpeed::Realtime => Some(Duration::from_micros(offset_us).min(MAX_OFFSET)),
+            ReplaySpeed::Scaled(factor) if factor > 0.0 => Some(
+                Duration::try_from_secs_f64(offset_us as f64 / 1_000_000.0 / factor)
+                    .map_or(MAX_OFFSET, |offset| offset.min(MAX_OFFSET)),
+            ),
+            ReplaySpeed::Scaled(_) | ReplaySpeed::Instant => None,
+        };
+        if let Some(offset) = offset {
+            tokio::time::sleep_until(started + offset).await;
+        }
+
+        self.packets.pop_front().map(|(_, packet)| packet)
+    }
+
+    /// Sends every remaining packet over `conn`, returns how many were sent.
+    pub async fn replay_tcp<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        conn: &mut TcpConnection<T, S>,
+    ) -> anyhow::Result<usize> {
+        let mut sent = 0;
+        while let Some(packet) = self.next_packet().await {
+            conn.write_packet(&packet).await?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Sends every remaining packet over `conn`, returns how many were sent.
+    pub async fn replay_udp(&mut self, conn: &mut UdpClient<T>) -> anyhow::Result<usize> {
+        let mut sent = 0;
+        while let Some(packet) = self.next_packet().await {
+            conn.write_packet(packet).await?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
@@ -0,0 +1,633 @@
+//! Static description of the wire layout of every packet.
+//!
+//! This mirrors the `from_raw`/`to_raw` implementations in [`crate::server_launcher`]
+//! and [`crate::launcher_client`] and has to be kept in sync with them by hand.
+//! [`export`] turns it into JSON for implementations outside of this crate.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    /// UTF-8 string. In binary bodies it's the last field and runs until the end of
+    /// the packet, see [`crate::server_launcher::wire`].
+    String,
+    /// Fixed length array.
+    Array {
+        of: &'static FieldType,
+        len: usize,
+    },
+    /// Variable length list.
+    List {
+        of: &'static FieldType,
+    },
+    Object {
+        fields: &'static [FieldDef],
+    },
+    /// One of a fixed set of values, sent as its code if `repr` is a number and as
+    /// its name if it's a string.
+    Enum {
+        repr: &'static FieldType,
+        values: &'static [EnumValue],
+    },
+}
+
+impl FieldType {
+    /// Size in bytes when encoded in a binary body, `None` if it isn't fixed.
+    pub fn binary_size(&self) -> Option<usize> {
+        match self {
+            Self::Bool | Self::U8 => Some(1),
+            Self::U16 => Some(2),
+            Self::U32 | Self::F32 => Some(4),
+            Self::U64 => Some(8),
+            Self::Array { of, len } => of.binary_size().map(|size| size * len),
+            Self::Enum { repr, .. } => repr.binary_size(),
+            Self::String | Self::List { .. } | Self::Object { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct EnumValue {
+    pub name: &'static str,
+    pub code: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct FieldDef {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub ty: FieldType,
+    /// May be left out of a JSON body.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "encoding", content = "fields", rename_all = "snake_case")]
+pub enum Body {
+    Empty,
+    /// Little endian fields back to back, in order.
+    Binary(&'static [FieldDef]),
+    /// A JSON object with the given keys.
+    Json(&'static [FieldDef]),
+}
+
+/// Who sends a packet, relative to the two ends of its packet family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    ToServer,
+    ToLauncher,
+    ToClient,
+    Both,
+}
+
+/// Which connection a packet is sent over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Tcp,
+    Udp,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketDef {
+    pub sig_a: char,
+    pub sig_b: char,
+    /// Name of the `Packet` enum variant.
+    pub name: &'static str,
+    pub direction: Direction,
+    pub transport: Transport,
+    pub body: Body,
+}
+
+impl Serialize for PacketDef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PacketDef", 5)?;
+        state.serialize_field("signature", &format!("{}{}", self.sig_a, self.sig_b))?;
+        state.serialize_field("name", self.name)?;
+        state.serialize_field("direction", &self.direction)?;
+        state.serialize_field("transport", &self.transport)?;
+        state.serialize_field("body", &self.body)?;
+        state.end()
+    }
+}
+
+const fn field(name: &'static str, ty: FieldType) -> FieldDef {
+    FieldDef {
+        name,
+        ty,
+        optional: false,
+    }
+}
+
+const fn optional(name: &'static str, ty: FieldType) -> FieldDef {
+    FieldDef {
+        name,
+        ty,
+        optional: true,
+    }
+}
+
+const fn value(name: &'static str, code: u8) -> EnumValue {
+    EnumValue { name, code }
+}
+
+/// Every [`DisconnectReason`](crate::DisconnectReason) with its JSON name and binary code.
+pub const DISCONNECT_REASONS: &[EnumValue] = &[
+    value("kicked", 0),
+    value("banned", 1),
+    value("server_shutdown", 2),
+    value("version_mismatch", 3),
+    value("auth_failed", 4),
+    value("timeout", 5),
+    value("session_expired", 6),
+];
+
+const VEHICLE_DATA: FieldType = FieldType::Object {
+    fields: &[
+        field("Jbeam", FieldType::String),
+        field("object_id", FieldType::U32),
+        field("paints", FieldType::String),
+        field("partConfig", FieldType::String),
+        field(
+            "pos",
+            FieldType::Array {
+                of: &FieldType::F32,
+                len: 3,
+            },
+        ),
+        field(
+            "rot",
+            FieldType::Array {
+                of: &FieldType::F32,
+                len: 4,
+            },
+        ),
+    ],
+};
+
+pub const SERVER_LAUNCHER: &[PacketDef] = &[
+    PacketDef {
+        sig_a: 'C',
+        sig_b: 'C',
+        name: "Confirmation",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field("confirm_id", FieldType::U16)]),
+    },
+    PacketDef {
+        sig_a: 'P',
+        sig_b: 'K',
+        name: "PlayerKick",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field("reason", FieldType::String)]),
+    },
+    PacketDef {
+        sig_a: 'P',
+        sig_b: 'I',
+        name: "Ping",
+        direction: Direction::Both,
+        transport: Transport::Both,
+        body: Body::Binary(&[field("ms", FieldType::U32)]),
+    },
+    PacketDef {
+        sig_a: 'P',
+        sig_b: 'O',
+        name: "Pong",
+        direction: Direction::Both,
+        transport: Transport::Both,
+        body: Body::Binary(&[
+            field("ping_ms", FieldType::U32),
+            field("ms", FieldType::U32),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'D',
+        sig_b: 'C',
+        name: "Disconnect",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field(
+                "reason",
+                FieldType::Enum {
+                    repr: &FieldType::U8,
+                    values: DISCONNECT_REASONS,
+                },
+            ),
+            field("message", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'C',
+        name: "Version",
+        direction: Direction::ToServer,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("confirm_id", FieldType::U16),
+            field("client_version", FieldType::U16),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'A',
+        sig_b: 'C',
+        name: "Authentication",
+        direction: Direction::ToServer,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("confirm_id", FieldType::U16),
+            field("auth_code", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'R',
+        sig_b: 'T',
+        name: "ResumeToken",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field(
+            "token",
+            FieldType::Array {
+                of: &FieldType::U8,
+                len: 16,
+            },
+        )]),
+    },
+    PacketDef {
+        sig_a: 'R',
+        sig_b: 'S',
+        name: "Resume",
+        direction: Direction::ToServer,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field(
+            "token",
+            FieldType::Array {
+                of: &FieldType::U8,
+                len: 16,
+            },
+        )]),
+    },
+    PacketDef {
+        sig_a: 'C',
+        sig_b: 'P',
+        name: "Capabilities",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field("capabilities", FieldType::U32)]),
+    },
+    PacketDef {
+        sig_a: 'U',
+        sig_b: 'K',
+        name: "UdpKey",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[field(
+            "key",
+            FieldType::Array {
+                of: &FieldType::U8,
+                len: 32,
+            },
+        )]),
+    },
+    PacketDef {
+        sig_a: 'H',
+        sig_b: 'I',
+        name: "ServerInfo",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("http_port", FieldType::U16),
+            field("udp_port", FieldType::U16),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'L',
+        sig_b: 'M',
+        name: "LoadMap",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("confirm_id", FieldType::U16),
+            field("map_name", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'P',
+        sig_b: 'D',
+        name: "PlayerData",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field(
+            "players",
+            FieldType::List {
+                of: &FieldType::Object {
+                    fields: &[
+                        field("name", FieldType::String),
+                        field("steam_id", FieldType::U64),
+                        field("avatar_hash", FieldType::String),
+                    ],
+                },
+            },
+        )]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'S',
+        name: "VehicleSpawn",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("confirm_id", FieldType::U16),
+            field("steam_id", FieldType::U64),
+            field("vehicle_id", FieldType::U16),
+            field("vehicle_data", VEHICLE_DATA),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'A',
+        name: "VehicleConfirm",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("confirm_id", FieldType::U16),
+            field("vehicle_id", FieldType::U16),
+            field("obj_id", FieldType::U32),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'D',
+        name: "VehicleDelete",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Binary(&[
+            field("player_id", FieldType::U64),
+            field("vehicle_id", FieldType::U16),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'T',
+        name: "VehicleTransform",
+        direction: Direction::Both,
+        transport: Transport::Udp,
+        body: Body::Binary(&[
+            field("player_id", FieldType::U64),
+            field("vehicle_id", FieldType::U16),
+            field("transform", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'U',
+        name: "VehicleUpdate",
+        direction: Direction::Both,
+        transport: Transport::Udp,
+        body: Body::Binary(&[
+            field("player_id", FieldType::U64),
+            field("vehicle_id", FieldType::U16),
+            field("ms", FieldType::U32),
+            field("runtime_data", FieldType::String),
+        ]),
+    },
+];
+
+pub const LAUNCHER_CLIENT: &[PacketDef] = &[
+    PacketDef {
+        sig_a: 'R',
+        sig_b: 'L',
+        name: "ReloadLauncherConnection",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Empty,
+    },
+    PacketDef {
+        sig_a: 'C',
+        sig_b: 'C',
+        name: "Confirmation",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field("confirm_id", FieldType::U16)]),
+    },
+    PacketDef {
+        sig_a: 'C',
+        sig_b: 'E',
+        name: "ConnectionError",
+        direction: Direction::ToClient,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field("error", FieldType::String)]),
+    },
+    PacketDef {
+        sig_a: 'D',
+        sig_b: 'C',
+        name: "Disconnect",
+        direction: Direction::ToClient,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field(
+                "reason",
+                FieldType::Enum {
+                    repr: &FieldType::String,
+                    values: DISCONNECT_REASONS,
+                },
+            ),
+            optional("message", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'C',
+        name: "Version",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field("protocol_version", FieldType::U16)]),
+    },
+    PacketDef {
+        sig_a: 'C',
+        sig_b: 'I',
+        name: "ClientInfo",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("userfolder", FieldType::String),
+            field("client_version", FieldType::U16),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'A',
+        sig_b: 'I',
+        name: "AuthenticationInfo",
+        direction: Direction::ToClient,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("success", FieldType::Bool),
+            field("player_name", FieldType::String),
+            field("steam_id", FieldType::String),
+            field("avatar_hash", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'L',
+        sig_b: 'R',
+        name: "LoginRequest",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Empty,
+    },
+    PacketDef {
+        sig_a: 'H',
+        sig_b: 'J',
+        name: "JoinServer",
+        direction: Direction::ToLauncher,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field("ip_address", FieldType::String)]),
+    },
+    PacketDef {
+        sig_a: 'L',
+        sig_b: 'M',
+        name: "LoadMap",
+        direction: Direction::ToClient,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("confirm_id", FieldType::U16),
+            field("map_string", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'P',
+        sig_b: 'D',
+        name: "PlayerData",
+        direction: Direction::ToClient,
+        transport: Transport::Tcp,
+        body: Body::Json(&[field(
+            "players",
+            FieldType::List {
+                of: &FieldType::Object {
+                    fields: &[
+                        field("name", FieldType::String),
+                        field("steam_id", FieldType::String),
+                        field("avatar_hash", FieldType::String),
+                    ],
+                },
+            },
+        )]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'S',
+        name: "VehicleSpawn",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("confirm_id", FieldType::U16),
+            field("steam_id", FieldType::String),
+            field("vehicle_id", FieldType::U16),
+            field("vehicle_data", VEHICLE_DATA),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'A',
+        name: "VehicleConfirm",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("confirm_id", FieldType::U16),
+            field("vehicle_id", FieldType::U16),
+            field("object_id", FieldType::U32),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'D',
+        name: "VehicleDelete",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("steam_id", FieldType::String),
+            field("vehicle_id", FieldType::U16),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'T',
+        name: "VehicleTransform",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("steam_id", FieldType::String),
+            field("vehicle_id", FieldType::U16),
+            field("transform", FieldType::String),
+        ]),
+    },
+    PacketDef {
+        sig_a: 'V',
+        sig_b: 'U',
+        name: "VehicleUpdate",
+        direction: Direction::Both,
+        transport: Transport::Tcp,
+        body: Body::Json(&[
+            field("steam_id", FieldType::String),
+            field("vehicle_id", FieldType::U16),
+            field("runtime_data", FieldType::String),
+        ]),
+    },
+];
+
+/// The full schema of both packet families as JSON.
+pub fn export() -> serde_json::Value {
+    serde_json::json!({
+        "framing": {
+            "description": "every packet is a 2 byte ASCII signature, followed by the body length as a little endian u32 and the body itself",
+            "header": [
+                { "name": "signature", "type": "string", "len": 2 },
+                { "name": "length", "type": "u32" },
+            ],
+            "byte_order": "little_endian",
+            "heartbeat": {
+                "description": "empty frame sent on idle connections, both TCP and UDP, to tell the peer it's still alive",
+                "signature": "HB",
+            },
+            "fragment": {
+                "description": "frames bigger than the UDP MTU are sent as a series of FG frames, each carrying a chunk of the original frame",
+                "signature": "FG",
+                "header": [
+                    { "name": "message_id", "type": "u32" },
+                    { "name": "index", "type": "u16" },
+                    { "name": "count", "type": "u16" },
+                ],
+            },
+            "encrypted": {
+                "description": "once a UDP key was exchanged every UDP frame is sealed with ChaCha20-Poly1305 into an EN frame; the nonce is the direction (0 towards the server, 1 towards the launcher), 3 zero bytes and the sequence number, the EN header is the associated data",
+                "signature": "EN",
+                "header": [
+                    { "name": "sequence", "type": "u64" },
+                ],
+                "trailer": [
+                    { "name": "tag", "type": "u8", "len": 16 },
+                ],
+            },
+        },
+        "families": {
+            "server_launcher": SERVER_LAUNCHER,
+            "launcher_client": LAUNCHER_CLIENT,
+        },
+    })
+}
+
+pub fn find(defs: &'static [PacketDef], sig_a: char, sig_b: char) -> Option<&'static PacketDef> {
+    defs.iter()
+        .find(|def| def.sig_a == sig_a && def.sig_b == sig_b)
+}
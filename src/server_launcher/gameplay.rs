@@ -1,36 +1,45 @@
+use super::wire;
 use super::{PacketDecodeError, PacketEncodeError};
+use crate::json;
 
+use bytes::BufMut;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerData {
     pub name: String,
     pub steam_id: u64,
     pub avatar_hash: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerDataPacket {
     pub players: Vec<PlayerData>,
 }
 
 impl PlayerDataPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let raw = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let raw = std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
 
-        Ok(serde_json::from_str(&raw)
-            .map_err(|e| PacketDecodeError::InvalidJson("PlayerDataPacket", e))?)
+        serde_json::from_str(raw).map_err(|e| PacketDecodeError::InvalidJson("PlayerDataPacket", e))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
     }
 
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        Ok(serde_json::to_string(&self)
-            .map_err(|_| PacketEncodeError::CannotSerializeJson)?
-            .as_bytes()
-            .to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleData {
     #[serde(rename = "Jbeam")]
     pub jbeam: String,
@@ -42,7 +51,7 @@ pub struct VehicleData {
     pub rot: [f32; 4],
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct VehicleSpawnPacket {
     pub confirm_id: u16,
     pub steam_id: u64,
@@ -51,20 +60,29 @@ pub struct VehicleSpawnPacket {
 }
 
 impl VehicleSpawnPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let json = String::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
-        serde_json::from_str(&json)
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let json =
+            std::str::from_utf8(packet_data).map_err(|_| PacketDecodeError::InvalidString)?;
+        serde_json::from_str(json)
             .map_err(|e| PacketDecodeError::InvalidJson("VehicleSpawnPacket", e))
     }
 
+    pub fn encoded_len(&self) -> usize {
+        json::encoded_len(self)
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        json::encode_into(buf, self)
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let json =
-            serde_json::to_string(&self).map_err(|_| PacketEncodeError::CannotSerializeJson)?;
-        Ok(json.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct VehicleConfirmPacket {
     pub confirm_id: u16,
     pub vehicle_id: u16,
@@ -72,8 +90,8 @@ pub struct VehicleConfirmPacket {
 }
 
 impl VehicleConfirmPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let mut pd = packet_data.into_iter();
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let mut pd = packet_data.iter().copied();
         let confirm_id = u16::from_le_bytes([
             pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
             pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
@@ -95,23 +113,33 @@ impl VehicleConfirmPacket {
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        8
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.confirm_id);
+        buf.put_u16_le(self.vehicle_id);
+        buf.put_u32_le(self.obj_id);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut bytes = self.confirm_id.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.vehicle_id.to_le_bytes());
-        bytes.extend_from_slice(&self.obj_id.to_le_bytes());
-        Ok(bytes)
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct VehicleDeletePacket {
     pub player_id: u64,
     pub vehicle_id: u16,
 }
 
 impl VehicleDeletePacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let mut pd = packet_data.into_iter();
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let mut pd = packet_data.iter().copied();
         let player_id = u64::from_le_bytes([
             pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
             pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
@@ -132,14 +160,52 @@ impl VehicleDeletePacket {
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        10
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u64_le(self.player_id);
+        buf.put_u16_le(self.vehicle_id);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut bytes = self.player_id.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.vehicle_id.to_le_bytes());
-        Ok(bytes)
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug)]
+/// The parts of a vehicle transform the protocol cares about, the game may send more.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub pos: [f32; 3],
+    #[serde(default)]
+    pub rot: [f32; 4],
+    #[serde(default)]
+    pub vel: [f32; 3],
+}
+
+impl Transform {
+    /// Fails with [`PacketDecodeError::InvalidNumber`] if any component isn't finite,
+    /// e.g. a number too large for an `f32`.
+    pub fn parse(transform: &str) -> Result<Self, PacketDecodeError> {
+        let transform: Self = serde_json::from_str(transform)
+            .map_err(|e| PacketDecodeError::InvalidJson("Transform", e))?;
+        let components = transform
+            .pos
+            .iter()
+            .chain(&transform.rot)
+            .chain(&transform.vel);
+        if !components.into_iter().all(|c| c.is_finite()) {
+            return Err(PacketDecodeError::InvalidNumber);
+        }
+        Ok(transform)
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct VehicleTransformPacket {
     pub player_id: u64,
     pub vehicle_id: u16,
@@ -147,24 +213,48 @@ pub struct VehicleTransformPacket {
 }
 
 impl VehicleTransformPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let mut pd = packet_data.into_iter();
-        let player_id = u64::from_le_bytes([
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-        ]);
-        let vehicle_id = u16::from_le_bytes([
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-        ]);
-        let transform = String::from_utf8(pd.collect::<Vec<u8>>())
-            .map_err(|_| PacketDecodeError::InvalidString)?;
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        Ok(VehicleTransformPacketRef::from_raw(packet_data)?.into_owned())
+    }
+
+    pub fn parse_transform(&self) -> Result<Transform, PacketDecodeError> {
+        Transform::parse(&self.transform)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        10 + self.transform.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u64_le(self.player_id);
+        buf.put_u16_le(self.vehicle_id);
+        wire::write_trailing_str(buf, &self.transform);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// [`VehicleTransformPacket`] borrowing its transform from the receive buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VehicleTransformPacketRef<'a> {
+    pub player_id: u64,
+    pub vehicle_id: u16,
+    pub transform: &'a str,
+}
+
+impl<'a> VehicleTransformPacketRef<'a> {
+    pub fn from_raw(packet_data: &'a [u8]) -> Result<Self, PacketDecodeError> {
+        if packet_data.len() < 10 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        let player_id = u64::from_le_bytes(packet_data[0..8].try_into().unwrap());
+        let vehicle_id = u16::from_le_bytes([packet_data[8], packet_data[9]]);
+        let transform = wire::read_trailing_str_ref(&packet_data[10..])?;
         Ok(Self {
             player_id,
             vehicle_id,
@@ -172,15 +262,20 @@ impl VehicleTransformPacket {
         })
     }
 
-    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut bytes = self.player_id.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.vehicle_id.to_le_bytes());
-        bytes.extend_from_slice(&self.transform.as_bytes());
-        Ok(bytes)
+    pub fn parse_transform(&self) -> Result<Transform, PacketDecodeError> {
+        Transform::parse(self.transform)
+    }
+
+    pub fn into_owned(self) -> VehicleTransformPacket {
+        VehicleTransformPacket {
+            player_id: self.player_id,
+            vehicle_id: self.vehicle_id,
+            transform: self.transform.to_string(),
+        }
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
 pub struct VehicleUpdatePacket {
     pub player_id: u64,
     pub vehicle_id: u16,
@@ -189,30 +284,47 @@ pub struct VehicleUpdatePacket {
 }
 
 impl VehicleUpdatePacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let mut pd = packet_data.into_iter();
-        let player_id = u64::from_le_bytes([
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-        ]);
-        let vehicle_id = u16::from_le_bytes([
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-        ]);
-        let ms = u32::from_le_bytes([
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-            pd.next().ok_or(PacketDecodeError::UnexpectedEof)?,
-        ]);
-        let runtime_data = String::from_utf8(pd.collect::<Vec<u8>>())
-            .map_err(|_| PacketDecodeError::InvalidString)?;
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        Ok(VehicleUpdatePacketRef::from_raw(packet_data)?.into_owned())
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        14 + self.runtime_data.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u64_le(self.player_id);
+        buf.put_u16_le(self.vehicle_id);
+        buf.put_u32_le(self.ms);
+        wire::write_trailing_str(buf, &self.runtime_data);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// [`VehicleUpdatePacket`] borrowing its runtime data from the receive buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VehicleUpdatePacketRef<'a> {
+    pub player_id: u64,
+    pub vehicle_id: u16,
+    pub ms: u32,
+    pub runtime_data: &'a str,
+}
+
+impl<'a> VehicleUpdatePacketRef<'a> {
+    pub fn from_raw(packet_data: &'a [u8]) -> Result<Self, PacketDecodeError> {
+        if packet_data.len() < 14 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        let player_id = u64::from_le_bytes(packet_data[0..8].try_into().unwrap());
+        let vehicle_id = u16::from_le_bytes([packet_data[8], packet_data[9]]);
+        let ms = u32::from_le_bytes(packet_data[10..14].try_into().unwrap());
+        let runtime_data = wire::read_trailing_str_ref(&packet_data[14..])?;
         Ok(Self {
             player_id,
             vehicle_id,
@@ -221,11 +333,12 @@ impl VehicleUpdatePacket {
         })
     }
 
-    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut bytes = self.player_id.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.vehicle_id.to_le_bytes());
-        bytes.extend_from_slice(&self.ms.to_le_bytes());
-        bytes.extend_from_slice(&self.runtime_data.as_bytes());
-        Ok(bytes)
+    pub fn into_owned(self) -> VehicleUpdatePacket {
+        VehicleUpdatePacket {
+            player_id: self.player_id,
+            vehicle_id: self.vehicle_id,
+            ms: self.ms,
+            runtime_data: self.runtime_data.to_string(),
+        }
     }
 }
@@ -1,12 +1,15 @@
-use super::{PacketDecodeError, PacketEncodeError};
+use super::wire;
+use super::{DisconnectReason, PacketDecodeError, PacketEncodeError};
+use bytes::BufMut;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct ConfirmationPacket {
     pub confirm_id: u16,
 }
 
 impl ConfirmationPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let data_len = packet_data.len();
         if data_len != 2 { return Err(PacketDecodeError::InvalidDataSize { expected: 2, actual: data_len }); }
         let confirm_id = u16::from_le_bytes([packet_data[0], packet_data[1]]);
@@ -15,25 +18,161 @@ impl ConfirmationPacket {
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        2
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.confirm_id);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        Ok(self.confirm_id.to_le_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct PlayerKickPacket {
     pub reason: String,
 }
 
 impl PlayerKickPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        let reason = String::from_utf8(packet_data).map_err(|e| PacketDecodeError::InvalidString)?;
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let reason = wire::read_trailing_str(packet_data)?;
         Ok(Self {
             reason,
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        self.reason.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        wire::write_trailing_str(buf, &self.reason);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Sent over either transport to measure the round trip time and the offset between
+/// both clocks. `ms` is the sender's clock, in the same unit as [`super::gameplay::VehicleUpdatePacket::ms`].
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PingPacket {
+    pub ms: u32,
+}
+
+impl PingPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let data_len = packet_data.len();
+        if data_len != 4 { return Err(PacketDecodeError::InvalidDataSize { expected: 4, actual: data_len }); }
+        let ms = u32::from_le_bytes(packet_data.try_into().unwrap());
+        Ok(Self {
+            ms,
+        })
+    }
+
+    /// The pong to answer this ping with, `ms` being the responder's clock.
+    pub fn reply(&self, ms: u32) -> PongPacket {
+        PongPacket {
+            ping_ms: self.ms,
+            ms,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        4
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u32_le(self.ms);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PongPacket {
+    /// Echoed from the ping.
+    pub ping_ms: u32,
+    /// The responder's clock when it replied.
+    pub ms: u32,
+}
+
+impl PongPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let data_len = packet_data.len();
+        if data_len != 8 { return Err(PacketDecodeError::InvalidDataSize { expected: 8, actual: data_len }); }
+        let ping_ms = u32::from_le_bytes(packet_data[0..4].try_into().unwrap());
+        let ms = u32::from_le_bytes(packet_data[4..8].try_into().unwrap());
+        Ok(Self {
+            ping_ms,
+            ms,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        8
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u32_le(self.ping_ms);
+        buf.put_u32_le(self.ms);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Sent right before closing the connection.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DisconnectPacket {
+    pub reason: DisconnectReason,
+    /// Extra detail for the player, may be empty.
+    pub message: String,
+}
+
+impl DisconnectPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let (&code, message) = packet_data.split_first().ok_or(PacketDecodeError::UnexpectedEof)?;
+        let reason = DisconnectReason::from_code(code)?;
+        let message = wire::read_trailing_str(message)?;
+        Ok(Self {
+            reason,
+            message,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        1 + self.message.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u8(self.reason.code());
+        wire::write_trailing_str(buf, &self.message);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        Ok(self.reason.as_bytes().to_vec())
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
@@ -1,13 +1,18 @@
+use super::wire;
 use super::{PacketDecodeError, PacketEncodeError};
+use crate::crypto::UdpKey;
+use crate::session::SessionToken;
+use bytes::BufMut;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct VersionPacket {
     pub confirm_id: u16,
     pub client_version: u16
 }
 
 impl VersionPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let data_len = packet_data.len();
         if data_len != 4 { return Err(PacketDecodeError::InvalidDataSize { expected: 4, actual: data_len }); }
         let confirm_id = u16::from_le_bytes([packet_data[0], packet_data[1]]);
@@ -18,42 +23,212 @@ impl VersionPacket {
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        4
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.confirm_id);
+        buf.put_u16_le(self.client_version);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut buf = Vec::with_capacity(4);
-        buf.append(&mut self.confirm_id.to_le_bytes().to_vec());
-        buf.append(&mut self.client_version.to_le_bytes().to_vec());
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
         Ok(buf)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct AuthenticationPacket {
     pub confirm_id: u16,
+    /// May be empty, the body is then just the confirm id.
     pub auth_code: String,
 }
 
 impl AuthenticationPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let data_len = packet_data.len();
-        if data_len < 3 { return Err(PacketDecodeError::InvalidDataSize { expected: 3, actual: data_len }); }
+        if data_len < 2 { return Err(PacketDecodeError::InvalidDataSize { expected: 2, actual: data_len }); }
         let confirm_id = u16::from_le_bytes([packet_data[0], packet_data[1]]);
-        // TODO: String::with_capacity for 1 single big allocation for extra performance?
-        let mut auth_code = String::new();
-        for i in 2..packet_data.len() {
-            auth_code.push(packet_data[i] as char);
-        }
+        let auth_code = wire::read_trailing_str(&packet_data[2..])?;
         Ok(Self {
             confirm_id,
             auth_code,
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        2 + self.auth_code.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.confirm_id);
+        wire::write_trailing_str(buf, &self.auth_code);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Sent by the server once the handshake is done, and again after every successful
+/// resume. The launcher presents the token to pick up the session after a reconnect.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ResumeTokenPacket {
+    pub token: SessionToken,
+}
+
+impl ResumeTokenPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let token = SessionToken::from_raw(packet_data)?;
+        Ok(Self {
+            token,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        SessionToken::SIZE
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_slice(&self.token.0);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Sent by the launcher as the first packet of a new connection instead of the handshake.
+/// Answered with a fresh [`ResumeTokenPacket`], or a disconnect if the session is gone.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ResumePacket {
+    pub token: SessionToken,
+}
+
+impl ResumePacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let token = SessionToken::from_raw(packet_data)?;
+        Ok(Self {
+            token,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        SessionToken::SIZE
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_slice(&self.token.0);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Optional protocol features, as a set of flags.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// UDP frames get sealed with the key from a [`UdpKeyPacket`], see [`crate::crypto`].
+    pub const UDP_ENCRYPTION: Self = Self(1);
+    /// Everything this version of the protocol implements.
+    pub const SUPPORTED: Self = Self::UDP_ENCRYPTION;
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities both sides have.
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl std::ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Sent by the launcher after its version with everything it supports, and answered
+/// by the server with the capabilities both of them have. Launchers that never send
+/// it keep the protocol as it was, e.g. plaintext UDP.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CapabilitiesPacket {
+    pub capabilities: Capabilities,
+}
+
+impl CapabilitiesPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let data_len = packet_data.len();
+        if data_len != 4 { return Err(PacketDecodeError::InvalidDataSize { expected: 4, actual: data_len }); }
+        let capabilities = Capabilities(u32::from_le_bytes(packet_data.try_into().unwrap()));
+        Ok(Self {
+            capabilities,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        4
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u32_le(self.capabilities.0);
+        Ok(())
+    }
+
+    pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Sent by the server right after agreeing on [`Capabilities::UDP_ENCRYPTION`].
+/// Both sides seal their UDP frames with the key from then on.
+/// The key is in cleartext unless the TCP link uses TLS, see [`crate::crypto`].
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct UdpKeyPacket {
+    pub key: UdpKey,
+}
+
+impl UdpKeyPacket {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let key = UdpKey::from_raw(packet_data)?;
+        Ok(Self {
+            key,
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        UdpKey::SIZE
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_slice(&self.key.0);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut buf = Vec::with_capacity(2 + self.auth_code.len());
-        buf.append(&mut self.confirm_id.to_le_bytes().to_vec());
-        for c in self.auth_code.chars().into_iter() {
-            buf.push(c as u8);
-        }
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
         Ok(buf)
     }
 }
@@ -1,22 +1,34 @@
+use crate::framing::Frame;
 use crate::*;
 
 pub mod gameplay;
 pub mod generic;
 pub mod handshake;
 pub mod serverinfo;
+pub mod wire;
 
 use gameplay::*;
 use generic::*;
 use handshake::*;
 use serverinfo::*;
 
-#[derive(Debug)]
+use bytes::BufMut;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub enum Packet {
     Confirmation(ConfirmationPacket),
     PlayerKick(PlayerKickPacket),
+    Ping(PingPacket),
+    Pong(PongPacket),
+    Disconnect(DisconnectPacket),
 
     Version(VersionPacket),
     Authentication(AuthenticationPacket),
+    ResumeToken(ResumeTokenPacket),
+    Resume(ResumePacket),
+    Capabilities(CapabilitiesPacket),
+    UdpKey(UdpKeyPacket),
 
     ServerInfo(ServerInfoPacket),
     LoadMap(LoadMapPacket),
@@ -31,18 +43,116 @@ pub enum Packet {
     VehicleUpdate(VehicleUpdatePacket),
 }
 
+impl Packet {
+    pub fn signature(&self) -> (char, char) {
+        match self {
+            Self::Confirmation(_) => ('C', 'C'),
+            Self::PlayerKick(_) => ('P', 'K'),
+            Self::Ping(_) => ('P', 'I'),
+            Self::Pong(_) => ('P', 'O'),
+            Self::Disconnect(_) => ('D', 'C'),
+
+            Self::Version(_) => ('V', 'C'),
+            Self::Authentication(_) => ('A', 'C'),
+            Self::ResumeToken(_) => ('R', 'T'),
+            Self::Resume(_) => ('R', 'S'),
+            Self::Capabilities(_) => ('C', 'P'),
+            Self::UdpKey(_) => ('U', 'K'),
+
+            Self::ServerInfo(_) => ('H', 'I'),
+            Self::LoadMap(_) => ('L', 'M'),
+
+            Self::PlayerData(_) => ('P', 'D'),
+
+            Self::VehicleSpawn(_) => ('V', 'S'),
+            Self::VehicleConfirm(_) => ('V', 'A'),
+            Self::VehicleDelete(_) => ('V', 'D'),
+
+            Self::VehicleTransform(_) => ('V', 'T'),
+            Self::VehicleUpdate(_) => ('V', 'U'),
+        }
+    }
+
+    fn body_len(&self) -> usize {
+        match self {
+            Self::Confirmation(p) => p.encoded_len(),
+            Self::PlayerKick(p) => p.encoded_len(),
+            Self::Ping(p) => p.encoded_len(),
+            Self::Pong(p) => p.encoded_len(),
+            Self::Disconnect(p) => p.encoded_len(),
+
+            Self::Version(p) => p.encoded_len(),
+            Self::Authentication(p) => p.encoded_len(),
+            Self::ResumeToken(p) => p.encoded_len(),
+            Self::Resume(p) => p.encoded_len(),
+            Self::Capabilities(p) => p.encoded_len(),
+            Self::UdpKey(p) => p.encoded_len(),
+
+            Self::ServerInfo(p) => p.encoded_len(),
+            Self::LoadMap(p) => p.encoded_len(),
+
+            Self::PlayerData(p) => p.encoded_len(),
+
+            Self::VehicleSpawn(p) => p.encoded_len(),
+            Self::VehicleConfirm(p) => p.encoded_len(),
+            Self::VehicleDelete(p) => p.encoded_len(),
+
+            Self::VehicleTransform(p) => p.encoded_len(),
+            Self::VehicleUpdate(p) => p.encoded_len(),
+        }
+    }
+
+    fn encode_body(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        match self {
+            Self::Confirmation(p) => p.encode_into(buf),
+            Self::PlayerKick(p) => p.encode_into(buf),
+            Self::Ping(p) => p.encode_into(buf),
+            Self::Pong(p) => p.encode_into(buf),
+            Self::Disconnect(p) => p.encode_into(buf),
+
+            Self::Version(p) => p.encode_into(buf),
+            Self::Authentication(p) => p.encode_into(buf),
+            Self::ResumeToken(p) => p.encode_into(buf),
+            Self::Resume(p) => p.encode_into(buf),
+            Self::Capabilities(p) => p.encode_into(buf),
+            Self::UdpKey(p) => p.encode_into(buf),
+
+            Self::ServerInfo(p) => p.encode_into(buf),
+            Self::LoadMap(p) => p.encode_into(buf),
+
+            Self::PlayerData(p) => p.encode_into(buf),
+
+            Self::VehicleSpawn(p) => p.encode_into(buf),
+            Self::VehicleConfirm(p) => p.encode_into(buf),
+            Self::VehicleDelete(p) => p.encode_into(buf),
+
+            Self::VehicleTransform(p) => p.encode_into(buf),
+            Self::VehicleUpdate(p) => p.encode_into(buf),
+        }
+    }
+}
+
 impl PacketTrait for Packet {
-    fn from_raw(sig_a: char, sig_b: char, packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    fn from_raw(sig_a: char, sig_b: char, packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         match (sig_a, sig_b) {
             ('C', 'C') => Ok(Self::Confirmation(ConfirmationPacket::from_raw(
                 packet_data,
             )?)),
             ('P', 'K') => Ok(Self::PlayerKick(PlayerKickPacket::from_raw(packet_data)?)),
+            ('P', 'I') => Ok(Self::Ping(PingPacket::from_raw(packet_data)?)),
+            ('P', 'O') => Ok(Self::Pong(PongPacket::from_raw(packet_data)?)),
+            ('D', 'C') => Ok(Self::Disconnect(DisconnectPacket::from_raw(packet_data)?)),
 
             ('V', 'C') => Ok(Self::Version(VersionPacket::from_raw(packet_data)?)),
             ('A', 'C') => Ok(Self::Authentication(AuthenticationPacket::from_raw(
                 packet_data,
             )?)),
+            ('R', 'T') => Ok(Self::ResumeToken(ResumeTokenPacket::from_raw(packet_data)?)),
+            ('R', 'S') => Ok(Self::Resume(ResumePacket::from_raw(packet_data)?)),
+            ('C', 'P') => Ok(Self::Capabilities(CapabilitiesPacket::from_raw(
+                packet_data,
+            )?)),
+            ('U', 'K') => Ok(Self::UdpKey(UdpKeyPacket::from_raw(packet_data)?)),
 
             ('H', 'I') => Ok(Self::ServerInfo(ServerInfoPacket::from_raw(packet_data)?)),
             ('L', 'M') => Ok(Self::LoadMap(LoadMapPacket::from_raw(packet_data)?)),
@@ -71,24 +181,63 @@ impl PacketTrait for Packet {
     }
 
     fn to_raw(&self) -> Result<(char, char, Vec<u8>), PacketEncodeError> {
-        match self {
-            Self::Confirmation(p) => Ok(('C', 'C', p.to_raw()?)),
-            Self::PlayerKick(p) => Ok(('P', 'K', p.to_raw()?)),
+        let (sig_a, sig_b) = self.signature();
+        let mut raw = Vec::with_capacity(self.body_len());
+        self.encode_body(&mut raw)?;
+        Ok((sig_a, sig_b, raw))
+    }
+
+    fn encoded_len(&self) -> usize {
+        PacketHeader::SIZE + self.body_len()
+    }
 
-            Self::Version(p) => Ok(('V', 'C', p.to_raw()?)),
-            Self::Authentication(p) => Ok(('A', 'C', p.to_raw()?)),
+    fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        let (sig_a, sig_b) = self.signature();
+        let header = PacketHeader {
+            sig_a,
+            sig_b,
+            packet_length: self.body_len() as u32,
+        };
+        buf.put_slice(&header.to_bytes());
+        self.encode_body(buf)
+    }
+}
 
-            Self::ServerInfo(p) => Ok(('H', 'I', p.to_raw()?)),
-            Self::LoadMap(p) => Ok(('L', 'M', p.to_raw()?)),
+/// Decodes the per-tick vehicle packets without copying out of the receive buffer.
+/// Everything else is rare enough to simply be decoded into an owned [`Packet`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketRef<'a> {
+    VehicleTransform(VehicleTransformPacketRef<'a>),
+    VehicleUpdate(VehicleUpdatePacketRef<'a>),
+    Other(Packet),
+}
 
-            Self::PlayerData(p) => Ok(('P', 'D', p.to_raw()?)),
+impl<'a> PacketRef<'a> {
+    pub fn from_raw(
+        sig_a: char,
+        sig_b: char,
+        packet_data: &'a [u8],
+    ) -> Result<Self, PacketDecodeError> {
+        match (sig_a, sig_b) {
+            ('V', 'T') => Ok(Self::VehicleTransform(VehicleTransformPacketRef::from_raw(
+                packet_data,
+            )?)),
+            ('V', 'U') => Ok(Self::VehicleUpdate(VehicleUpdatePacketRef::from_raw(
+                packet_data,
+            )?)),
+            _ => Ok(Self::Other(Packet::from_raw(sig_a, sig_b, packet_data)?)),
+        }
+    }
 
-            Self::VehicleSpawn(p) => Ok(('V', 'S', p.to_raw()?)),
-            Self::VehicleConfirm(p) => Ok(('V', 'A', p.to_raw()?)),
-            Self::VehicleDelete(p) => Ok(('V', 'D', p.to_raw()?)),
+    pub fn from_frame(frame: Frame<'a>) -> Result<Self, PacketDecodeError> {
+        Self::from_raw(frame.sig_a, frame.sig_b, frame.data)
+    }
 
-            Self::VehicleTransform(p) => Ok(('V', 'T', p.to_raw()?)),
-            Self::VehicleUpdate(p) => Ok(('V', 'U', p.to_raw()?)),
+    pub fn into_owned(self) -> Packet {
+        match self {
+            Self::VehicleTransform(p) => Packet::VehicleTransform(p.into_owned()),
+            Self::VehicleUpdate(p) => Packet::VehicleUpdate(p.into_owned()),
+            Self::Other(p) => p,
         }
     }
 }
@@ -1,13 +1,16 @@
+use super::wire;
 use super::{PacketDecodeError, PacketEncodeError};
+use bytes::BufMut;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct ServerInfoPacket {
     pub http_port: u16,
     pub udp_port: u16,
 }
 
 impl ServerInfoPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let data_len = packet_data.len();
         if data_len != 4 { return Err(PacketDecodeError::InvalidDataSize { expected: 4, actual: data_len }); }
         let http_port = u16::from_le_bytes([packet_data[0], packet_data[1]]);
@@ -18,39 +21,56 @@ impl ServerInfoPacket {
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        4
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.http_port);
+        buf.put_u16_le(self.udp_port);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut bytes = self.http_port.to_le_bytes().to_vec();
-        bytes.extend_from_slice(&self.udp_port.to_le_bytes());
-        Ok(bytes)
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct LoadMapPacket {
     pub confirm_id: u16,
     pub map_name: String,
 }
 
 impl LoadMapPacket {
-    pub fn from_raw(packet_data: Vec<u8>) -> Result<Self, PacketDecodeError> {
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
         let data_len = packet_data.len();
         if data_len < 2 { return Err(PacketDecodeError::InvalidDataSize { expected: 2, actual: data_len }); }
 
         let confirm_id = u16::from_le_bytes([packet_data[0], packet_data[1]]);
 
-        // TODO: String::with_capacity for 1 single big allocation for extra performance?
-        let map_name = String::from_utf8(packet_data[2..].to_vec()).map_err(|_| PacketDecodeError::InvalidString)?;
+        let map_name = wire::read_trailing_str(&packet_data[2..])?;
         Ok(Self {
             confirm_id,
             map_name,
         })
     }
 
+    pub fn encoded_len(&self) -> usize {
+        2 + self.map_name.len()
+    }
+
+    pub fn encode_into(&self, buf: &mut impl BufMut) -> Result<(), PacketEncodeError> {
+        buf.put_u16_le(self.confirm_id);
+        wire::write_trailing_str(buf, &self.map_name);
+        Ok(())
+    }
+
     pub fn to_raw(&self) -> Result<Vec<u8>, PacketEncodeError> {
-        let mut buf = self.confirm_id.to_le_bytes().to_vec();
-        for c in self.map_name.chars().into_iter() {
-            buf.push(c as u8);
-        }
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
         Ok(buf)
     }
 }
@@ -0,0 +1,21 @@
+//! String encoding shared by the binary packets.
+//!
+//! Strings are always UTF-8 and get validated on decode. Every string is the last
+//! field of its packet and simply runs until the end of the body.
+
+use super::PacketDecodeError;
+
+use bytes::BufMut;
+
+pub fn read_trailing_str(data: &[u8]) -> Result<String, PacketDecodeError> {
+    Ok(read_trailing_str_ref(data)?.to_string())
+}
+
+/// Like [`read_trailing_str`], but borrows the string from the packet body.
+pub fn read_trailing_str_ref(data: &[u8]) -> Result<&str, PacketDecodeError> {
+    std::str::from_utf8(data).map_err(|_| PacketDecodeError::InvalidString)
+}
+
+pub fn write_trailing_str(buf: &mut impl BufMut, s: &str) {
+    buf.put_slice(s.as_bytes());
+}
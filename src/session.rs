@@ -0,0 +1,381 @@
+//! Picking a session back up after the launcher lost its connection to the server.
+//!
+//! Once the handshake is done the server hands out a [`SessionToken`]. When the
+//! connection drops, the server parks the session in its [`SessionStore`] instead
+//! of dropping the player, and the launcher's [`ReconnectingClient`] connects
+//! again and presents the token. Within the grace window the player keeps their
+//! slot and vehicles, afterwards the session is gone for good.
+
+use crate::capture::Recorder;
+use crate::connection::{KeepaliveConfig, StreamTransport, TcpConnection, UdpClient};
+use crate::redact::redacted;
+use crate::server_launcher::generic::DisconnectPacket;
+use crate::server_launcher::handshake::{ResumePacket, ResumeTokenPacket};
+use crate::server_launcher::Packet;
+use crate::*;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+
+/// Random bearer token identifying a session, whoever presents it takes the session over.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken(pub [u8; SessionToken::SIZE]);
+
+impl SessionToken {
+    pub const SIZE: usize = 16;
+
+    pub fn generate() -> Self {
+        let mut token = [0u8; Self::SIZE];
+        getrandom::fill(&mut token).expect("no randomness available");
+        Self(token)
+    }
+
+    pub fn from_raw(packet_data: &[u8]) -> Result<Self, PacketDecodeError> {
+        let token = packet_data
+            .try_into()
+            .map_err(|_| PacketDecodeError::InvalidDataSize {
+                expected: Self::SIZE,
+                actual: packet_data.len(),
+            })?;
+        Ok(Self(token))
+    }
+}
+
+redacted!(SessionToken);
+
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(30);
+
+enum Slot<S> {
+    /// The player is connected, the server holds on to their state itself.
+    Active,
+    Parked {
+        since: Instant,
+        state: S,
+    },
+}
+
+/// What [`SessionStore::resume`] found.
+#[derive(Debug, PartialEq)]
+pub enum Resumed<S> {
+    /// The state that was parked when the old connection dropped.
+    Parked(S),
+    /// The old connection hasn't been noticed as dropped yet. The caller has to
+    /// move the session over from it.
+    Active,
+}
+
+/// Server side bookkeeping of resumable sessions. `S` is whatever the server needs
+/// to restore a player, e.g. their id and vehicles.
+pub struct SessionStore<S> {
+    grace: Duration,
+    sessions: HashMap<SessionToken, Slot<S>>,
+}
+
+impl<S> Default for SessionStore<S> {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRACE)
+    }
+}
+
+impl<S> SessionStore<S> {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Starts a session for a player that just finished the handshake.
+    /// Send the token to them in a [`ResumeTokenPacket`].
+    pub fn issue(&mut self) -> SessionToken {
+        let token = SessionToken::generate();
+        self.sessions.insert(token, Slot::Active);
+        token
+    }
+
+    /// Keeps the player's state around for the grace window after their connection dropped.
+    /// Returns the state back if the token is unknown.
+    pub fn park(&mut self, token: &SessionToken, state: S, now: Instant) -> Result<(), S> {
+        match self.sessions.get_mut(token) {
+            Some(slot) => {
+                *slot = Slot::Parked { since: now, state };
+                Ok(())
+            }
+            None => Err(state),
+        }
+    }
+
+    /// Hands the session to whoever presented the token. The token is used up,
+    /// the session continues under the returned one.
+    /// A session past its grace window isn't resumed, it's left for [`Self::expire`].
+    pub fn resume(
+        &mut self,
+        token: &SessionToken,
+        now: Instant,
+    ) -> Option<(SessionToken, Resumed<S>)> {
+        if let Slot::Parked { since, .. } = self.sessions.get(token)? {
+            if now.saturating_duration_since(*since) >= self.grace {
+                return None;
+            }
+        }
+        let resumed = match self.sessions.remove(token)? {
+            Slot::Active => Resumed::Active,
+            Slot::Parked { state, .. } => Resumed::Parked(state),
+        };
+        let token = SessionToken::generate();
+        self.sessions.insert(token, Slot::Active);
+        Some((token, resumed))
+    }
+
+    /// Ends a session for good, e.g. after a clean disconnect.
+    pub fn remove(&mut self, token: &SessionToken) -> Option<S> {
+        match self.sessions.remove(token)? {
+            Slot::Active => None,
+            Slot::Parked { state, .. } => Some(state),
+        }
+    }
+
+    /// Drops every parked session past its grace window and returns their state, so the
+    /// server can free the slots and delete the vehicles.
+    pub fn expire(&mut self, now: Instant) -> Vec<S> {
+        let grace = self.grace;
+        let expired: Vec<SessionToken> = self
+            .sessions
+            .iter()
+            .filter(|(_, slot)| match slot {
+                Slot::Parked { since, .. } => now.saturating_duration_since(*since) >= grace,
+                Slot::Active => false,
+            })
+            .map(|(token, _)| *token)
+            .collect();
+        expired
+            .iter()
+            .filter_map(|token| self.remove(token))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled after every failed one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Errors that mean the connection is gone, as opposed to the peer misbehaving.
+fn is_connection_lost(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>().is_some()
+        || matches!(
+            e.downcast_ref::<ConnectionError>(),
+            Some(ConnectionError::Timeout)
+        )
+}
+
+/// Opens a new connection to the server for a [`ReconnectingClient`], e.g. TCP
+/// followed by a TLS handshake. Any closure returning such a future will do.
+pub trait Connector {
+    type Stream: StreamTransport;
+
+    fn connect(
+        &mut self,
+    ) -> impl Future<Output = anyhow::Result<TcpConnection<Packet, Self::Stream>>>;
+}
+
+impl<F, Fut, S> Connector for F
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<TcpConnection<Packet, S>>>,
+    S: StreamTransport,
+{
+    type Stream = S;
+
+    fn connect(&mut self) -> impl Future<Output = anyhow::Result<TcpConnection<Packet, S>>> {
+        self()
+    }
+}
+
+/// Plain TCP to a fixed address.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpConnector(pub SocketAddr);
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    async fn connect(&mut self) -> anyhow::Result<TcpConnection<Packet>> {
+        Ok(TcpConnection::from_stream(
+            TcpStream::connect(self.0).await?,
+        ))
+    }
+}
+
+/// Launcher side connection to the server that reconnects and resumes the session
+/// on its own whenever the TCP connection drops. The handshake is up to the caller,
+/// resume tokens are picked out of the received packets.
+///
+/// The UDP client, if any, is kept as it is across reconnects, its socket isn't
+/// tied to the TCP connection.
+pub struct ReconnectingClient<C: Connector = TcpConnector> {
+    connector: C,
+    config: ReconnectConfig,
+    tcp: TcpConnection<Packet, C::Stream>,
+    udp: Option<UdpClient<Packet>>,
+    token: Option<SessionToken>,
+    keepalive: KeepaliveConfig,
+    recorder: Option<Arc<dyn Recorder>>,
+    reconnects: u32,
+}
+
+impl ReconnectingClient {
+    pub async fn connect(addr: SocketAddr, config: ReconnectConfig) -> anyhow::Result<Self> {
+        Self::with_connector(TcpConnector(addr), config).await
+    }
+}
+
+impl<C: Connector> ReconnectingClient<C> {
+    /// Every connection, the first one included, is opened through `connector`.
+    pub async fn with_connector(mut connector: C, config: ReconnectConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            tcp: connector.connect().await?,
+            connector,
+            config,
+            udp: None,
+            token: None,
+            keepalive: KeepaliveConfig::default(),
+            recorder: None,
+            reconnects: 0,
+        })
+    }
+
+    pub fn set_udp(&mut self, udp: UdpClient<Packet>) {
+        self.udp = Some(udp);
+    }
+
+    pub fn udp_mut(&mut self) -> Option<&mut UdpClient<Packet>> {
+        self.udp.as_mut()
+    }
+
+    /// Applied to every TCP connection, including the ones after a reconnect.
+    pub fn set_keepalive(&mut self, config: KeepaliveConfig) {
+        self.keepalive = config;
+        self.tcp.set_keepalive(config);
+    }
+
+    /// Applied to every TCP connection, including the ones after a reconnect.
+    pub fn set_recorder(&mut self, recorder: Arc<dyn Recorder>) {
+        self.tcp.set_recorder(recorder.clone());
+        self.recorder = Some(recorder);
+    }
+
+    /// The token the next resume would use, `None` before the server sent one.
+    pub fn token(&self) -> Option<SessionToken> {
+        self.token
+    }
+
+    /// How often the session was resumed so far.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// Resume tokens are consumed here and never returned.
+    pub async fn wait_for_packet(&mut self) -> anyhow::Result<Packet> {
+        loop {
+            let packet = match self.tcp.wait_for_packet().await {
+                Ok(packet) => packet,
+                Err(e) if is_connection_lost(&e) && self.token.is_some() => {
+                    debug!("connection lost ({e}), resuming");
+                    self.reconnect().await?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match packet {
+                Packet::ResumeToken(ResumeTokenPacket { token }) => self.token = Some(token),
+                packet => return Ok(packet),
+            }
+        }
+    }
+
+    /// If the connection turns out to be gone, the packet is sent again after resuming.
+    pub async fn write_packet(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        match self.tcp.write_packet(packet).await {
+            Err(e) if is_connection_lost(&e) && self.token.is_some() => {
+                debug!("connection lost ({e}), resuming");
+                self.reconnect().await?;
+                self.tcp.write_packet(packet).await
+            }
+            result => result,
+        }
+    }
+
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        let mut backoff = self.config.initial_backoff;
+        let mut last_error = None;
+        for attempt in 1..=self.config.max_attempts {
+            if attempt > 1 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.config.max_backoff);
+            }
+            match self.resume().await {
+                Ok(()) => {
+                    self.reconnects += 1;
+                    return Ok(());
+                }
+                Err(e) if is_connection_lost(&e) => {
+                    debug!("resume attempt {attempt} failed: {e}");
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no reconnect attempts allowed")))
+    }
+
+    async fn resume(&mut self) -> anyhow::Result<()> {
+        let Some(token) = self.token else {
+            anyhow::bail!("no session to resume");
+        };
+        let mut tcp = self.connector.connect().await?;
+        tcp.set_keepalive(self.keepalive);
+        if let Some(recorder) = &self.recorder {
+            tcp.set_recorder(recorder.clone());
+        }
+        tcp.write_packet(&Packet::Resume(ResumePacket { token }))
+            .await?;
+        match tcp.wait_for_packet().await? {
+            Packet::ResumeToken(ResumeTokenPacket { token }) => {
+                self.token = Some(token);
+                self.tcp = tcp;
+                Ok(())
+            }
+            Packet::Disconnect(DisconnectPacket { reason, .. }) => {
+                self.token = None;
+                Err(ConnectionError::ResumeRefused(reason).into())
+            }
+            packet => anyhow::bail!("unexpected answer to resume: {:?}", packet.signature()),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! TLS for the TCP link between server and launcher, so auth codes and the rest of
+//! the handshake don't travel in cleartext. Needs the `tls` feature.
+//!
+//! Only the TCP side is covered, UDP stays as it is.
+
+use crate::connection::{StreamTransport, TcpConnection};
+use crate::*;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{client, server};
+
+pub use tokio_rustls::{rustls, TlsAcceptor, TlsConnector};
+
+/// Server side of a TLS connection, usually on top of TCP.
+pub type ServerTlsConnection<T, S = TcpStream> = TcpConnection<T, server::TlsStream<S>>;
+/// Launcher side of a TLS connection, usually on top of TCP.
+pub type ClientTlsConnection<T, S = TcpStream> = TcpConnection<T, client::TlsStream<S>>;
+
+impl<S: StreamTransport> StreamTransport for server::TlsStream<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+}
+
+impl<S: StreamTransport> StreamTransport for client::TlsStream<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+}
+
+/// Builds an acceptor presenting `cert_chain`, leaf first.
+pub fn acceptor(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> anyhow::Result<TlsAcceptor> {
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Builds a connector trusting only `roots`, e.g. a server's self-signed certificate.
+pub fn connector(roots: RootCertStore) -> anyhow::Result<TlsConnector> {
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+impl<T: PacketTrait, S: StreamTransport> ServerTlsConnection<T, S> {
+    /// Runs the TLS handshake on a freshly accepted connection.
+    pub async fn accept_tls(stream: S, acceptor: &TlsAcceptor) -> anyhow::Result<Self> {
+        Ok(Self::new(acceptor.accept(stream).await?))
+    }
+}
+
+impl<T: PacketTrait, S: StreamTransport> ClientTlsConnection<T, S> {
+    /// Runs the TLS handshake, `server_name` has to match the server's certificate.
+    pub async fn connect_tls(
+        stream: S,
+        connector: &TlsConnector,
+        server_name: ServerName<'static>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::new(connector.connect(server_name, stream).await?))
+    }
+}
@@ -0,0 +1,252 @@
+//! Writing captures and reading them back, including corrupt files.
+
+use ngmp_protocol_impl::capture::{
+    CaptureReader, CaptureRecord, CaptureWriter, Direction, Recorder, Transport,
+};
+use ngmp_protocol_impl::framing::{encode_frame, DatagramBatch, HEARTBEAT};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleUpdatePacket;
+use ngmp_protocol_impl::server_launcher::generic::ConfirmationPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::CaptureError;
+
+use std::net::SocketAddr;
+
+fn confirmation(confirm_id: u16) -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id })
+}
+
+fn record(timestamp_us: u64, peer: Option<SocketAddr>, packet: &Packet) -> CaptureRecord {
+    CaptureRecord {
+        timestamp_us,
+        direction: Direction::Outbound,
+        transport: Transport::Tcp,
+        peer,
+        frame: encode_frame(packet).unwrap().to_vec(),
+    }
+}
+
+/// The datagrams `packets` get coalesced and fragmented into.
+fn datagrams(packets: &[Packet], mtu: usize) -> Vec<Vec<u8>> {
+    let mut batch = DatagramBatch::new(mtu);
+    for packet in packets {
+        batch.push(packet).unwrap();
+    }
+    batch.datagrams().map(<[u8]>::to_vec).collect()
+}
+
+fn udp_record(timestamp_us: u64, datagram: Vec<u8>) -> CaptureRecord {
+    CaptureRecord {
+        timestamp_us,
+        direction: Direction::Inbound,
+        transport: Transport::Udp,
+        peer: Some("127.0.0.1:30815".parse().unwrap()),
+        frame: datagram,
+    }
+}
+
+fn capture(records: &[CaptureRecord]) -> Vec<u8> {
+    let writer = CaptureWriter::new(Vec::new()).unwrap();
+    for record in records {
+        writer.write_record(record).unwrap();
+    }
+    writer.into_inner()
+}
+
+#[test]
+fn records_round_trip() {
+    let records = [
+        record(1, None, &confirmation(1)),
+        record(
+            2,
+            Some("127.0.0.1:30814".parse().unwrap()),
+            &confirmation(2),
+        ),
+        record(3, Some("[::1]:30815".parse().unwrap()), &confirmation(3)),
+    ];
+    let bytes = capture(&records);
+
+    let read = CaptureReader::new(&bytes[..])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(read.len(), records.len());
+    for (read, written) in read.iter().zip(&records) {
+        assert_eq!(read.timestamp_us, written.timestamp_us);
+        assert_eq!(read.direction, written.direction);
+        assert_eq!(read.transport, written.transport);
+        assert_eq!(read.peer, written.peer);
+        assert_eq!(read.frame, written.frame);
+    }
+}
+
+#[test]
+fn recorder_writes_readable_records() {
+    let writer = CaptureWriter::new(Vec::new()).unwrap();
+    let peer = Some("127.0.0.1:4000".parse().unwrap());
+    let frame = encode_frame(&confirmation(7)).unwrap();
+    writer.record(Direction::Inbound, Transport::Udp, peer, &frame);
+    let bytes = writer.into_inner();
+
+    let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+    let record = reader.read_record().unwrap().unwrap();
+    assert_eq!(record.direction, Direction::Inbound);
+    assert_eq!(record.transport, Transport::Udp);
+    assert_eq!(record.peer, peer);
+    assert_eq!(record.frame, frame);
+    assert!(record.timestamp_us > 0);
+    assert!(reader.read_record().unwrap().is_none());
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let mut bytes = capture(&[]);
+    bytes[0] = b'X';
+    assert!(matches!(
+        CaptureReader::new(&bytes[..]),
+        Err(CaptureError::InvalidMagic)
+    ));
+    assert!(matches!(
+        CaptureReader::new(&b"NGM"[..]),
+        Err(CaptureError::InvalidMagic)
+    ));
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let mut bytes = capture(&[]);
+    bytes[7] = 2;
+    assert!(matches!(
+        CaptureReader::new(&bytes[..]),
+        Err(CaptureError::UnsupportedVersion(2))
+    ));
+}
+
+#[test]
+fn truncated_records_are_errors() {
+    let bytes = capture(&[record(
+        1,
+        Some("127.0.0.1:30814".parse().unwrap()),
+        &confirmation(1),
+    )]);
+    // Every cut inside the record, from the timestamp to the last frame byte
+    for len in 9..bytes.len() {
+        let mut reader = CaptureReader::new(&bytes[..len]).unwrap();
+        assert!(
+            matches!(reader.next(), Some(Err(CaptureError::CorruptRecord))),
+            "cut at {}",
+            len
+        );
+        assert!(reader.next().is_none());
+    }
+}
+
+#[test]
+fn frame_length_past_the_end_is_an_error() {
+    let mut bytes = capture(&[record(1, None, &confirmation(1))]);
+    // magic, timestamp, direction, transport, family
+    let len_offset = 8 + 8 + 3;
+    bytes[len_offset..len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+    assert!(matches!(
+        reader.read_record(),
+        Err(CaptureError::CorruptRecord)
+    ));
+}
+
+#[test]
+fn invalid_record_fields_are_errors() {
+    let bytes = capture(&[record(1, None, &confirmation(1))]);
+    // direction, transport and address family
+    for offset in [16, 17, 18] {
+        let mut bytes = bytes.clone();
+        bytes[offset] = 9;
+        let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert!(matches!(
+            reader.read_record(),
+            Err(CaptureError::CorruptRecord)
+        ));
+    }
+}
+
+#[test]
+fn every_frame_of_a_record_is_decoded() {
+    let packets = [confirmation(1), confirmation(2), confirmation(3)];
+    let datagrams = datagrams(&packets, 1200);
+    assert_eq!(datagrams.len(), 1);
+
+    let record = udp_record(1, datagrams[0].clone());
+    assert_eq!(record.decode::<Packet>().unwrap(), packets);
+}
+
+#[test]
+fn heartbeats_are_skipped() {
+    let mut datagram = HEARTBEAT.to_vec();
+    datagram.extend_from_slice(&encode_frame(&confirmation(1)).unwrap());
+    datagram.extend_from_slice(&HEARTBEAT);
+    assert_eq!(
+        udp_record(1, datagram).decode::<Packet>().unwrap(),
+        [confirmation(1)]
+    );
+    assert!(udp_record(2, HEARTBEAT.to_vec())
+        .decode::<Packet>()
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn fragments_are_reassembled_across_records() {
+    let update = Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id: 1,
+        vehicle_id: 2,
+        ms: 3,
+        runtime_data: "r".repeat(500),
+    });
+    let datagrams = datagrams(&[update.clone(), confirmation(1)], 200);
+    assert!(datagrams.len() > 2);
+
+    // A single record only holds part of the update
+    let first = udp_record(1, datagrams[0].clone());
+    assert!(first.decode::<Packet>().unwrap().is_empty());
+
+    let records = datagrams
+        .into_iter()
+        .enumerate()
+        .map(|(i, datagram)| udp_record(i as u64, datagram))
+        .collect::<Vec<_>>();
+    let bytes = capture(&records);
+    let decoded = CaptureReader::new(&bytes[..])
+        .unwrap()
+        .packets::<Packet>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(decoded.len(), records.len());
+    let (last, packets) = decoded.last().unwrap();
+    assert_eq!(last.timestamp_us, records.len() as u64 - 1);
+    assert_eq!(packets, &[update, confirmation(1)]);
+    assert!(decoded[..records.len() - 1]
+        .iter()
+        .all(|(_, packets)| packets.is_empty()));
+}
+
+#[test]
+fn undecodable_frames_are_errors() {
+    let mut frame = encode_frame(&confirmation(1)).unwrap().to_vec();
+    frame.push(0);
+    let trailing = CaptureRecord {
+        frame,
+        ..record(1, None, &confirmation(1))
+    };
+    assert!(matches!(
+        trailing.decode::<Packet>(),
+        Err(CaptureError::InvalidFrame(_))
+    ));
+
+    let unknown = CaptureRecord {
+        frame: b"ZZ\0\0\0\0".to_vec(),
+        ..record(1, None, &confirmation(1))
+    };
+    assert!(matches!(
+        unknown.decode::<Packet>(),
+        Err(CaptureError::Decode(_))
+    ));
+}
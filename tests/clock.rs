@@ -0,0 +1,118 @@
+//! Round trip time and clock offset estimation on simulated links.
+
+use ngmp_protocol_impl::clock::{ClockSync, LocalClock, RttEstimator, MIN_RTO};
+use ngmp_protocol_impl::server_launcher::generic::PongPacket;
+
+use std::time::{Duration, Instant};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Pings a peer whose clock is `offset` ms ahead, with the given one way delays.
+fn exchange(sync: &mut ClockSync, sent: Instant, offset: u32, there: u64, back: u64) -> Instant {
+    let ping = sync.ping(sent);
+    let remote_now = ping.ms.wrapping_add(there as u32).wrapping_add(offset);
+    let pong = ping.reply(remote_now);
+    let received = sent + ms(there + back);
+    sync.on_pong(&pong, received).unwrap();
+    received
+}
+
+#[test]
+fn rtt_follows_rfc_6298() {
+    let mut rtt = RttEstimator::default();
+    assert_eq!(rtt.srtt(), None);
+    assert_eq!(rtt.rto(), Duration::from_secs(1));
+
+    rtt.update(ms(100));
+    assert_eq!(rtt.srtt(), Some(ms(100)));
+    assert_eq!(rtt.rttvar(), ms(50));
+    assert_eq!(rtt.rto(), ms(300));
+
+    rtt.update(ms(180));
+    assert_eq!(rtt.srtt(), Some(ms(110)));
+    assert_eq!(rtt.rttvar(), ms(57) + Duration::from_micros(500));
+    assert_eq!(rtt.min_rtt(), Some(ms(100)));
+
+    let mut fast = RttEstimator::default();
+    fast.update(ms(2));
+    assert_eq!(fast.rto(), MIN_RTO);
+}
+
+#[test]
+fn offset_from_symmetric_link() {
+    let start = Instant::now();
+    let mut sync = ClockSync::new(LocalClock::new(start), 8);
+    assert_eq!(sync.offset_ms(), None);
+    assert_eq!(sync.to_local_ms(1234), None);
+
+    let now = exchange(&mut sync, start + ms(1000), 5000, 40, 40);
+    assert_eq!(sync.rtt().srtt(), Some(ms(80)));
+    assert_eq!(sync.offset_ms(), Some(5000));
+    assert_eq!(sync.to_local_ms(7000), Some(2000));
+    assert_eq!(sync.to_local_instant(7000, now), Some(start + ms(2000)));
+}
+
+#[test]
+fn queueing_delay_does_not_skew_offset() {
+    let start = Instant::now();
+    let mut sync = ClockSync::new(LocalClock::new(start), 8);
+    let mut now = start + ms(1000);
+    // A clean exchange between congested ones, where the way back got stuck in a queue
+    for (there, back) in [(20, 300), (20, 20), (20, 150), (20, 500)] {
+        now = exchange(&mut sync, now, 250, there, back) + ms(100);
+    }
+    assert_eq!(sync.offset_ms(), Some(250));
+    assert_eq!(sync.rtt().min_rtt(), Some(ms(40)));
+}
+
+#[test]
+fn old_samples_leave_the_window() {
+    let start = Instant::now();
+    let mut sync = ClockSync::new(LocalClock::new(start), 2);
+    let mut now = start + ms(1000);
+    now = exchange(&mut sync, now, 100, 10, 10);
+    // The remote clock got adjusted since
+    for _ in 0..2 {
+        now = exchange(&mut sync, now, 900, 50, 50);
+    }
+    assert_eq!(sync.offset_ms(), Some(900));
+}
+
+#[test]
+fn negative_offset_and_wraparound() {
+    let start = Instant::now();
+    let mut sync = ClockSync::new(LocalClock::new(start), 8);
+    // Remote clock is behind ours and about to wrap
+    let offset = u32::MAX - 499;
+    exchange(&mut sync, start + ms(100), offset, 30, 30);
+    assert_eq!(sync.offset_ms(), Some(-500));
+    assert_eq!(sync.to_local_ms(u32::MAX - 99), Some(400));
+    assert_eq!(sync.to_local_ms(100), Some(600));
+}
+
+#[test]
+fn bogus_pongs_are_ignored() {
+    let start = Instant::now();
+    let mut sync = ClockSync::new(LocalClock::new(start), 8);
+    let now = start + ms(1000);
+    // Echoes a ping from the future
+    let pong = PongPacket {
+        ping_ms: 5000,
+        ms: 0,
+    };
+    assert_eq!(sync.on_pong(&pong, now), None);
+    assert_eq!(sync.offset_ms(), None);
+    assert_eq!(sync.rtt().srtt(), None);
+}
+
+#[test]
+fn instants_around_now() {
+    let start = Instant::now();
+    let clock = LocalClock::new(start);
+    let now = start + ms(10_000);
+    assert_eq!(clock.ms_at(now), 10_000);
+    assert_eq!(clock.instant_at(9_000, now), start + ms(9_000));
+    assert_eq!(clock.instant_at(12_000, now), start + ms(12_000));
+}
@@ -0,0 +1,397 @@
+//! Sealed UDP frames, on their own and through the UDP connections.
+
+use ngmp_protocol_impl::capture::{CaptureReader, CaptureWriter, RecordDecoder};
+use ngmp_protocol_impl::connection::{UdpClient, UdpListener};
+use ngmp_protocol_impl::crypto::{self, Opener, ReplayWindow, Sealer, Side, UdpKey};
+use ngmp_protocol_impl::fragment::{self, Reassembler};
+use ngmp_protocol_impl::framing::{datagram_frames, decode_datagram, encode_frame};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleTransformPacket;
+use ngmp_protocol_impl::server_launcher::handshake::{Capabilities, UdpKeyPacket};
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::ConnectionError;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+
+fn transform(transform: &str) -> Packet {
+    Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 2,
+        transform: transform.to_string(),
+    })
+}
+
+fn seal(sealer: &Sealer, packet: &Packet) -> Vec<u8> {
+    let mut sealed = BytesMut::new();
+    sealer
+        .seal(&encode_frame(packet).unwrap(), &mut sealed)
+        .unwrap();
+    sealed.to_vec()
+}
+
+fn open(opener: &mut Opener, sealed: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+    let frame = datagram_frames(sealed).unwrap().next().unwrap();
+    opener.open(&frame)
+}
+
+fn connection_error(e: anyhow::Error) -> ConnectionError {
+    e.downcast().unwrap()
+}
+
+#[test]
+fn seal_and_open() {
+    let key = UdpKey::generate();
+    let sealer = Sealer::new(&key, Side::Launcher);
+    let mut opener = Opener::new(&key, Side::Server);
+
+    let frame = encode_frame(&transform("a")).unwrap();
+    let sealed = seal(&sealer, &transform("a"));
+    assert_eq!(sealed.len(), frame.len() + crypto::OVERHEAD);
+    assert_eq!(&sealed[..2], b"EN");
+    assert!(!sealed.windows(frame.len()).any(|w| w == &frame[..]));
+    assert_eq!(open(&mut opener, &sealed).unwrap(), frame);
+    assert_eq!(format!("{key:?}"), "UdpKey(..)");
+}
+
+#[test]
+fn tampering_is_detected() {
+    let key = UdpKey::generate();
+    let sealer = Sealer::new(&key, Side::Launcher);
+    let mut opener = Opener::new(&key, Side::Server);
+
+    let sealed = seal(&sealer, &transform("a"));
+    for i in 2..sealed.len() {
+        // Changing the length would make it a different frame altogether
+        if (2..6).contains(&i) {
+            continue;
+        }
+        let mut tampered = sealed.clone();
+        tampered[i] ^= 1;
+        assert!(open(&mut opener, &tampered).is_err(), "byte {i}");
+    }
+    // None of that moved the replay window
+    assert!(open(&mut opener, &sealed).is_ok());
+
+    let mut other = Opener::new(&UdpKey::generate(), Side::Server);
+    assert!(matches!(
+        open(&mut other, &seal(&sealer, &transform("b"))),
+        Err(ConnectionError::Unauthenticated)
+    ));
+}
+
+#[test]
+fn reflected_frames_are_rejected() {
+    let key = UdpKey::generate();
+    let sealer = Sealer::new(&key, Side::Server);
+    // The server must not accept its own frames bounced back at it
+    let mut opener = Opener::new(&key, Side::Server);
+    assert!(matches!(
+        open(&mut opener, &seal(&sealer, &transform("a"))),
+        Err(ConnectionError::Unauthenticated)
+    ));
+}
+
+#[test]
+fn replays_are_rejected() {
+    let key = UdpKey::generate();
+    let sealer = Sealer::new(&key, Side::Launcher);
+    let mut opener = Opener::new(&key, Side::Server);
+
+    let sealed: Vec<Vec<u8>> = (0..100).map(|_| seal(&sealer, &transform("a"))).collect();
+    assert!(open(&mut opener, &sealed[10]).is_ok());
+    assert!(matches!(
+        open(&mut opener, &sealed[10]),
+        Err(ConnectionError::Replayed(10))
+    ));
+    // Reordered within the window is fine
+    assert!(open(&mut opener, &sealed[5]).is_ok());
+    assert!(open(&mut opener, &sealed[99]).is_ok());
+    // Too far behind to tell
+    assert!(matches!(
+        open(&mut opener, &sealed[20]),
+        Err(ConnectionError::Replayed(20))
+    ));
+    assert!(open(&mut opener, &sealed[36]).is_ok());
+}
+
+#[test]
+fn replay_window() {
+    let mut window = ReplayWindow::default();
+    assert!(window.check(0));
+    window.mark(0);
+    assert!(!window.check(0));
+
+    window.mark(100);
+    assert!(!window.check(100));
+    assert!(window.check(99));
+    assert!(window.check(100 - ReplayWindow::SIZE + 1));
+    assert!(!window.check(100 - ReplayWindow::SIZE));
+
+    window.mark(99);
+    window.mark(101);
+    assert!(!window.check(99));
+    assert!(!window.check(101));
+    assert!(window.check(98));
+}
+
+#[test]
+fn capabilities() {
+    let launcher = Capabilities::SUPPORTED;
+    let old_server = Capabilities::NONE;
+    assert!(launcher.contains(Capabilities::UDP_ENCRYPTION));
+    assert!(!launcher
+        .intersection(old_server)
+        .contains(Capabilities::UDP_ENCRYPTION));
+    assert_eq!(
+        (Capabilities(2) | Capabilities::UDP_ENCRYPTION).intersection(Capabilities(3)),
+        Capabilities(3)
+    );
+}
+
+async fn encrypted_pair() -> (UdpListener<Packet>, UdpClient<Packet>) {
+    let mut listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    let mut client = UdpClient::connect(socket, listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let key = listener.issue_key(client_addr);
+    client.set_key(&key).unwrap();
+    (listener, client)
+}
+
+#[tokio::test]
+async fn encrypted_both_ways() {
+    let (mut listener, mut client) = encrypted_pair().await;
+
+    client.write_packet(transform("a")).await.unwrap();
+    let (packet, addr) = listener.wait_for_packet().await.unwrap();
+    assert_eq!(packet, transform("a"));
+    assert_eq!(addr, client.local_addr().unwrap());
+
+    listener.queue_packet(addr, &transform("b")).unwrap();
+    listener.queue_packet(addr, &transform("c")).unwrap();
+    assert!(listener.flush().await.is_ok());
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("b"));
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("c"));
+
+    let sender = listener.sender();
+    sender.write_packet(addr, &transform("d")).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("d"));
+}
+
+#[tokio::test]
+async fn fragmented_and_encrypted() {
+    let (mut listener, mut client) = encrypted_pair().await;
+    client.set_mtu(200);
+
+    let big = transform(&"x".repeat(1000));
+    client.write_packet(big.clone()).await.unwrap();
+    assert_eq!(listener.wait_for_packet().await.unwrap().0, big);
+}
+
+#[tokio::test]
+async fn fragments_are_sealed_one_by_one() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let key = UdpKey::generate();
+    client.set_key(&key).unwrap();
+    client.set_mtu(200);
+
+    let big = transform(&"x".repeat(1000));
+    client.write_packet(big.clone()).await.unwrap();
+    let mut opener = Opener::new(&key, Side::Server);
+    let mut reassembler = Reassembler::default();
+    let mut buf = [0u8; 1500];
+    let message = loop {
+        let n = server.recv(&mut buf).await.unwrap();
+        assert!(n <= 200);
+        let opened = open(&mut opener, &buf[..n]).unwrap();
+        let frame = crypto::sealed_frame(&opened).unwrap();
+        assert!(fragment::is_fragment(&frame));
+        if let Some(message) = reassembler.push((), frame.data, Instant::now()).unwrap() {
+            break message;
+        }
+    };
+    assert_eq!(
+        fragment::decode_reassembled::<Packet>(&message).unwrap(),
+        big
+    );
+}
+
+#[tokio::test]
+async fn unsealed_fragments_are_rejected() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    listener.issue_key(peer.local_addr().unwrap());
+
+    // Forged by someone without the key, it never gets near the reassembler
+    let mut forged = BytesMut::new();
+    fragment::write_fragment(&mut forged, 1, 0, 2, &[0; 100]);
+    peer.send_to(&forged, listener_addr).await.unwrap();
+    let e = listener.wait_for_packet().await.unwrap_err();
+    assert!(matches!(connection_error(e), ConnectionError::Unencrypted));
+}
+
+#[tokio::test]
+async fn captures_are_recorded_in_plaintext() {
+    let (mut listener, mut client) = encrypted_pair().await;
+    let writer = Arc::new(CaptureWriter::new(Vec::new()).unwrap());
+    listener.set_recorder(writer.clone());
+    client.set_recorder(writer.clone());
+    client.set_mtu(200);
+
+    let big = transform(&"x".repeat(1000));
+    client.write_packet(transform("a")).await.unwrap();
+    let (_, addr) = listener.wait_for_packet().await.unwrap();
+    client.write_packet(big.clone()).await.unwrap();
+    listener.wait_for_packet().await.unwrap();
+    listener.queue_packet(addr, &transform("b")).unwrap();
+    assert!(listener.flush().await.is_ok());
+    client.wait_for_packet().await.unwrap();
+    drop((listener, client));
+
+    let capture = Arc::into_inner(writer).unwrap().into_inner();
+    let mut decoder = RecordDecoder::new();
+    let mut packets = Vec::new();
+    for record in CaptureReader::new(&capture[..]).unwrap() {
+        let record = record.unwrap();
+        assert!(!record.frame.starts_with(b"EN"));
+        packets.extend(decoder.decode::<Packet>(&record).unwrap());
+    }
+    assert_eq!(
+        packets,
+        [
+            transform("a"),
+            transform("a"),
+            big.clone(),
+            big,
+            transform("b"),
+            transform("b")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn plaintext_from_encrypted_peer_is_rejected() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    listener.issue_key(peer.local_addr().unwrap());
+
+    peer.send_to(&encode_frame(&transform("a")).unwrap(), listener_addr)
+        .await
+        .unwrap();
+    let e = listener.wait_for_packet().await.unwrap_err();
+    assert!(matches!(connection_error(e), ConnectionError::Unencrypted));
+}
+
+#[tokio::test]
+async fn replayed_datagrams_are_dropped() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let key = listener.issue_key(peer.local_addr().unwrap());
+
+    let sealer = Sealer::new(&key, Side::Launcher);
+    let sealed = seal(&sealer, &transform("a"));
+    peer.send_to(&sealed, listener_addr).await.unwrap();
+    peer.send_to(&sealed, listener_addr).await.unwrap();
+    peer.send_to(&seal(&sealer, &transform("b")), listener_addr)
+        .await
+        .unwrap();
+    // The copy is skipped without failing the listener
+    assert_eq!(listener.wait_for_packet().await.unwrap().0, transform("a"));
+    assert_eq!(listener.wait_for_packet().await.unwrap().0, transform("b"));
+
+    // Same on the launcher side
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, server.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.set_key(&key).unwrap();
+    let sealer = Sealer::new(&key, Side::Server);
+    let sealed = seal(&sealer, &transform("c"));
+    server.send_to(&sealed, client_addr).await.unwrap();
+    server.send_to(&sealed, client_addr).await.unwrap();
+    server
+        .send_to(&seal(&sealer, &transform("d")), client_addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("c"));
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("d"));
+}
+
+#[tokio::test]
+async fn keys_are_never_installed_twice() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer = socket.local_addr().unwrap();
+    let first = listener.issue_key(peer);
+    let second = listener.issue_key(peer);
+    assert_ne!(first, second);
+
+    let mut client = UdpClient::<Packet>::connect(socket, listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.set_key(&first).unwrap();
+    assert!(matches!(
+        client.set_key(&first),
+        Err(ConnectionError::KeyReused)
+    ));
+    client.set_key(&second).unwrap();
+}
+
+#[test]
+fn keys_stay_out_of_dumps() {
+    let key = UdpKey::generate();
+    assert_eq!(format!("{key:?}"), "UdpKey(..)");
+    let packet = Packet::UdpKey(UdpKeyPacket { key });
+    assert_eq!(
+        serde_json::to_value(&packet).unwrap(),
+        serde_json::json!({ "UdpKey": { "key": ".." } })
+    );
+}
+
+#[tokio::test]
+async fn broadcast_to_mixed_peers() {
+    let (mut listener, mut client) = encrypted_pair().await;
+    // An older launcher without a key
+    let plain = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let targets = [client.local_addr().unwrap(), plain.local_addr().unwrap()];
+
+    assert!(listener
+        .broadcast(&transform("a"), targets)
+        .await
+        .unwrap()
+        .is_ok());
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("a"));
+    let mut buf = [0u8; 1500];
+    let n = plain.recv(&mut buf).await.unwrap();
+    assert_eq!(
+        decode_datagram::<Packet>(&buf[..n]).unwrap(),
+        [transform("a")]
+    );
+
+    // Once the player is gone, their key is forgotten too
+    listener
+        .disconnect(targets[0], &transform("b"))
+        .await
+        .unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform("b"));
+    listener
+        .write_packet(targets[0], transform("c"))
+        .await
+        .unwrap();
+    let e = client.wait_for_packet().await.unwrap_err();
+    assert!(matches!(connection_error(e), ConnectionError::Unencrypted));
+}
@@ -0,0 +1,145 @@
+//! Splitting UDP datagrams into frames.
+
+use ngmp_protocol_impl::fragment::{self, Reassembler};
+use ngmp_protocol_impl::framing::{datagram_frames, decode_datagram, encode_frame, DatagramBatch};
+use ngmp_protocol_impl::server_launcher::gameplay::{VehicleDeletePacket, VehicleTransformPacket};
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::ConnectionError;
+
+use std::time::Instant;
+
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::VehicleTransform(VehicleTransformPacket {
+            player_id: 1,
+            vehicle_id: 2,
+            transform: "{}".to_string(),
+        }),
+        Packet::VehicleDelete(VehicleDeletePacket {
+            player_id: 3,
+            vehicle_id: 4,
+        }),
+        Packet::VehicleTransform(VehicleTransformPacket {
+            player_id: 5,
+            vehicle_id: 6,
+            transform: String::new(),
+        }),
+    ]
+}
+
+fn coalesced() -> Vec<u8> {
+    packets()
+        .iter()
+        .flat_map(|p| encode_frame(p).unwrap())
+        .collect()
+}
+
+#[test]
+fn single_frame() {
+    let datagram = encode_frame(&packets()[0]).unwrap();
+    assert_eq!(
+        decode_datagram::<Packet>(&datagram).unwrap(),
+        packets()[..1]
+    );
+}
+
+#[test]
+fn coalesced_frames() {
+    let datagram = coalesced();
+    assert_eq!(datagram_frames(&datagram).unwrap().count(), 3);
+    assert_eq!(decode_datagram::<Packet>(&datagram).unwrap(), packets());
+}
+
+#[test]
+fn trailing_garbage_is_rejected() {
+    for garbage in [&[0u8][..], &[b'V', b'T', 0, 0, 0]] {
+        let mut datagram = coalesced();
+        datagram.extend_from_slice(garbage);
+        assert!(matches!(
+            datagram_frames(&datagram),
+            Err(ConnectionError::TrailingData(n)) if n == garbage.len()
+        ));
+        assert!(decode_datagram::<Packet>(&datagram).is_err());
+    }
+}
+
+#[test]
+fn truncated_frame_is_rejected() {
+    let mut datagram = coalesced();
+    datagram.pop();
+    assert!(matches!(
+        datagram_frames(&datagram),
+        Err(ConnectionError::InvalidPacketSize)
+    ));
+}
+
+#[test]
+fn empty_datagram_is_rejected() {
+    assert!(matches!(
+        datagram_frames(&[]),
+        Err(ConnectionError::InvalidPacketSize)
+    ));
+}
+
+#[test]
+fn batch_packs_frames_up_to_the_mtu() {
+    let frame_len = encode_frame(&packets()[0]).unwrap().len();
+    let mut batch = DatagramBatch::new(frame_len * 2);
+    for _ in 0..5 {
+        batch.push(&packets()[0]).unwrap();
+    }
+    let sizes = batch.datagrams().map(<[u8]>::len).collect::<Vec<_>>();
+    assert_eq!(sizes, [frame_len * 2, frame_len * 2, frame_len]);
+
+    let decoded = batch
+        .datagrams()
+        .flat_map(|d| decode_datagram::<Packet>(d).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, vec![packets()[0].clone(); 5]);
+}
+
+#[test]
+fn batch_keeps_frame_order_across_datagrams() {
+    let mut batch = DatagramBatch::new(30);
+    for packet in packets() {
+        batch.push(&packet).unwrap();
+    }
+    let decoded = batch
+        .datagrams()
+        .flat_map(|d| decode_datagram::<Packet>(d).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, packets());
+    assert!(batch.datagrams().all(|d| d.len() <= 30));
+}
+
+#[test]
+fn oversized_frame_gets_fragmented() {
+    let big = Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 1,
+        transform: "x".repeat(100),
+    });
+    let mut batch = DatagramBatch::new(64);
+    batch.push(&packets()[1]).unwrap();
+    batch.push(&big).unwrap();
+    batch.push(&packets()[1]).unwrap();
+    assert!(batch.datagrams().all(|datagram| datagram.len() <= 64));
+
+    let mut reassembler = Reassembler::default();
+    let mut decoded = Vec::new();
+    for datagram in batch.datagrams() {
+        for frame in datagram_frames(datagram).unwrap() {
+            if !fragment::is_fragment(&frame) {
+                decoded.push(frame.decode::<Packet>().unwrap());
+            } else if let Some(full) = reassembler.push((), frame.data, Instant::now()).unwrap() {
+                decoded.push(fragment::decode_reassembled(&full).unwrap());
+            }
+        }
+    }
+    assert_eq!(decoded, [packets()[1].clone(), big, packets()[1].clone()]);
+    assert_eq!(reassembler.pending(), 0);
+
+    batch.clear();
+    assert!(batch.is_empty());
+    assert_eq!(batch.datagrams().count(), 0);
+}
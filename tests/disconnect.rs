@@ -0,0 +1,114 @@
+//! Structured disconnects in both packet families and closing connections with them.
+
+use ngmp_protocol_impl::connection::{TcpConnection, UdpClient, UdpListener};
+use ngmp_protocol_impl::framing::decode_datagram;
+use ngmp_protocol_impl::server_launcher::generic::{ConfirmationPacket, DisconnectPacket};
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::{launcher_client, DisconnectReason, PacketDecodeError, PacketTrait};
+
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+fn shutdown() -> Packet {
+    Packet::Disconnect(DisconnectPacket {
+        reason: DisconnectReason::ServerShutdown,
+        message: "restarting".to_string(),
+    })
+}
+
+fn confirmation() -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id: 1 })
+}
+
+#[test]
+fn server_launcher_encoding() {
+    let (sig_a, sig_b, raw) = shutdown().to_raw().unwrap();
+    assert_eq!((sig_a, sig_b), ('D', 'C'));
+    assert_eq!(raw, b"\x02restarting");
+
+    assert!(matches!(
+        Packet::from_raw('D', 'C', b"\x09"),
+        Err(PacketDecodeError::InvalidNumber)
+    ));
+    assert!(matches!(
+        Packet::from_raw('D', 'C', b""),
+        Err(PacketDecodeError::UnexpectedEof)
+    ));
+}
+
+#[test]
+fn launcher_client_encoding() {
+    use launcher_client::generic::DisconnectPacket;
+    use launcher_client::Packet;
+
+    let packet = Packet::Disconnect(DisconnectPacket {
+        reason: DisconnectReason::VersionMismatch,
+        message: String::new(),
+    });
+    let (_, _, raw) = packet.to_raw().unwrap();
+    assert_eq!(raw, br#"{"reason":"version_mismatch"}"#);
+    assert_eq!(Packet::from_raw('D', 'C', &raw).unwrap(), packet);
+
+    assert!(Packet::from_raw('D', 'C', br#"{"reason":"bored"}"#).is_err());
+}
+
+#[tokio::test]
+async fn tcp_close_sends_disconnect_before_eof() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let server = TcpConnection::<Packet>::from_stream(server);
+    let mut client = TcpConnection::<Packet>::from_stream(client);
+
+    server.close_with(&shutdown()).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), shutdown());
+    assert!(client.wait_for_packet().await.is_err());
+}
+
+#[tokio::test]
+async fn udp_client_close_flushes_queued_packets() {
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, peer.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.queue_packet(&confirmation()).unwrap();
+    client.close_with(&shutdown()).await.unwrap();
+
+    let mut buf = [0u8; 1500];
+    let n = peer.recv(&mut buf).await.unwrap();
+    assert_eq!(
+        decode_datagram::<Packet>(&buf[..n]).unwrap(),
+        [confirmation(), shutdown()]
+    );
+}
+
+#[tokio::test]
+async fn listener_disconnect_sends_to_one_target() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+    listener.queue_packet(a_addr, &confirmation()).unwrap();
+    listener.queue_packet(b_addr, &confirmation()).unwrap();
+
+    listener.disconnect(a_addr, &shutdown()).await.unwrap();
+    let mut buf = [0u8; 1500];
+    let n = a.recv(&mut buf).await.unwrap();
+    assert_eq!(
+        decode_datagram::<Packet>(&buf[..n]).unwrap(),
+        [confirmation(), shutdown()]
+    );
+
+    // a's queue is gone, b's is untouched
+    let report = listener.flush().await;
+    assert_eq!(report.sent, 1);
+    let n = b.recv(&mut buf).await.unwrap();
+    assert_eq!(
+        decode_datagram::<Packet>(&buf[..n]).unwrap(),
+        [confirmation()]
+    );
+    let mut byte = [0u8; 1];
+    assert!(a.try_recv(&mut byte).is_err());
+}
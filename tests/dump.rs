@@ -0,0 +1,202 @@
+//! Running the ngmp-dump binary on a sample capture.
+
+use ngmp_protocol_impl::capture::{CaptureRecord, CaptureWriter, Direction, Transport};
+use ngmp_protocol_impl::crypto::{Sealer, Side, UdpKey};
+use ngmp_protocol_impl::framing::{encode_frame, DatagramBatch, HEARTBEAT};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleUpdatePacket;
+use ngmp_protocol_impl::server_launcher::generic::{ConfirmationPacket, PingPacket};
+use ngmp_protocol_impl::server_launcher::Packet;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use bytes::BytesMut;
+
+fn dump(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ngmp-dump"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn update(player_id: u64, runtime_data: String) -> Packet {
+    Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id,
+        vehicle_id: 2,
+        ms: 3,
+        runtime_data,
+    })
+}
+
+fn confirmation() -> Vec<u8> {
+    encode_frame(&Packet::Confirmation(ConfirmationPacket { confirm_id: 7 }))
+        .unwrap()
+        .to_vec()
+}
+
+/// Writes `records` to a temporary capture, one microsecond apart starting at one second.
+fn write_capture(name: &str, records: &[(Transport, &[u8])]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ngmp-dump-{}-{}.cap", name, std::process::id()));
+    let writer = CaptureWriter::create(&path).unwrap();
+    for (i, (transport, frame)) in records.iter().enumerate() {
+        writer
+            .write_record(&CaptureRecord {
+                timestamp_us: 1_000_000 + i as u64,
+                direction: Direction::Outbound,
+                transport: *transport,
+                peer: Some("127.0.0.1:30814".parse().unwrap()),
+                frame: frame.to_vec(),
+            })
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    path
+}
+
+/// A capture with a confirmation and a datagram holding updates of two players.
+fn sample_capture(name: &str) -> PathBuf {
+    let updates = [
+        encode_frame(&update(1, "r".to_string())).unwrap(),
+        encode_frame(&update(2, "r".to_string())).unwrap(),
+    ]
+    .concat();
+    write_capture(
+        name,
+        &[
+            (Transport::Tcp, &confirmation()),
+            (Transport::Udp, &updates),
+        ],
+    )
+}
+
+/// A capture with a packet, a heartbeat, a fragmented update coalesced with a ping
+/// and an encrypted frame.
+fn transport_capture(name: &str) -> PathBuf {
+    let mut fragments = DatagramBatch::new(200);
+    fragments.push(&update(1, "r".repeat(300))).unwrap();
+    fragments.push(&Packet::Ping(PingPacket { ms: 5 })).unwrap();
+    let mut sealed = BytesMut::new();
+    Sealer::new(&UdpKey::generate(), Side::Server)
+        .seal(&HEARTBEAT, &mut sealed)
+        .unwrap();
+
+    let confirmation = confirmation();
+    let mut records = vec![
+        (Transport::Tcp, &confirmation[..]),
+        (Transport::Tcp, &HEARTBEAT[..]),
+    ];
+    records.extend(fragments.datagrams().map(|d| (Transport::Udp, d)));
+    records.push((Transport::Udp, &sealed));
+    write_capture(name, &records)
+}
+
+fn dump_file(path: &Path, args: &[&str]) -> Output {
+    dump(&[&[path.to_str().unwrap()], args].concat())
+}
+
+#[test]
+fn help_exits_cleanly() {
+    for flag in ["-h", "--help"] {
+        let output = dump(&[flag]);
+        assert!(output.status.success());
+        assert!(stdout(&output).starts_with("usage: ngmp-dump"));
+    }
+}
+
+#[test]
+fn bad_arguments_exit_with_usage() {
+    let output = dump(&["--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown option --bogus"));
+}
+
+#[test]
+fn sample_capture_is_dumped() {
+    let path = sample_capture("dumped");
+    let output = dump_file(&path, &[]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let out = stdout(&output);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{}", out);
+    assert_eq!(
+        lines[0],
+        "1.000000 Outbound Tcp 127.0.0.1:30814 @0      CC len=2 Confirmation(ConfirmationPacket { confirm_id: 7 })"
+    );
+    assert!(lines[1].starts_with("1.000001 Outbound Udp 127.0.0.1:30814 @0      VU len="));
+    assert!(lines[1].contains("player_id: 1"));
+    assert!(lines[2].contains("player_id: 2"));
+}
+
+#[test]
+fn filters_select_packets() {
+    let path = sample_capture("filtered");
+    let by_sig = dump_file(&path, &["--sig", "CC"]);
+    let by_player = dump_file(&path, &["--player", "2", "--json"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout(&by_sig).lines().count(), 1);
+    let by_player = stdout(&by_player);
+    assert_eq!(by_player.lines().count(), 1, "{}", by_player);
+    assert!(by_player.contains("\"player_id\":2"));
+}
+
+#[test]
+fn transport_frames_are_labelled() {
+    let path = transport_capture("labelled");
+    let output = dump_file(&path, &[]);
+    let by_sig = dump_file(&path, &["--sig", "CC"]);
+    let by_player = dump_file(&path, &["--player", "1"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let out = stdout(&output);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 7, "{}", out);
+    assert!(lines[0].ends_with("CC len=2 Confirmation(ConfirmationPacket { confirm_id: 7 })"));
+    assert!(lines[1].ends_with("HB len=0 heartbeat"));
+    assert!(lines[2].contains("FG len="));
+    assert!(lines[2].contains("fragment 1 of 2"));
+    assert!(lines[3].contains("fragment 2 of 2"));
+    assert!(lines[4].contains(" reassembled @0      VU len=314 VehicleUpdate("));
+    // Coalesced behind the last fragment
+    assert!(lines[5].ends_with("PI len=4 Ping(PingPacket { ms: 5 })"));
+    assert!(lines[6].ends_with("encrypted (sequence 0)"));
+    assert!(!out.contains("error"), "{}", out);
+
+    // Transport frames aren't packets, so field filters only match the reassembled update
+    assert_eq!(stdout(&by_sig).lines().count(), 1);
+    let by_player = stdout(&by_player);
+    assert_eq!(by_player.lines().count(), 1, "{}", by_player);
+    assert!(by_player.contains("reassembled @0      VU"));
+}
+
+#[test]
+fn hex_input_is_decoded() {
+    let output = dump(&["--hex", "43 43 02 00 00 00 07 00", "--json"]);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "@0      CC len=2 {\"Confirmation\":{\"confirm_id\":7}}\n"
+    );
+}
+
+#[test]
+fn every_frame_is_shown() {
+    // A confirmation followed by an unknown signature
+    let output = dump(&["--hex", "43 43 02 00 00 00 07 00 5a 5a 01 00 00 00 ff"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{}", out);
+    assert!(lines[0].starts_with("@0      CC len=2 Confirmation"));
+    assert_eq!(
+        lines[1],
+        "@8      ZZ len=1 error: unknown packet (ZZ) (body at offset 14..15)"
+    );
+}
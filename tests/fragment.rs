@@ -0,0 +1,197 @@
+//! Fragmenting oversized frames and putting them back together.
+
+use ngmp_protocol_impl::fragment::{self, Reassembler};
+use ngmp_protocol_impl::framing::{datagram_frames, encode_frame, DatagramBatch};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleUpdatePacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::{ConnectionError, PacketEncodeError};
+
+use std::time::{Duration, Instant};
+
+fn update(len: usize) -> Packet {
+    Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id: 1,
+        vehicle_id: 2,
+        ms: 3,
+        runtime_data: "r".repeat(len),
+    })
+}
+
+/// The bodies of every fragment the packet gets split into.
+fn fragments(packet: &Packet, mtu: usize) -> Vec<Vec<u8>> {
+    let mut batch = DatagramBatch::new(mtu);
+    batch.push(packet).unwrap();
+    batch
+        .datagrams()
+        .flat_map(|datagram| datagram_frames(datagram).unwrap())
+        .inspect(|frame| assert!(fragment::is_fragment(frame)))
+        .map(|frame| frame.data.to_vec())
+        .collect()
+}
+
+fn reassemble(reassembler: &mut Reassembler<u8>, fragments: &[Vec<u8>]) -> Option<Vec<u8>> {
+    let now = Instant::now();
+    let mut result = None;
+    for fragment in fragments {
+        if let Some(frame) = reassembler.push(0, fragment, now).unwrap() {
+            assert!(result.is_none());
+            result = Some(frame);
+        }
+    }
+    result
+}
+
+#[test]
+fn reassembles_in_order() {
+    let packet = update(1000);
+    let fragments = fragments(&packet, 200);
+    assert!(fragments.len() > 1);
+
+    let mut reassembler = Reassembler::default();
+    let frame = reassemble(&mut reassembler, &fragments).unwrap();
+    assert_eq!(frame, encode_frame(&packet).unwrap());
+    assert_eq!(
+        fragment::decode_reassembled::<Packet>(&frame).unwrap(),
+        packet
+    );
+    assert_eq!(reassembler.pending(), 0);
+    assert_eq!(reassembler.buffered(), 0);
+}
+
+#[test]
+fn reassembles_out_of_order_and_ignores_duplicates() {
+    let packet = update(1000);
+    let mut fragments = fragments(&packet, 200);
+    fragments.reverse();
+    let duplicate = fragments[0].clone();
+    fragments.insert(1, duplicate);
+
+    let mut reassembler = Reassembler::default();
+    let frame = reassemble(&mut reassembler, &fragments).unwrap();
+    assert_eq!(
+        fragment::decode_reassembled::<Packet>(&frame).unwrap(),
+        packet
+    );
+}
+
+#[test]
+fn keeps_senders_apart() {
+    let packet = update(1000);
+    let fragments = fragments(&packet, 200);
+
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+    for fragment in &fragments[..fragments.len() - 1] {
+        assert_eq!(reassembler.push(1, fragment, now).unwrap(), None);
+    }
+    // Same message id, but from someone else
+    assert_eq!(
+        reassembler.push(2, fragments.last().unwrap(), now).unwrap(),
+        None
+    );
+    assert_eq!(reassembler.pending(), 2);
+    assert!(reassembler
+        .push(1, fragments.last().unwrap(), now)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn incomplete_messages_expire() {
+    let fragments = fragments(&update(1000), 200);
+    let mut reassembler = Reassembler::new(Duration::from_secs(1), usize::MAX);
+    let start = Instant::now();
+    reassembler.push(0, &fragments[0], start).unwrap();
+    assert_eq!(reassembler.pending(), 1);
+
+    reassembler.expire(start + Duration::from_millis(500));
+    assert_eq!(reassembler.pending(), 1);
+    reassembler.expire(start + Duration::from_secs(1));
+    assert_eq!(reassembler.pending(), 0);
+    assert_eq!(reassembler.buffered(), 0);
+
+    // The rest of the message arriving late doesn't bring it back
+    let late = start + Duration::from_secs(2);
+    for fragment in &fragments[1..] {
+        assert_eq!(reassembler.push(0, fragment, late).unwrap(), None);
+    }
+}
+
+#[test]
+fn memory_cap_evicts_oldest_message() {
+    let first = fragments(&update(1000), 200);
+    let second = fragments(&update(1000), 200);
+    let mut reassembler = Reassembler::new(Duration::from_secs(60), 1200);
+    let start = Instant::now();
+
+    for fragment in &first[..first.len() - 1] {
+        reassembler.push(0, fragment, start).unwrap();
+    }
+    let later = start + Duration::from_millis(1);
+    let frame = second
+        .iter()
+        .filter_map(|fragment| reassembler.push(0, fragment, later).unwrap())
+        .next();
+    assert!(frame.is_some());
+    assert!(reassembler.buffered() <= 1200);
+
+    // The first message got evicted to make room
+    assert_eq!(
+        reassembler.push(0, first.last().unwrap(), later).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn rejects_invalid_fragments() {
+    let mut reassembler = Reassembler::<u8>::default();
+    let now = Instant::now();
+    // Too short
+    assert!(matches!(
+        reassembler.push(0, &[0; 8], now),
+        Err(ConnectionError::InvalidFragment)
+    ));
+    // Index past the count
+    let mut fragment = vec![0, 0, 0, 0, 2, 0, 2, 0];
+    fragment.push(b'x');
+    assert!(matches!(
+        reassembler.push(0, &fragment, now),
+        Err(ConnectionError::InvalidFragment)
+    ));
+    // Count changing halfway through a message
+    assert_eq!(
+        reassembler
+            .push(0, &[0, 0, 0, 0, 0, 0, 3, 0, b'x'], now)
+            .unwrap(),
+        None
+    );
+    assert!(matches!(
+        reassembler.push(0, &[0, 0, 0, 0, 1, 0, 4, 0, b'x'], now),
+        Err(ConnectionError::InvalidFragment)
+    ));
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[test]
+fn mtu_too_small_for_fragments() {
+    let mut batch = DatagramBatch::new(fragment::OVERHEAD);
+    assert!(matches!(
+        batch.push(&update(100)),
+        Err(PacketEncodeError::FrameTooLarge(_))
+    ));
+    assert!(batch.is_empty());
+}
+
+#[test]
+fn removing_a_sender_drops_their_messages() {
+    let fragments = fragments(&update(1000), 200);
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+    reassembler.push(1, &fragments[0], now).unwrap();
+    reassembler.push(2, &fragments[0], now).unwrap();
+    let buffered = reassembler.buffered();
+
+    reassembler.remove_sender(&1);
+    assert_eq!(reassembler.pending(), 1);
+    assert_eq!(reassembler.buffered(), buffered / 2);
+}
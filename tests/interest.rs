@@ -0,0 +1,222 @@
+//! Area of interest filtering on synthetic grids of players and vehicles.
+
+use ngmp_protocol_impl::interest::{InterestConfig, InterestLevel, InterestManager};
+use ngmp_protocol_impl::server_launcher::gameplay::{Transform, VehicleTransformPacket};
+use ngmp_protocol_impl::{ConfigError, PacketDecodeError};
+
+fn config() -> InterestConfig {
+    InterestConfig {
+        cell_size: 50.0,
+        full_radius: 100.0,
+        reduced_radius: 300.0,
+        reduced_every: 3,
+    }
+}
+
+/// One player every `spacing` metres on a `size` x `size` grid.
+fn grid(size: u32, spacing: f32) -> InterestManager<u32, u32> {
+    let mut interest = InterestManager::new(config()).unwrap();
+    for x in 0..size {
+        for y in 0..size {
+            let player = x * size + y;
+            interest.set_player(player, [x as f32 * spacing, y as f32 * spacing, 0.0]);
+        }
+    }
+    interest
+}
+
+#[test]
+fn levels_follow_distance() {
+    let mut interest = InterestManager::new(config()).unwrap();
+    interest.set_player(1, [0.0, 0.0, 0.0]);
+    interest.set_player(2, [150.0, 0.0, 0.0]);
+    interest.set_player(3, [0.0, 1000.0, 0.0]);
+    interest.set_player(4, [0.0, 0.0, 250.0]);
+    interest.update_vehicle(10, 1, [50.0, 0.0, 0.0]);
+
+    // The owner already knows where its vehicle is
+    assert_eq!(interest.level(&1, &10), InterestLevel::None);
+    assert_eq!(interest.level(&2, &10), InterestLevel::Full);
+    assert_eq!(interest.level(&3, &10), InterestLevel::None);
+    // Height counts towards the distance too
+    assert_eq!(interest.level(&4, &10), InterestLevel::Reduced);
+    // Unknown players and vehicles get nothing
+    assert_eq!(interest.level(&5, &10), InterestLevel::None);
+    assert_eq!(interest.level(&2, &11), InterestLevel::None);
+}
+
+#[test]
+fn grid_lookup_matches_brute_force() {
+    let mut interest = grid(20, 40.0);
+    let vehicles = [
+        [0.0, 0.0, 0.0],
+        [400.0, 400.0, 0.0],
+        [-120.0, 390.0, 0.0],
+        [760.0, 10.0, 30.0],
+        [2000.0, 2000.0, 0.0],
+    ];
+    for (vehicle, pos) in vehicles.into_iter().enumerate() {
+        interest.update_vehicle(vehicle as u32, 0, pos);
+    }
+
+    for vehicle in 0..vehicles.len() as u32 {
+        let mut recipients = interest.recipients(&vehicle);
+        recipients.sort_by_key(|(player, _)| *player);
+        let expected: Vec<_> = (0..400)
+            .map(|player| (player, interest.level(&player, &vehicle)))
+            .filter(|(_, level)| *level != InterestLevel::None)
+            .collect();
+        assert_eq!(recipients, expected, "vehicle {vehicle}");
+    }
+}
+
+#[test]
+fn reduced_players_get_every_nth_update() {
+    let mut interest = InterestManager::new(config()).unwrap();
+    interest.set_player(1, [0.0, 0.0, 0.0]);
+    interest.set_player(2, [200.0, 0.0, 0.0]);
+    interest.update_vehicle(10, 0, [0.0, 0.0, 0.0]);
+
+    let mut counts = [0, 0];
+    for _ in 0..9 {
+        for player in interest.targets(&10) {
+            counts[player as usize - 1] += 1;
+        }
+    }
+    assert_eq!(counts, [9, 3]);
+}
+
+#[test]
+fn moving_players_change_cells() {
+    let mut interest = InterestManager::new(config()).unwrap();
+    interest.set_player(1, [1000.0, 1000.0, 0.0]);
+    interest.update_vehicle(10, 0, [0.0, 0.0, 0.0]);
+    assert!(interest.recipients(&10).is_empty());
+
+    interest.set_player(1, [10.0, 10.0, 0.0]);
+    assert_eq!(interest.recipients(&10), [(1, InterestLevel::Full)]);
+    // Moving within the same cell
+    interest.set_player(1, [20.0, 20.0, 0.0]);
+    assert_eq!(interest.recipients(&10), [(1, InterestLevel::Full)]);
+
+    interest.set_player(1, [-1000.0, 0.0, 0.0]);
+    assert!(interest.recipients(&10).is_empty());
+}
+
+#[test]
+fn removing_a_player_drops_their_vehicles() {
+    let mut interest = grid(3, 10.0);
+    interest.update_vehicle(10, 0, [0.0, 0.0, 0.0]);
+    interest.update_vehicle(11, 1, [0.0, 0.0, 0.0]);
+    assert_eq!(interest.recipients(&10).len(), 8);
+
+    interest.remove_player(&0);
+    assert!(interest.recipients(&10).is_empty());
+    assert_eq!(interest.recipients(&11).len(), 7);
+    assert!(interest.targets(&10).is_empty());
+
+    interest.remove_vehicle(&11);
+    assert!(interest.recipients(&11).is_empty());
+}
+
+#[test]
+fn position_from_transform() {
+    let packet = VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 2,
+        transform: r#"{"pos":[1.5,2,3],"rot":[0,0,0,1],"extra":true}"#.to_string(),
+    };
+    let transform = packet.parse_transform().unwrap();
+    assert_eq!(transform.pos, [1.5, 2.0, 3.0]);
+    assert_eq!(transform.rot, [0.0, 0.0, 0.0, 1.0]);
+    assert_eq!(transform.vel, [0.0; 3]);
+
+    assert!(Transform::parse(r#"{"rot":[0,0,0,1]}"#).is_err());
+    // Too large for an f32
+    assert!(matches!(
+        Transform::parse(r#"{"pos":[1e39,0,0]}"#),
+        Err(PacketDecodeError::InvalidNumber)
+    ));
+    assert!(matches!(
+        Transform::parse(r#"{"pos":[0,0,0],"vel":[0,-1e39,0]}"#),
+        Err(PacketDecodeError::InvalidNumber)
+    ));
+}
+
+#[test]
+fn huge_ranges_only_look_at_known_players() {
+    // Far more cells in range than could ever be scanned
+    let config = InterestConfig {
+        cell_size: 1.0,
+        full_radius: 1e6,
+        reduced_radius: 1e7,
+        reduced_every: 1,
+    };
+    let mut interest = InterestManager::new(config).unwrap();
+    interest.set_player(1, [0.0, 0.0, 0.0]);
+    interest.set_player(2, [5e6, 0.0, 0.0]);
+    interest.update_vehicle(10, 0, [0.0, 0.0, 0.0]);
+    interest.update_vehicle(11, 0, [f32::MAX, f32::MAX, 0.0]);
+    interest.update_vehicle(12, 0, [f32::NAN, 0.0, 0.0]);
+
+    let mut recipients = interest.recipients(&10);
+    recipients.sort_by_key(|(player, _)| *player);
+    assert_eq!(
+        recipients,
+        [(1, InterestLevel::Full), (2, InterestLevel::Reduced)]
+    );
+    assert!(interest.recipients(&11).is_empty());
+    assert!(interest.recipients(&12).is_empty());
+}
+
+#[test]
+fn invalid_cell_sizes_are_rejected() {
+    for cell_size in [0.0, -50.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        let config = InterestConfig {
+            cell_size,
+            ..config()
+        };
+        assert!(
+            matches!(
+                InterestManager::<u32, u32>::new(config),
+                Err(ConfigError::InvalidCellSize(_))
+            ),
+            "cell size {}",
+            cell_size
+        );
+    }
+    assert!(InterestManager::<u32, u32>::new(InterestConfig::default()).is_ok());
+}
+
+#[test]
+fn invalid_radii_are_rejected() {
+    for (full_radius, reduced_radius) in [
+        (-1.0, 300.0),
+        (400.0, 300.0),
+        (f32::NAN, 300.0),
+        (100.0, f32::NAN),
+        (100.0, f32::INFINITY),
+    ] {
+        let config = InterestConfig {
+            full_radius,
+            reduced_radius,
+            ..config()
+        };
+        assert!(
+            matches!(
+                InterestManager::<u32, u32>::new(config),
+                Err(ConfigError::InvalidRadii { .. })
+            ),
+            "radii {} and {}",
+            full_radius,
+            reduced_radius
+        );
+    }
+    // Only players right on top of a vehicle get its updates, still valid
+    let config = InterestConfig {
+        full_radius: 0.0,
+        reduced_radius: 0.0,
+        ..config()
+    };
+    assert!(InterestManager::<u32, u32>::new(config).is_ok());
+}
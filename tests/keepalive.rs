@@ -0,0 +1,159 @@
+//! Heartbeats and idle timeouts over loopback, on a paused clock.
+
+use ngmp_protocol_impl::connection::{KeepaliveConfig, TcpConnection, UdpClient, UdpListener};
+use ngmp_protocol_impl::crypto::{Sealer, Side, UdpKey};
+use ngmp_protocol_impl::framing::{encode_frame, HEARTBEAT};
+use ngmp_protocol_impl::server_launcher::generic::ConfirmationPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::ConnectionError;
+
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::Instant;
+
+fn confirmation() -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id: 7 })
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<ConnectionError>(),
+        Some(ConnectionError::Timeout)
+    )
+}
+
+async fn tcp_pair() -> (TcpConnection<Packet>, TcpConnection<Packet>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    (
+        TcpConnection::from_stream(server),
+        TcpConnection::from_stream(client),
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_tcp_peer_times_out() {
+    let (mut server, _client) = tcp_pair().await;
+    server.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: None,
+        idle_timeout: Some(Duration::from_millis(100)),
+    });
+    let e = server.wait_for_packet().await.unwrap_err();
+    assert!(is_timeout(&e), "{e}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn tcp_heartbeats_keep_the_connection_alive() {
+    let (mut server, mut client) = tcp_pair().await;
+    server.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: None,
+        idle_timeout: Some(Duration::from_millis(150)),
+    });
+    client.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: Some(Duration::from_millis(30)),
+        idle_timeout: None,
+    });
+
+    let client = tokio::spawn(async move {
+        // Only waiting, the heartbeats get sent in the meantime
+        let _ = tokio::time::timeout(Duration::from_millis(500), client.wait_for_packet()).await;
+        client.write_packet(&confirmation()).await.unwrap();
+        client
+    });
+    // Well past the idle timeout, but the heartbeats never make it up to here
+    assert_eq!(server.wait_for_packet().await.unwrap(), confirmation());
+    drop(client.await.unwrap());
+}
+
+#[tokio::test(start_paused = true)]
+async fn polling_tcp_connection_sends_heartbeats() {
+    let (mut server, mut client) = tcp_pair().await;
+    client.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: Some(Duration::from_millis(10)),
+        idle_timeout: None,
+    });
+    client.keepalive().await.unwrap();
+    tokio::time::advance(Duration::from_millis(20)).await;
+    client.keepalive().await.unwrap();
+    client.write_packet(&confirmation()).await.unwrap();
+
+    // Both the heartbeat and the packet are there, only the packet comes out
+    assert_eq!(server.wait_for_packet().await.unwrap(), confirmation());
+    assert!(server.try_read_packet().await.unwrap().is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_udp_peer_times_out() {
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, peer.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: Some(Duration::from_millis(20)),
+        idle_timeout: Some(Duration::from_millis(100)),
+    });
+    let e = client.wait_for_packet().await.unwrap_err();
+    assert!(is_timeout(&e), "{e}");
+
+    // Heartbeats went out while it was waiting
+    let mut buf = [0u8; 64];
+    let n = peer.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], HEARTBEAT);
+}
+
+#[tokio::test]
+async fn listener_skips_udp_heartbeats() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.write_bytes(&HEARTBEAT).await.unwrap();
+    let mut datagram = HEARTBEAT.to_vec();
+    datagram.extend_from_slice(&encode_frame(&confirmation()).unwrap());
+    client.write_bytes(&datagram).await.unwrap();
+
+    let (packet, _) = listener.wait_for_packet().await.unwrap();
+    assert_eq!(packet, confirmation());
+}
+
+#[tokio::test(start_paused = true)]
+async fn replayed_udp_frames_dont_keep_the_connection_alive() {
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, peer.local_addr().unwrap())
+        .await
+        .unwrap();
+    let key = UdpKey::generate();
+    client.set_key(&key).unwrap();
+    client.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: None,
+        idle_timeout: Some(Duration::from_millis(100)),
+    });
+
+    let mut sealed = BytesMut::new();
+    Sealer::new(&key, Side::Server)
+        .seal(&encode_frame(&confirmation()).unwrap(), &mut sealed)
+        .unwrap();
+    peer.send_to(&sealed, client_addr).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), confirmation());
+
+    // Someone replaying the frame can't hold the connection open
+    let start = Instant::now();
+    tokio::spawn(async move {
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            peer.send_to(&sealed, client_addr).await.unwrap();
+        }
+    });
+    let e = client.wait_for_packet().await.unwrap_err();
+    assert!(is_timeout(&e), "{e}");
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
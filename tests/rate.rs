@@ -0,0 +1,238 @@
+//! Adapting the send rate to loss and scheduling vehicle updates by distance.
+
+use ngmp_protocol_impl::connection::UdpClient;
+use ngmp_protocol_impl::framing::decode_datagram;
+use ngmp_protocol_impl::rate::{RateConfig, RateController, RateLimitedClient, UpdateSchedule};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleTransformPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::ConfigError;
+
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+#[test]
+fn rate_grows_without_loss() {
+    let start = Instant::now();
+    let mut controller = RateController::new(RateConfig::default(), start);
+    let initial = controller.rate();
+    for i in 1..=4 {
+        controller.on_ack(10_000, start + INTERVAL * i);
+    }
+    assert!(controller.rate() > initial);
+    assert_eq!(controller.loss(), 0.0);
+    assert!(controller.estimated_bandwidth() > 0.0);
+    assert_eq!(controller.congestion(), 1.0);
+}
+
+#[test]
+fn rate_backs_off_on_loss() {
+    let start = Instant::now();
+    let config = RateConfig::default();
+    let mut controller = RateController::new(config, start);
+    controller.on_ack(10_000, start + INTERVAL);
+    let before = controller.rate();
+
+    controller.on_ack(7_000, start + INTERVAL + Duration::from_millis(1));
+    controller.on_loss(3_000, start + INTERVAL * 2);
+    assert!(controller.rate() < before);
+    assert!(controller.loss() > 0.0);
+    assert!(controller.congestion() > 1.0);
+
+    // Never below the configured minimum, no matter how bad it gets
+    for i in 3..50 {
+        controller.on_loss(10_000, start + INTERVAL * i);
+    }
+    assert_eq!(controller.rate(), config.min_rate);
+}
+
+#[test]
+fn loss_below_threshold_is_tolerated() {
+    let start = Instant::now();
+    let mut controller = RateController::new(RateConfig::default(), start);
+    let initial = controller.rate();
+    controller.on_ack(99_000, start);
+    controller.on_loss(1_000, start + INTERVAL);
+    assert!(controller.rate() > initial);
+}
+
+#[test]
+fn budget_refills_at_the_current_rate() {
+    let start = Instant::now();
+    let config = RateConfig {
+        initial_rate: 1000.0,
+        min_rate: 1000.0,
+        burst: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let mut controller = RateController::new(config, start);
+    assert_eq!(controller.budget(start), 100);
+    assert!(controller.try_consume(100, start));
+    assert!(!controller.try_consume(1, start));
+
+    assert_eq!(controller.budget(start + Duration::from_millis(50)), 50);
+    // Unused budget only piles up to the burst size
+    assert_eq!(controller.budget(start + Duration::from_secs(10)), 100);
+}
+
+#[test]
+fn distant_and_congested_vehicles_update_less_often() {
+    let schedule = UpdateSchedule {
+        base_interval: Duration::from_millis(50),
+        falloff_distance: 100.0,
+        max_interval: Duration::from_secs(1),
+    };
+    assert_eq!(schedule.interval(0.0, 1.0), Duration::from_millis(50));
+    assert_eq!(schedule.interval(100.0, 1.0), Duration::from_millis(100));
+    assert_eq!(schedule.interval(100.0, 2.0), Duration::from_millis(200));
+    assert_eq!(schedule.interval(10_000.0, 1.0), Duration::from_secs(1));
+}
+
+#[test]
+fn unknown_distances_get_the_max_interval() {
+    let schedule = UpdateSchedule::default();
+    for distance in [f32::INFINITY, f32::NEG_INFINITY, f32::NAN, f32::MAX] {
+        assert_eq!(
+            schedule.interval(distance, 1.0),
+            schedule.max_interval,
+            "distance {}",
+            distance
+        );
+    }
+    assert_eq!(schedule.interval(0.0, f64::INFINITY), schedule.max_interval);
+    assert_eq!(schedule.interval(0.0, f64::NAN), schedule.base_interval);
+}
+
+#[tokio::test]
+async fn invalid_falloff_distances_are_rejected() {
+    for falloff_distance in [0.0, -1.0, f32::INFINITY, f32::NAN] {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let client = UdpClient::<Packet>::connect(socket, addr).await.unwrap();
+        let schedule = UpdateSchedule {
+            falloff_distance,
+            ..Default::default()
+        };
+        let result = RateLimitedClient::<Packet, u16>::new(client, RateConfig::default(), schedule);
+        assert!(
+            matches!(result, Err(ConfigError::InvalidFalloffDistance(_))),
+            "falloff {}",
+            falloff_distance
+        );
+    }
+}
+
+fn transform(vehicle_id: u16) -> Packet {
+    Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id,
+        transform: "x".repeat(44),
+    })
+}
+
+#[tokio::test]
+async fn nearest_vehicle_goes_first_when_budget_is_tight() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UdpClient::<Packet>::connect(socket, receiver.local_addr().unwrap())
+        .await
+        .unwrap();
+    // Room for a single 60 byte packet per tick
+    let config = RateConfig {
+        initial_rate: 1000.0,
+        min_rate: 1000.0,
+        max_rate: 1000.0,
+        burst: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let mut client = RateLimitedClient::new(client, config, UpdateSchedule::default()).unwrap();
+    client.update_vehicle(3, transform(3), 300.0);
+    client.update_vehicle(1, transform(1), 5.0);
+    client.update_vehicle(2, transform(2), 50.0);
+
+    let mut buf = [0u8; 1500];
+    let mut order = Vec::new();
+    let mut now = Instant::now();
+    while order.len() < 3 {
+        if client.tick(now).await.unwrap() > 0 {
+            let n = receiver.recv(&mut buf).await.unwrap();
+            for packet in decode_datagram::<Packet>(&buf[..n]).unwrap() {
+                let Packet::VehicleTransform(transform) = packet else {
+                    panic!("unexpected packet");
+                };
+                order.push(transform.vehicle_id);
+            }
+        }
+        now += Duration::from_millis(60);
+    }
+    assert_eq!(order, [1, 2, 3]);
+
+    // Nothing new to send
+    assert_eq!(client.tick(now + Duration::from_secs(1)).await.unwrap(), 0);
+}
+
+#[test]
+fn oversized_packets_go_once_the_budget_is_full() {
+    let start = Instant::now();
+    let config = RateConfig {
+        initial_rate: 1000.0,
+        min_rate: 1000.0,
+        max_rate: 1000.0,
+        burst: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let mut controller = RateController::new(config, start);
+    assert!(controller.try_consume(250, start));
+    // Paying off the debt before anything else goes
+    assert_eq!(controller.budget(start + Duration::from_millis(150)), 0);
+    assert!(!controller.try_consume(250, start + Duration::from_millis(150)));
+    assert_eq!(controller.budget(start + Duration::from_millis(200)), 50);
+}
+
+#[tokio::test]
+async fn oversized_update_does_not_block_the_others() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client = UdpClient::<Packet>::connect(socket, receiver.local_addr().unwrap())
+        .await
+        .unwrap();
+    // 100 bytes of budget at most, less than the big update alone
+    let config = RateConfig {
+        initial_rate: 1000.0,
+        min_rate: 1000.0,
+        max_rate: 1000.0,
+        burst: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let mut client = RateLimitedClient::new(client, config, UpdateSchedule::default()).unwrap();
+    let big = Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 0,
+        transform: "x".repeat(200),
+    });
+    client.update_vehicle(0, big, 0.0);
+    for vehicle_id in 1..=4 {
+        client.update_vehicle(vehicle_id, transform(vehicle_id), 10.0 * vehicle_id as f32);
+    }
+
+    let mut buf = [0u8; 1500];
+    let mut sent = Vec::new();
+    let mut now = Instant::now();
+    for _ in 0..40 {
+        if client.tick(now).await.unwrap() > 0 {
+            let n = receiver.recv(&mut buf).await.unwrap();
+            for packet in decode_datagram::<Packet>(&buf[..n]).unwrap() {
+                let Packet::VehicleTransform(transform) = packet else {
+                    panic!("unexpected packet");
+                };
+                sent.push(transform.vehicle_id);
+            }
+        }
+        now += Duration::from_millis(60);
+    }
+    sent.sort();
+    sent.dedup();
+    assert_eq!(sent, [0, 1, 2, 3, 4]);
+}
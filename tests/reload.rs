@@ -0,0 +1,202 @@
+//! The game asking the launcher to reload their connection.
+
+use ngmp_protocol_impl::connection::TcpConnection;
+use ngmp_protocol_impl::launcher_client::gameplay::*;
+use ngmp_protocol_impl::launcher_client::generic::*;
+use ngmp_protocol_impl::launcher_client::handshake::*;
+use ngmp_protocol_impl::launcher_client::reload::{ClientSessionState, GameConnection};
+use ngmp_protocol_impl::launcher_client::Packet;
+use ngmp_protocol_impl::{ConnectionError, DisconnectReason};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+
+const PROTOCOL_VERSION: u16 = 3;
+
+fn auth() -> Packet {
+    Packet::AuthenticationInfo(AuthenticationInfoPacket {
+        success: true,
+        player_name: "player".to_string(),
+        steam_id: "1".to_string(),
+        avatar_hash: String::new(),
+    })
+}
+
+fn map() -> Packet {
+    Packet::LoadMap(LoadMapPacket {
+        confirm_id: 1,
+        map_string: "/levels/gridmap_v2/info.json".to_string(),
+    })
+}
+
+fn spawn(steam_id: &str, vehicle_id: u16) -> VehicleSpawnPacket {
+    VehicleSpawnPacket {
+        confirm_id: 2,
+        steam_id: steam_id.to_string(),
+        vehicle_id,
+        vehicle_data: VehicleData {
+            jbeam: "pickup".to_string(),
+            object_id: 0,
+            paints: String::new(),
+            part_config: String::new(),
+            pos: [0.0; 3],
+            rot: [0.0, 0.0, 0.0, 1.0],
+        },
+    }
+}
+
+fn transform(steam_id: &str, vehicle_id: u16, transform: &str) -> VehicleTransformPacket {
+    VehicleTransformPacket {
+        steam_id: steam_id.to_string(),
+        vehicle_id,
+        transform: transform.to_string(),
+    }
+}
+
+/// Connects the way the game does and returns the launcher's answer to its version.
+async fn game_connect(addr: SocketAddr, protocol_version: u16) -> (TcpConnection<Packet>, Packet) {
+    let mut game = TcpConnection::from_stream(TcpStream::connect(addr).await.unwrap());
+    game.write_packet(&Packet::Version(VersionPacket { protocol_version }))
+        .await
+        .unwrap();
+    let answer = game.wait_for_packet().await.unwrap();
+    if matches!(answer, Packet::Version(_)) {
+        game.write_packet(&Packet::ClientInfo(ClientInfoPacket {
+            userfolder: "C:/Users/player".to_string(),
+            client_version: 1,
+        }))
+        .await
+        .unwrap();
+    }
+    (game, answer)
+}
+
+#[test]
+fn state_follows_what_the_game_was_told() {
+    let mut state = ClientSessionState::default();
+    state.observe(&auth());
+    state.observe(&map());
+    state.observe(&Packet::VehicleSpawn(spawn("1", 0)));
+    state.observe(&Packet::VehicleSpawn(spawn("2", 0)));
+    state.observe(&Packet::VehicleSpawn(spawn("2", 1)));
+    state.observe(&Packet::VehicleTransform(transform("2", 0, "a")));
+    state.observe(&Packet::VehicleTransform(transform("2", 0, "b")));
+    state.observe(&Packet::VehicleDelete(VehicleDeletePacket {
+        steam_id: "1".to_string(),
+        vehicle_id: 0,
+    }));
+    // Transforms of unknown vehicles are ignored
+    state.observe(&Packet::VehicleTransform(transform("3", 0, "c")));
+
+    assert_eq!(
+        state.restore_packets(),
+        [
+            auth(),
+            map(),
+            Packet::VehicleSpawn(spawn("2", 0)),
+            Packet::VehicleTransform(transform("2", 0, "b")),
+            Packet::VehicleSpawn(spawn("2", 1)),
+        ]
+    );
+
+    // A new map starts from scratch
+    state.observe(&map());
+    assert_eq!(state.restore_packets(), [auth(), map()]);
+
+    state.observe(&Packet::Disconnect(DisconnectPacket {
+        reason: DisconnectReason::Kicked,
+        message: String::new(),
+    }));
+    assert!(state.restore_packets().is_empty());
+}
+
+#[tokio::test]
+async fn reload_replays_session_state() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let game = tokio::spawn(async move {
+        let (mut game, answer) = game_connect(addr, PROTOCOL_VERSION).await;
+        assert_eq!(
+            answer,
+            Packet::Version(VersionPacket {
+                protocol_version: PROTOCOL_VERSION
+            })
+        );
+        assert_eq!(game.wait_for_packet().await.unwrap(), auth());
+        assert_eq!(game.wait_for_packet().await.unwrap(), map());
+
+        game.write_packet(&Packet::ReloadLauncherConnection)
+            .await
+            .unwrap();
+        let (mut game, _) = game_connect(addr, PROTOCOL_VERSION).await;
+        assert_eq!(game.wait_for_packet().await.unwrap(), auth());
+        assert_eq!(game.wait_for_packet().await.unwrap(), map());
+        assert_eq!(
+            game.wait_for_packet().await.unwrap(),
+            Packet::VehicleSpawn(spawn("2", 0))
+        );
+        game.write_packet(&Packet::LoginRequest).await.unwrap();
+    });
+
+    let mut launcher = GameConnection::accept(listener, PROTOCOL_VERSION)
+        .await
+        .unwrap();
+    assert_eq!(launcher.client_info().userfolder, "C:/Users/player");
+    launcher.write_packet(&auth()).await.unwrap();
+    launcher.write_packet(&map()).await.unwrap();
+    // Written before the game asked for the reload, but never read by it
+    launcher
+        .write_packet(&Packet::VehicleSpawn(spawn("2", 0)))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        launcher.wait_for_packet().await.unwrap(),
+        Packet::LoginRequest
+    );
+    assert_eq!(launcher.reloads(), 1);
+    game.await.unwrap();
+}
+
+#[tokio::test]
+async fn version_mismatch_is_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let game = tokio::spawn(async move { game_connect(addr, PROTOCOL_VERSION + 1).await.1 });
+    assert!(GameConnection::accept(listener, PROTOCOL_VERSION)
+        .await
+        .is_err());
+    let Packet::Disconnect(disconnect) = game.await.unwrap() else {
+        panic!("expected a disconnect");
+    };
+    assert_eq!(disconnect.reason, DisconnectReason::VersionMismatch);
+}
+
+#[tokio::test]
+async fn game_not_coming_back_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let game = tokio::spawn(async move {
+        let (mut game, _) = game_connect(addr, PROTOCOL_VERSION).await;
+        game.write_packet(&Packet::ReloadLauncherConnection)
+            .await
+            .unwrap();
+        game
+    });
+
+    let mut launcher = GameConnection::accept(listener, PROTOCOL_VERSION)
+        .await
+        .unwrap();
+    launcher.set_reload_timeout(Duration::from_millis(50));
+    let e = launcher.wait_for_packet().await.unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<ConnectionError>(),
+        Some(ConnectionError::Timeout)
+    ));
+    drop(game.await.unwrap());
+}
@@ -0,0 +1,227 @@
+//! Replaying captured packets in order and with their recorded timing.
+
+use ngmp_protocol_impl::capture::{
+    CaptureReader, CaptureRecord, CaptureWriter, Direction, Transport,
+};
+use ngmp_protocol_impl::connection::TcpConnection;
+use ngmp_protocol_impl::framing::{encode_frame, DatagramBatch, HEARTBEAT};
+use ngmp_protocol_impl::replay::{ReplaySpeed, Replayer};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleUpdatePacket;
+use ngmp_protocol_impl::server_launcher::generic::ConfirmationPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+fn confirmation(confirm_id: u16) -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id })
+}
+
+fn record(
+    timestamp_us: u64,
+    direction: Direction,
+    transport: Transport,
+    frame: &[u8],
+) -> CaptureRecord {
+    CaptureRecord {
+        timestamp_us,
+        direction,
+        transport,
+        peer: None,
+        frame: frame.to_vec(),
+    }
+}
+
+fn capture(records: &[CaptureRecord]) -> Vec<u8> {
+    let writer = CaptureWriter::new(Vec::new()).unwrap();
+    for record in records {
+        writer.write_record(record).unwrap();
+    }
+    writer.into_inner()
+}
+
+async fn drain(replayer: &mut Replayer<Packet>) -> Vec<(Duration, Packet)> {
+    let started = Instant::now();
+    let mut packets = Vec::new();
+    while let Some(packet) = replayer.next_packet().await {
+        packets.push((started.elapsed(), packet));
+    }
+    packets
+}
+
+async fn send_times(replayer: &mut Replayer<Packet>) -> Vec<Duration> {
+    drain(replayer).await.into_iter().map(|(t, _)| t).collect()
+}
+
+/// Confirmation packets one second apart.
+fn one_per_second(count: u16) -> Vec<(u64, Packet)> {
+    (0..count)
+        .map(|i| (1_000_000 + i as u64 * 1_000_000, confirmation(i)))
+        .collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn packets_come_out_in_order() {
+    let mut replayer = Replayer::new(one_per_second(3), ReplaySpeed::Instant);
+    assert_eq!(replayer.remaining(), 3);
+    let packets = drain(&mut replayer).await;
+    assert_eq!(
+        packets.into_iter().map(|(_, p)| p).collect::<Vec<_>>(),
+        vec![confirmation(0), confirmation(1), confirmation(2)]
+    );
+    assert_eq!(replayer.remaining(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn realtime_keeps_the_recorded_gaps() {
+    let mut replayer = Replayer::new(one_per_second(3), ReplaySpeed::Realtime);
+    let times = send_times(&mut replayer).await;
+    assert_eq!(times, [0, 1, 2].map(Duration::from_secs).to_vec());
+}
+
+#[tokio::test(start_paused = true)]
+async fn scaled_divides_the_gaps() {
+    let mut replayer = Replayer::new(one_per_second(3), ReplaySpeed::Scaled(4.0));
+    let times = send_times(&mut replayer).await;
+    assert_eq!(times, [0, 250, 500].map(Duration::from_millis).to_vec());
+
+    let mut replayer = Replayer::new(one_per_second(3), ReplaySpeed::Instant);
+    let times = send_times(&mut replayer).await;
+    assert_eq!(times, vec![Duration::ZERO; 3]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn extreme_speed_factors_do_not_panic() {
+    for factor in [
+        f64::MIN_POSITIVE,
+        1e-300,
+        f64::MAX,
+        f64::INFINITY,
+        f64::NAN,
+        -1.0,
+    ] {
+        let mut replayer = Replayer::new(one_per_second(2), ReplaySpeed::Scaled(factor));
+        assert_eq!(drain(&mut replayer).await.len(), 2, "factor {}", factor);
+    }
+
+    let far_apart = vec![(0, confirmation(0)), (u64::MAX, confirmation(1))];
+    let mut replayer = Replayer::new(far_apart, ReplaySpeed::Realtime);
+    assert_eq!(drain(&mut replayer).await.len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn capture_is_filtered_by_direction_and_transport() {
+    let frame = |id| encode_frame(&confirmation(id)).unwrap();
+    let bytes = capture(&[
+        record(1_000_000, Direction::Outbound, Transport::Tcp, &frame(0)),
+        record(1_100_000, Direction::Inbound, Transport::Tcp, &frame(1)),
+        record(1_200_000, Direction::Outbound, Transport::Udp, &frame(2)),
+        record(1_500_000, Direction::Outbound, Transport::Tcp, &frame(3)),
+    ]);
+
+    let reader = CaptureReader::new(&bytes[..]).unwrap();
+    let mut replayer = Replayer::from_capture(
+        reader,
+        Direction::Outbound,
+        Transport::Tcp,
+        ReplaySpeed::Realtime,
+    )
+    .unwrap();
+    assert_eq!(
+        drain(&mut replayer).await,
+        vec![
+            (Duration::ZERO, confirmation(0)),
+            (Duration::from_millis(500), confirmation(3)),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn fragmented_and_coalesced_packets_are_replayed() {
+    let update = Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id: 1,
+        vehicle_id: 2,
+        ms: 3,
+        runtime_data: "r".repeat(500),
+    });
+    let mut batch = DatagramBatch::new(200);
+    for packet in [confirmation(0), update.clone(), confirmation(1)] {
+        batch.push(&packet).unwrap();
+    }
+    let records = batch
+        .datagrams()
+        .enumerate()
+        .map(|(i, datagram)| {
+            record(
+                1_000_000 + i as u64 * 100_000,
+                Direction::Inbound,
+                Transport::Udp,
+                datagram,
+            )
+        })
+        .collect::<Vec<_>>();
+    let last_us = records.last().unwrap().timestamp_us;
+    let bytes = capture(&records);
+
+    let reader = CaptureReader::new(&bytes[..]).unwrap();
+    let mut replayer = Replayer::from_capture(
+        reader,
+        Direction::Inbound,
+        Transport::Udp,
+        ReplaySpeed::Realtime,
+    )
+    .unwrap();
+    // The update goes out with its last fragment
+    let last = Duration::from_micros(last_us - 1_000_000);
+    assert_eq!(
+        drain(&mut replayer).await,
+        vec![
+            (Duration::ZERO, confirmation(0)),
+            (last, update),
+            (last, confirmation(1)),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn heartbeats_are_not_replayed() {
+    let frame = |id| encode_frame(&confirmation(id)).unwrap();
+    // A heartbeat on its own and one coalesced into a datagram
+    let mut datagram = HEARTBEAT.to_vec();
+    datagram.extend_from_slice(&frame(1));
+    let bytes = capture(&[
+        record(1_000_000, Direction::Inbound, Transport::Udp, &frame(0)),
+        record(1_100_000, Direction::Inbound, Transport::Udp, &HEARTBEAT),
+        record(1_200_000, Direction::Inbound, Transport::Udp, &datagram),
+    ]);
+
+    let reader = CaptureReader::new(&bytes[..]).unwrap();
+    let mut replayer = Replayer::from_capture(
+        reader,
+        Direction::Inbound,
+        Transport::Udp,
+        ReplaySpeed::Realtime,
+    )
+    .unwrap();
+    assert_eq!(
+        drain(&mut replayer).await,
+        vec![
+            (Duration::ZERO, confirmation(0)),
+            (Duration::from_millis(200), confirmation(1)),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn replayed_over_any_stream() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = TcpConnection::<Packet, _>::from_io(client, None);
+    let mut server = TcpConnection::<Packet, _>::from_io(server, None);
+
+    let mut replayer = Replayer::new(one_per_second(3), ReplaySpeed::Realtime);
+    assert_eq!(replayer.replay_tcp(&mut client).await.unwrap(), 3);
+    for i in 0..3 {
+        assert_eq!(server.wait_for_packet().await.unwrap(), confirmation(i));
+    }
+}
@@ -0,0 +1,330 @@
+//! `from_raw(to_raw(p)) == p` for every packet of both families, `encode_into` agreeing
+//! with `to_raw`, and the borrowed
+//! server_launcher decode agreeing with the owned one.
+
+use ngmp_protocol_impl::{
+    launcher_client, server_launcher, DisconnectReason, PacketHeader, PacketTrait,
+};
+
+use proptest::prelude::*;
+
+fn roundtrip<T: PacketTrait + PartialEq + std::fmt::Debug>(packet: T) -> Result<(), TestCaseError> {
+    let (sig_a, sig_b, raw) = packet
+        .to_raw()
+        .map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let decoded =
+        T::from_raw(sig_a, sig_b, &raw).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(&decoded, &packet);
+
+    // The frame written by encode_into has to match the header + to_raw
+    let mut frame = Vec::new();
+    packet
+        .encode_into(&mut frame)
+        .map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(frame.len(), packet.encoded_len());
+    let header = PacketHeader::from_bytes(frame[..PacketHeader::SIZE].try_into().unwrap());
+    prop_assert_eq!((header.sig_a, header.sig_b), (sig_a, sig_b));
+    prop_assert_eq!(header.packet_length as usize, raw.len());
+    prop_assert_eq!(&frame[PacketHeader::SIZE..], &raw[..]);
+    Ok(())
+}
+
+/// JSON can't represent NaN and infinities
+fn finite_f32() -> impl Strategy<Value = f32> {
+    prop::num::f32::NORMAL | prop::num::f32::SUBNORMAL | prop::num::f32::ZERO
+}
+
+fn disconnect_reason() -> impl Strategy<Value = DisconnectReason> {
+    prop_oneof![
+        Just(DisconnectReason::Kicked),
+        Just(DisconnectReason::Banned),
+        Just(DisconnectReason::ServerShutdown),
+        Just(DisconnectReason::VersionMismatch),
+        Just(DisconnectReason::AuthFailed),
+        Just(DisconnectReason::Timeout),
+        Just(DisconnectReason::SessionExpired),
+    ]
+}
+
+mod server_launcher_packets {
+    use super::*;
+    use ngmp_protocol_impl::crypto::UdpKey;
+    use ngmp_protocol_impl::session::SessionToken;
+    use server_launcher::gameplay::*;
+    use server_launcher::generic::*;
+    use server_launcher::handshake::*;
+    use server_launcher::serverinfo::*;
+    use server_launcher::Packet;
+
+    fn vehicle_data() -> impl Strategy<Value = VehicleData> {
+        (
+            any::<String>(),
+            any::<u32>(),
+            any::<String>(),
+            any::<String>(),
+            prop::array::uniform3(finite_f32()),
+            prop::array::uniform4(finite_f32()),
+        )
+            .prop_map(
+                |(jbeam, object_id, paints, part_config, pos, rot)| VehicleData {
+                    jbeam,
+                    object_id,
+                    paints,
+                    part_config,
+                    pos,
+                    rot,
+                },
+            )
+    }
+
+    fn packet() -> impl Strategy<Value = Packet> {
+        prop_oneof![
+            any::<u16>()
+                .prop_map(|confirm_id| Packet::Confirmation(ConfirmationPacket { confirm_id })),
+            any::<String>().prop_map(|reason| Packet::PlayerKick(PlayerKickPacket { reason })),
+            any::<u32>().prop_map(|ms| Packet::Ping(PingPacket { ms })),
+            (any::<u32>(), any::<u32>())
+                .prop_map(|(ping_ms, ms)| Packet::Pong(PongPacket { ping_ms, ms })),
+            (disconnect_reason(), any::<String>()).prop_map(|(reason, message)| {
+                Packet::Disconnect(DisconnectPacket { reason, message })
+            }),
+            (any::<u16>(), any::<u16>()).prop_map(|(confirm_id, client_version)| {
+                Packet::Version(VersionPacket {
+                    confirm_id,
+                    client_version,
+                })
+            }),
+            (any::<u16>(), any::<String>()).prop_map(|(confirm_id, auth_code)| {
+                Packet::Authentication(AuthenticationPacket {
+                    confirm_id,
+                    auth_code,
+                })
+            }),
+            any::<[u8; 16]>().prop_map(|token| Packet::ResumeToken(ResumeTokenPacket {
+                token: SessionToken(token)
+            })),
+            any::<[u8; 16]>().prop_map(|token| Packet::Resume(ResumePacket {
+                token: SessionToken(token)
+            })),
+            any::<u32>().prop_map(|capabilities| Packet::Capabilities(CapabilitiesPacket {
+                capabilities: Capabilities(capabilities)
+            })),
+            any::<[u8; 32]>().prop_map(|key| Packet::UdpKey(UdpKeyPacket { key: UdpKey(key) })),
+            (any::<u16>(), any::<u16>()).prop_map(|(http_port, udp_port)| {
+                Packet::ServerInfo(ServerInfoPacket {
+                    http_port,
+                    udp_port,
+                })
+            }),
+            (any::<u16>(), any::<String>()).prop_map(|(confirm_id, map_name)| {
+                Packet::LoadMap(LoadMapPacket {
+                    confirm_id,
+                    map_name,
+                })
+            }),
+            prop::collection::vec(
+                (any::<String>(), any::<u64>(), any::<String>()).prop_map(
+                    |(name, steam_id, avatar_hash)| PlayerData {
+                        name,
+                        steam_id,
+                        avatar_hash,
+                    }
+                ),
+                0..4
+            )
+            .prop_map(|players| Packet::PlayerData(PlayerDataPacket { players })),
+            (any::<u16>(), any::<u64>(), any::<u16>(), vehicle_data()).prop_map(
+                |(confirm_id, steam_id, vehicle_id, vehicle_data)| {
+                    Packet::VehicleSpawn(VehicleSpawnPacket {
+                        confirm_id,
+                        steam_id,
+                        vehicle_id,
+                        vehicle_data,
+                    })
+                }
+            ),
+            (any::<u16>(), any::<u16>(), any::<u32>()).prop_map(
+                |(confirm_id, vehicle_id, obj_id)| {
+                    Packet::VehicleConfirm(VehicleConfirmPacket {
+                        confirm_id,
+                        vehicle_id,
+                        obj_id,
+                    })
+                }
+            ),
+            (any::<u64>(), any::<u16>()).prop_map(|(player_id, vehicle_id)| {
+                Packet::VehicleDelete(VehicleDeletePacket {
+                    player_id,
+                    vehicle_id,
+                })
+            }),
+            (any::<u64>(), any::<u16>(), any::<String>()).prop_map(
+                |(player_id, vehicle_id, transform)| {
+                    Packet::VehicleTransform(VehicleTransformPacket {
+                        player_id,
+                        vehicle_id,
+                        transform,
+                    })
+                }
+            ),
+            (any::<u64>(), any::<u16>(), any::<u32>(), any::<String>()).prop_map(
+                |(player_id, vehicle_id, ms, runtime_data)| {
+                    Packet::VehicleUpdate(VehicleUpdatePacket {
+                        player_id,
+                        vehicle_id,
+                        ms,
+                        runtime_data,
+                    })
+                }
+            ),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn packet_roundtrip(packet in packet()) {
+            roundtrip(packet)?;
+        }
+
+        #[test]
+        fn borrowed_decode_matches_owned(packet in packet()) {
+            let (sig_a, sig_b, raw) = packet.to_raw().unwrap();
+            let borrowed = server_launcher::PacketRef::from_raw(sig_a, sig_b, &raw).unwrap();
+            prop_assert_eq!(borrowed.into_owned(), packet);
+        }
+    }
+}
+
+mod launcher_client_packets {
+    use super::*;
+    use launcher_client::gameplay::*;
+    use launcher_client::generic::*;
+    use launcher_client::handshake::*;
+    use launcher_client::Packet;
+
+    fn vehicle_data() -> impl Strategy<Value = VehicleData> {
+        (
+            any::<String>(),
+            any::<u32>(),
+            any::<String>(),
+            any::<String>(),
+            prop::array::uniform3(finite_f32()),
+            prop::array::uniform4(finite_f32()),
+        )
+            .prop_map(
+                |(jbeam, object_id, paints, part_config, pos, rot)| VehicleData {
+                    jbeam,
+                    object_id,
+                    paints,
+                    part_config,
+                    pos,
+                    rot,
+                },
+            )
+    }
+
+    fn packet() -> impl Strategy<Value = Packet> {
+        prop_oneof![
+            Just(Packet::ReloadLauncherConnection),
+            any::<u16>()
+                .prop_map(|confirm_id| Packet::Confirmation(ConfirmationPacket { confirm_id })),
+            any::<String>()
+                .prop_map(|error| Packet::ConnectionError(ConnectionErrorPacket { error })),
+            (disconnect_reason(), any::<String>()).prop_map(|(reason, message)| {
+                Packet::Disconnect(DisconnectPacket { reason, message })
+            }),
+            any::<u16>()
+                .prop_map(|protocol_version| Packet::Version(VersionPacket { protocol_version })),
+            (any::<String>(), any::<u16>()).prop_map(|(userfolder, client_version)| {
+                Packet::ClientInfo(ClientInfoPacket {
+                    userfolder,
+                    client_version,
+                })
+            }),
+            (
+                any::<bool>(),
+                any::<String>(),
+                any::<String>(),
+                any::<String>()
+            )
+                .prop_map(|(success, player_name, steam_id, avatar_hash)| {
+                    Packet::AuthenticationInfo(AuthenticationInfoPacket {
+                        success,
+                        player_name,
+                        steam_id,
+                        avatar_hash,
+                    })
+                }),
+            Just(Packet::LoginRequest),
+            any::<String>()
+                .prop_map(|ip_address| Packet::JoinServer(JoinServerPacket { ip_address })),
+            (any::<u16>(), any::<String>()).prop_map(|(confirm_id, map_string)| {
+                Packet::LoadMap(LoadMapPacket {
+                    confirm_id,
+                    map_string,
+                })
+            }),
+            prop::collection::vec(
+                (any::<String>(), any::<String>(), any::<String>()).prop_map(
+                    |(name, steam_id, avatar_hash)| PlayerData {
+                        name,
+                        steam_id,
+                        avatar_hash,
+                    }
+                ),
+                0..4
+            )
+            .prop_map(|players| Packet::PlayerData(PlayerDataPacket { players })),
+            (any::<u16>(), any::<String>(), any::<u16>(), vehicle_data()).prop_map(
+                |(confirm_id, steam_id, vehicle_id, vehicle_data)| {
+                    Packet::VehicleSpawn(VehicleSpawnPacket {
+                        confirm_id,
+                        steam_id,
+                        vehicle_id,
+                        vehicle_data,
+                    })
+                }
+            ),
+            (any::<u16>(), any::<u16>(), any::<u32>()).prop_map(
+                |(confirm_id, vehicle_id, object_id)| {
+                    Packet::VehicleConfirm(VehicleConfirmPacket {
+                        confirm_id,
+                        vehicle_id,
+                        object_id,
+                    })
+                }
+            ),
+            (any::<String>(), any::<u16>()).prop_map(|(steam_id, vehicle_id)| {
+                Packet::VehicleDelete(VehicleDeletePacket {
+                    steam_id,
+                    vehicle_id,
+                })
+            }),
+            (any::<String>(), any::<u16>(), any::<String>()).prop_map(
+                |(steam_id, vehicle_id, transform)| {
+                    Packet::VehicleTransform(VehicleTransformPacket {
+                        steam_id,
+                        vehicle_id,
+                        transform,
+                    })
+                }
+            ),
+            (any::<String>(), any::<u16>(), any::<String>()).prop_map(
+                |(steam_id, vehicle_id, runtime_data)| {
+                    Packet::VehicleUpdate(VehicleUpdatePacket {
+                        steam_id,
+                        vehicle_id,
+                        runtime_data,
+                    })
+                }
+            ),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn packet_roundtrip(packet in packet()) {
+            roundtrip(packet)?;
+        }
+    }
+}
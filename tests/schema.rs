@@ -0,0 +1,289 @@
+//! The hand-written schema against the real encoders, and the outputs generated from it.
+
+use ngmp_protocol_impl::dissector::{generate_lua, DissectorConfig};
+use ngmp_protocol_impl::schema::{self, Body, FieldType, PacketDef};
+use ngmp_protocol_impl::{launcher_client, server_launcher, DisconnectReason, PacketTrait};
+
+use std::process::{Command, Output};
+
+use serde_json::{json, Value};
+
+fn run(bin: &str, args: &[&str]) -> Output {
+    Command::new(bin).args(args).output().unwrap()
+}
+
+/// Sample binary value of a field, strings get `"ab"`.
+fn binary_sample(ty: &FieldType) -> Vec<u8> {
+    match (ty, ty.binary_size()) {
+        (FieldType::Enum { values, .. }, _) => vec![values[1].code],
+        (_, Some(size)) => (1..=size as u8).collect(),
+        (_, None) => b"ab".to_vec(),
+    }
+}
+
+fn json_sample(ty: &FieldType) -> Value {
+    match ty {
+        FieldType::Bool => json!(true),
+        FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 => json!(1),
+        FieldType::F32 => json!(1.5),
+        // Strings that hold a number, like steam ids, have to parse
+        FieldType::String => json!("1"),
+        FieldType::Enum { repr, values } => match repr.binary_size() {
+            Some(_) => json!(values[1].code),
+            None => json!(values[1].name),
+        },
+        FieldType::Array { of, len } => Value::Array(vec![json_sample(of); *len]),
+        FieldType::List { of } => Value::Array(vec![json_sample(of)]),
+        FieldType::Object { fields } => Value::Object(
+            fields
+                .iter()
+                .map(|field| (field.name.to_string(), json_sample(&field.ty)))
+                .collect(),
+        ),
+    }
+}
+
+/// Body built from the definition alone.
+fn sample_body(def: &PacketDef) -> Vec<u8> {
+    match def.body {
+        Body::Empty => Vec::new(),
+        Body::Binary(fields) => fields.iter().flat_map(|f| binary_sample(&f.ty)).collect(),
+        Body::Json(fields) => json_sample(&FieldType::Object { fields })
+            .to_string()
+            .into_bytes(),
+    }
+}
+
+/// Every definition has to describe a body the decoder accepts and the encoder
+/// writes back the same way, under the same signature and variant name.
+fn check_defs<T: PacketTrait + serde::Serialize + std::fmt::Debug>(defs: &[PacketDef]) {
+    for def in defs {
+        let body = sample_body(def);
+        let packet = T::from_raw(def.sig_a, def.sig_b, &body)
+            .unwrap_or_else(|e| panic!("{} doesn't decode its sample: {}", def.name, e));
+        // Unit variants serialize as just their name
+        let variant = match serde_json::to_value(&packet).unwrap() {
+            Value::String(name) => name,
+            Value::Object(variant) => variant.keys().next().unwrap().clone(),
+            other => panic!("unexpected packet json {}", other),
+        };
+        assert_eq!(
+            variant, def.name,
+            "{}{} decoded as {:?}",
+            def.sig_a, def.sig_b, packet
+        );
+
+        let (sig_a, sig_b, raw) = packet.to_raw().unwrap();
+        assert_eq!((sig_a, sig_b), (def.sig_a, def.sig_b), "{}", def.name);
+        match def.body {
+            Body::Json(_) => assert_eq!(
+                serde_json::from_slice::<Value>(&raw).unwrap(),
+                serde_json::from_slice::<Value>(&body).unwrap(),
+                "{}",
+                def.name
+            ),
+            Body::Empty | Body::Binary(_) => assert_eq!(raw, body, "{}", def.name),
+        }
+
+        if let Body::Json(fields) = def.body {
+            let mut required = json_sample(&FieldType::Object { fields });
+            for field in fields.iter().filter(|f| f.optional) {
+                required.as_object_mut().unwrap().remove(field.name);
+            }
+            T::from_raw(def.sig_a, def.sig_b, required.to_string().as_bytes()).unwrap_or_else(
+                |e| {
+                    panic!(
+                        "{} doesn't decode without its optional fields: {}",
+                        def.name, e
+                    )
+                },
+            );
+        }
+    }
+}
+
+#[test]
+fn server_launcher_schema_matches_encoding() {
+    check_defs::<server_launcher::Packet>(schema::SERVER_LAUNCHER);
+}
+
+#[test]
+fn launcher_client_schema_matches_encoding() {
+    check_defs::<launcher_client::Packet>(schema::LAUNCHER_CLIENT);
+}
+
+#[test]
+fn signatures_are_unique() {
+    for defs in [schema::SERVER_LAUNCHER, schema::LAUNCHER_CLIENT] {
+        for (i, def) in defs.iter().enumerate() {
+            assert_eq!(
+                schema::find(defs, def.sig_a, def.sig_b),
+                Some(&defs[i]),
+                "{}",
+                def.name
+            );
+        }
+    }
+}
+
+#[test]
+fn export_lists_every_packet() {
+    let export = schema::export();
+    // Has to survive a trip through text
+    let export: Value = serde_json::from_str(&export.to_string()).unwrap();
+
+    for (family, defs) in [
+        ("server_launcher", schema::SERVER_LAUNCHER),
+        ("launcher_client", schema::LAUNCHER_CLIENT),
+    ] {
+        let packets = export["families"][family].as_array().unwrap();
+        assert_eq!(packets.len(), defs.len());
+        for (packet, def) in packets.iter().zip(defs) {
+            assert_eq!(packet["signature"], format!("{}{}", def.sig_a, def.sig_b));
+            assert_eq!(packet["name"], def.name);
+        }
+    }
+    for (frame, signature) in [("heartbeat", "HB"), ("fragment", "FG"), ("encrypted", "EN")] {
+        assert_eq!(export["framing"][frame]["signature"], signature);
+    }
+}
+
+#[test]
+fn export_lists_transports_and_optional_fields() {
+    let export = schema::export();
+    let packet = |family: &str, name: &str| {
+        export["families"][family]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|packet| packet["name"] == name)
+            .unwrap()
+            .clone()
+    };
+
+    // Pings measure the round trip over either connection
+    for name in ["Ping", "Pong"] {
+        assert_eq!(packet("server_launcher", name)["transport"], "both");
+    }
+    assert_eq!(
+        packet("server_launcher", "VehicleUpdate")["transport"],
+        "udp"
+    );
+
+    let fields = &packet("launcher_client", "Disconnect")["body"]["fields"];
+    assert_eq!(fields[1]["name"], "message");
+    assert_eq!(fields[1]["optional"], true);
+    // Only optional fields say so
+    assert!(fields[0].get("optional").is_none());
+}
+
+#[test]
+fn dissector_covers_every_packet() {
+    let lua = generate_lua(&DissectorConfig {
+        server_launcher_tcp_ports: vec![30814],
+        server_launcher_udp_ports: vec![30815],
+        launcher_client_tcp_ports: vec![4444],
+    });
+
+    for (proto, defs) in [
+        ("ngmp_sl", schema::SERVER_LAUNCHER),
+        ("ngmp_lc", schema::LAUNCHER_CLIENT),
+    ] {
+        assert!(lua.contains(&format!("local {proto} = Proto(")));
+        for def in defs {
+            let entry = format!(
+                "{proto}_packets[\"{}{}\"] = {{ name = \"{}\"",
+                def.sig_a, def.sig_b, def.name
+            );
+            assert!(lua.contains(&entry), "{}", entry);
+        }
+    }
+    assert!(lua.contains("tcp_port:add(30814, ngmp_sl)"));
+    assert!(lua.contains("udp_port:add(30815, ngmp_sl)"));
+    assert!(lua.contains("tcp_port:add(4444, ngmp_lc)"));
+    for sig in ["HB", "FG", "EN"] {
+        assert!(lua.contains(&format!("sig == \"{sig}\"")), "{}", sig);
+    }
+    // Fixed size arrays like the session token don't run until the end of the packet
+    assert!(lua.contains("{ size = 16, field = ngmp_sl_fields[\"RT.token\"] }"));
+    assert!(lua.contains("{ field = ngmp_sl_fields[\"PK.reason\"] }"));
+    // Enum codes are shown with their names
+    assert!(lua.contains("[4] = \"auth_failed\""));
+}
+
+#[test]
+fn disconnect_reasons_match_the_codes() {
+    let mut code = 0;
+    while let Ok(reason) = DisconnectReason::from_code(code) {
+        let value = schema::DISCONNECT_REASONS
+            .iter()
+            .find(|v| v.code == code)
+            .unwrap_or_else(|| panic!("code {} missing from the schema", code));
+        assert_eq!(serde_json::to_value(reason).unwrap(), value.name);
+        code += 1;
+    }
+    assert_eq!(schema::DISCONNECT_REASONS.len(), code as usize);
+}
+
+#[test]
+fn export_lists_disconnect_codes() {
+    let export = schema::export();
+    for family in ["server_launcher", "launcher_client"] {
+        let disconnect = export["families"][family]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|packet| packet["name"] == "Disconnect")
+            .unwrap();
+        let reason = &disconnect["body"]["fields"][0];
+        assert_eq!(reason["name"], "reason");
+        assert_eq!(reason["type"], "enum");
+        let values = reason["values"].as_array().unwrap();
+        assert_eq!(values.len(), schema::DISCONNECT_REASONS.len());
+        assert_eq!(
+            values[4],
+            serde_json::json!({ "name": "auth_failed", "code": 4 })
+        );
+    }
+
+    let disconnect = schema::find(schema::SERVER_LAUNCHER, 'D', 'C').unwrap();
+    let schema::Body::Binary(fields) = disconnect.body else {
+        panic!("server_launcher disconnect has a binary body");
+    };
+    assert_eq!(fields[0].ty.binary_size(), Some(1));
+    assert!(matches!(fields[0].ty, FieldType::Enum { .. }));
+}
+
+#[test]
+fn dissector_help_exits_cleanly() {
+    let bin = env!("CARGO_BIN_EXE_ngmp-dissector");
+    for flag in ["-h", "--help"] {
+        let output = run(bin, &[flag]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: ngmp-dissector"));
+    }
+
+    let output = run(bin, &["--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: unknown argument --bogus"));
+}
+
+#[test]
+fn schema_help_exits_cleanly() {
+    let bin = env!("CARGO_BIN_EXE_ngmp-schema");
+    for flag in ["-h", "--help"] {
+        let output = run(bin, &[flag]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: ngmp-schema"));
+    }
+
+    let output = run(bin, &["--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: unknown argument: --bogus"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("usage: ngmp-schema"));
+}
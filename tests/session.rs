@@ -0,0 +1,252 @@
+//! Session resumption, both the server side store and the reconnecting launcher side.
+
+use ngmp_protocol_impl::connection::TcpConnection;
+use ngmp_protocol_impl::server_launcher::generic::{ConfirmationPacket, DisconnectPacket};
+use ngmp_protocol_impl::server_launcher::handshake::ResumeTokenPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::session::{
+    ReconnectConfig, ReconnectingClient, Resumed, SessionStore, SessionToken,
+};
+use ngmp_protocol_impl::{ConnectionError, DisconnectReason};
+
+use std::time::{Duration, Instant};
+
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+const GRACE: Duration = Duration::from_secs(10);
+
+#[test]
+fn tokens_are_unique_and_stay_out_of_logs() {
+    let (a, b) = (SessionToken::generate(), SessionToken::generate());
+    assert_ne!(a, b);
+    assert_eq!(format!("{a:?}"), "SessionToken(..)");
+
+    let packet = Packet::ResumeToken(ResumeTokenPacket { token: a });
+    assert_eq!(
+        serde_json::to_value(&packet).unwrap(),
+        serde_json::json!({ "ResumeToken": { "token": ".." } })
+    );
+}
+
+#[test]
+fn parked_session_resumes_within_grace() {
+    let mut store = SessionStore::new(GRACE);
+    let now = Instant::now();
+    let token = store.issue();
+    store.park(&token, vec![1u16, 2], now).unwrap();
+
+    let (new_token, resumed) = store.resume(&token, now + GRACE / 2).unwrap();
+    assert_eq!(resumed, Resumed::Parked(vec![1, 2]));
+    assert_ne!(new_token, token);
+    // Tokens are single use
+    assert!(store.resume(&token, now).is_none());
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn resuming_an_active_session() {
+    let mut store = SessionStore::<()>::new(GRACE);
+    let token = store.issue();
+    let (_, resumed) = store.resume(&token, Instant::now()).unwrap();
+    assert_eq!(resumed, Resumed::Active);
+}
+
+#[test]
+fn parked_sessions_expire() {
+    let mut store = SessionStore::new(GRACE);
+    let now = Instant::now();
+    let early = store.issue();
+    let late = store.issue();
+    let active = store.issue();
+    store.park(&early, "early", now).unwrap();
+    store.park(&late, "late", now + GRACE / 2).unwrap();
+
+    assert!(store.expire(now + GRACE / 2).is_empty());
+    assert_eq!(store.expire(now + GRACE), ["early"]);
+    assert!(store.resume(&early, now + GRACE).is_none());
+    assert!(store.resume(&late, now + GRACE * 2).is_none());
+    assert!(store.resume(&active, now + GRACE * 2).is_some());
+    // A late resume doesn't lose the state, it still gets handed back for cleanup
+    assert_eq!(store.expire(now + GRACE * 2), ["late"]);
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn unknown_and_removed_tokens() {
+    let mut store = SessionStore::new(GRACE);
+    let now = Instant::now();
+    assert_eq!(store.park(&SessionToken::generate(), 5, now), Err(5));
+
+    let token = store.issue();
+    store.park(&token, 5, now).unwrap();
+    assert_eq!(store.remove(&token), Some(5));
+    assert!(store.resume(&token, now).is_none());
+    assert!(store.is_empty());
+}
+
+fn confirmation(confirm_id: u16) -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id })
+}
+
+fn config() -> ReconnectConfig {
+    ReconnectConfig {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn client_resumes_transparently() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let mut store = SessionStore::new(GRACE);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = TcpConnection::<Packet>::from_stream(stream);
+        let token = store.issue();
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket { token }))
+            .await
+            .unwrap();
+        conn.write_packet(&confirmation(1)).await.unwrap();
+        // The connection drops, the player's vehicles are kept around
+        drop(conn);
+        store.park(&token, vec![7u16], Instant::now()).unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = TcpConnection::<Packet>::from_stream(stream);
+        let Packet::Resume(resume) = conn.wait_for_packet().await.unwrap() else {
+            panic!("expected a resume");
+        };
+        let (token, resumed) = store.resume(&resume.token, Instant::now()).unwrap();
+        assert_eq!(resumed, Resumed::Parked(vec![7]));
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket { token }))
+            .await
+            .unwrap();
+        conn.write_packet(&confirmation(2)).await.unwrap();
+        assert_eq!(conn.wait_for_packet().await.unwrap(), confirmation(3));
+        token
+    });
+
+    let mut client = ReconnectingClient::connect(addr, config()).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), confirmation(1));
+    let first_token = client.token().unwrap();
+    // Only arrives after the connection dropped and got resumed
+    assert_eq!(client.wait_for_packet().await.unwrap(), confirmation(2));
+    client.write_packet(&confirmation(3)).await.unwrap();
+
+    let token = server.await.unwrap();
+    assert_eq!(client.reconnects(), 1);
+    assert_eq!(client.token(), Some(token));
+    assert_ne!(token, first_token);
+}
+
+#[tokio::test(start_paused = true)]
+async fn refused_resume_is_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = TcpConnection::<Packet>::from_stream(stream);
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket {
+            token: SessionToken::generate(),
+        }))
+        .await
+        .unwrap();
+        drop(conn);
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let conn = TcpConnection::<Packet>::from_stream(stream);
+        conn.close_with(&Packet::Disconnect(DisconnectPacket {
+            reason: DisconnectReason::SessionExpired,
+            message: String::new(),
+        }))
+        .await
+        .unwrap();
+    });
+
+    let mut client = ReconnectingClient::connect(addr, config()).await.unwrap();
+    let e = client.wait_for_packet().await.unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<ConnectionError>(),
+        Some(ConnectionError::ResumeRefused(
+            DisconnectReason::SessionExpired
+        ))
+    ));
+    assert_eq!(client.token(), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn gives_up_once_the_server_is_gone() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = TcpConnection::<Packet>::from_stream(stream);
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket {
+            token: SessionToken::generate(),
+        }))
+        .await
+        .unwrap();
+        // Dropping the listener too, nothing to reconnect to
+    });
+
+    let mut client = ReconnectingClient::connect(addr, config()).await.unwrap();
+    server.await.unwrap();
+    let e = client.wait_for_packet().await.unwrap_err();
+    assert!(e.downcast_ref::<std::io::Error>().is_some(), "{e}");
+    assert_eq!(client.reconnects(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn any_transport_can_be_resumed() {
+    // Every connect hands the server its end of a fresh in-memory pipe
+    let (accept, mut incoming) = mpsc::unbounded_channel::<DuplexStream>();
+    let connector = move || {
+        let accept = accept.clone();
+        async move {
+            let (client, server) = tokio::io::duplex(4096);
+            accept.send(server)?;
+            Ok(TcpConnection::<Packet, _>::new(client))
+        }
+    };
+
+    let server = tokio::spawn(async move {
+        let mut store = SessionStore::new(GRACE);
+        let mut conn = TcpConnection::<Packet, _>::new(incoming.recv().await.unwrap());
+        let token = store.issue();
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket { token }))
+            .await
+            .unwrap();
+        drop(conn);
+        store.park(&token, (), Instant::now()).unwrap();
+
+        let mut conn = TcpConnection::<Packet, _>::new(incoming.recv().await.unwrap());
+        let Packet::Resume(resume) = conn.wait_for_packet().await.unwrap() else {
+            panic!("expected a resume");
+        };
+        let (token, _) = store.resume(&resume.token, Instant::now()).unwrap();
+        conn.write_packet(&Packet::ResumeToken(ResumeTokenPacket { token }))
+            .await
+            .unwrap();
+        conn.write_packet(&confirmation(1)).await.unwrap();
+    });
+
+    let mut client = ReconnectingClient::with_connector(connector, config())
+        .await
+        .unwrap();
+    // Has to be able to run on another task
+    let client = tokio::spawn(async move {
+        assert_eq!(client.wait_for_packet().await.unwrap(), confirmation(1));
+        client
+    })
+    .await
+    .unwrap();
+    server.await.unwrap();
+    assert_eq!(client.reconnects(), 1);
+}
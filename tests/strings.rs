@@ -0,0 +1,83 @@
+//! UTF-8 handling of the strings in `server_launcher` packets.
+
+use ngmp_protocol_impl::server_launcher::generic::PlayerKickPacket;
+use ngmp_protocol_impl::server_launcher::handshake::AuthenticationPacket;
+use ngmp_protocol_impl::server_launcher::serverinfo::LoadMapPacket;
+use ngmp_protocol_impl::server_launcher::wire;
+use ngmp_protocol_impl::PacketDecodeError;
+
+#[test]
+fn authentication_non_ascii() {
+    let packet = AuthenticationPacket {
+        confirm_id: 7,
+        auth_code: "Jürgen-ключ-🔑".to_string(),
+    };
+    let raw = packet.to_raw().unwrap();
+    assert_eq!(&raw[2..], "Jürgen-ключ-🔑".as_bytes());
+    assert_eq!(AuthenticationPacket::from_raw(&raw).unwrap(), packet);
+}
+
+#[test]
+fn load_map_non_ascii() {
+    let packet = LoadMapPacket {
+        confirm_id: 1,
+        map_name: "/levels/Åsnes_ßtraße/info.json".to_string(),
+    };
+    let raw = packet.to_raw().unwrap();
+    assert_eq!(&raw[2..], "/levels/Åsnes_ßtraße/info.json".as_bytes());
+    assert_eq!(LoadMapPacket::from_raw(&raw).unwrap(), packet);
+}
+
+#[test]
+fn player_kick_non_ascii() {
+    let packet = PlayerKickPacket {
+        reason: "Verbindung getrennt – 接続が切断されました".to_string(),
+    };
+    let raw = packet.to_raw().unwrap();
+    assert_eq!(PlayerKickPacket::from_raw(&raw).unwrap(), packet);
+}
+
+#[test]
+fn invalid_utf8_is_rejected() {
+    // Latin-1 "ü", which used to be accepted as is
+    let raw = vec![7, 0, b'J', 0xFC, b'r'];
+    assert!(matches!(
+        AuthenticationPacket::from_raw(&raw),
+        Err(PacketDecodeError::InvalidString)
+    ));
+    assert!(matches!(
+        LoadMapPacket::from_raw(&raw),
+        Err(PacketDecodeError::InvalidString)
+    ));
+}
+
+#[test]
+fn authentication_empty_code() {
+    // Just the confirm id, e.g. for servers that don't check auth codes
+    let packet = AuthenticationPacket {
+        confirm_id: 7,
+        auth_code: String::new(),
+    };
+    let raw = packet.to_raw().unwrap();
+    assert_eq!(raw, [7, 0]);
+    assert_eq!(AuthenticationPacket::from_raw(&raw).unwrap(), packet);
+    assert!(matches!(
+        AuthenticationPacket::from_raw(&[7]),
+        Err(PacketDecodeError::InvalidDataSize {
+            expected: 2,
+            actual: 1
+        })
+    ));
+}
+
+#[test]
+fn trailing_str_split_codepoint() {
+    // The body ends in the middle of "é"
+    let mut raw = "Café".as_bytes().to_vec();
+    raw.pop();
+    assert!(matches!(
+        wire::read_trailing_str(&raw),
+        Err(PacketDecodeError::InvalidString)
+    ));
+    assert_eq!(wire::read_trailing_str(b"").unwrap(), "");
+}
@@ -0,0 +1,189 @@
+//! The server/launcher TCP link over TLS, with a self-signed certificate.
+#![cfg(feature = "tls")]
+
+use ngmp_protocol_impl::server_launcher::handshake::AuthenticationPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::tls::rustls::pki_types::{PrivateKeyDer, ServerName};
+use ngmp_protocol_impl::tls::rustls::RootCertStore;
+use ngmp_protocol_impl::tls::{self, ClientTlsConnection, ServerTlsConnection, TlsAcceptor};
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn self_signed() -> (TlsAcceptor, RootCertStore) {
+    let key = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+    let cert = key.cert.der().clone();
+    let private_key = PrivateKeyDer::Pkcs8(key.key_pair.serialize_der().into());
+    let acceptor = tls::acceptor(vec![cert.clone()], private_key).unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    (acceptor, roots)
+}
+
+fn auth() -> Packet {
+    Packet::Authentication(AuthenticationPacket {
+        confirm_id: 1,
+        auth_code: "very secret".to_string(),
+    })
+}
+
+#[tokio::test]
+async fn packets_over_tls() {
+    let (acceptor, roots) = self_signed();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let mut conn = ServerTlsConnection::<Packet>::accept_tls(tcp, &acceptor)
+            .await
+            .unwrap();
+        let packet = conn.wait_for_packet().await.unwrap();
+        conn.write_packet(&packet).await.unwrap();
+        assert!(conn.peer_addr().is_some());
+    });
+
+    let connector = tls::connector(roots).unwrap();
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut conn = ClientTlsConnection::<Packet>::connect_tls(tcp, &connector, server_name)
+        .await
+        .unwrap();
+    conn.write_packet(&auth()).await.unwrap();
+    assert_eq!(conn.wait_for_packet().await.unwrap(), auth());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn auth_code_is_not_on_the_wire() {
+    let (acceptor, roots) = self_signed();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let mut conn = ServerTlsConnection::<Packet>::accept_tls(tcp, &acceptor)
+            .await
+            .unwrap();
+        conn.wait_for_packet().await.unwrap()
+    });
+
+    // Sits between launcher and server and keeps what the launcher sent
+    let relay_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay_addr = relay_listener.local_addr().unwrap();
+    let relay = tokio::spawn(async move {
+        let (launcher, _) = relay_listener.accept().await.unwrap();
+        let upstream = TcpStream::connect(addr).await.unwrap();
+        let (mut launcher_read, mut launcher_write) = launcher.into_split();
+        let (mut upstream_read, mut upstream_write) = upstream.into_split();
+        tokio::spawn(async move { tokio::io::copy(&mut upstream_read, &mut launcher_write).await });
+        let mut sent = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            // Unread session tickets make the launcher reset rather than close
+            let read = match launcher_read.read(&mut buf).await {
+                Ok(0) | Err(_) => return sent,
+                Ok(read) => read,
+            };
+            sent.extend_from_slice(&buf[..read]);
+            upstream_write.write_all(&buf[..read]).await.unwrap();
+        }
+    });
+
+    let connector = tls::connector(roots).unwrap();
+    let tcp = TcpStream::connect(relay_addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut conn = ClientTlsConnection::<Packet>::connect_tls(tcp, &connector, server_name)
+        .await
+        .unwrap();
+    conn.write_packet(&auth()).await.unwrap();
+    assert_eq!(server.await.unwrap(), auth());
+    drop(conn);
+
+    let sent = relay.await.unwrap();
+    assert!(!sent.is_empty());
+    assert!(!sent.windows(11).any(|w| w == b"very secret"));
+}
+
+#[tokio::test]
+async fn untrusted_certificate_is_rejected() {
+    let (acceptor, _) = self_signed();
+    let (_, other_roots) = self_signed();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        ServerTlsConnection::<Packet>::accept_tls(tcp, &acceptor)
+            .await
+            .is_err()
+    });
+
+    let connector = tls::connector(other_roots).unwrap();
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    assert!(
+        ClientTlsConnection::<Packet>::connect_tls(tcp, &connector, server_name)
+            .await
+            .is_err()
+    );
+    assert!(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn tls_over_any_stream() {
+    let (acceptor, roots) = self_signed();
+    let (a, b) = tokio::io::duplex(4096);
+
+    let server = tokio::spawn(async move {
+        let mut conn = ServerTlsConnection::<Packet, _>::accept_tls(a, &acceptor)
+            .await
+            .unwrap();
+        conn.wait_for_packet().await.unwrap()
+    });
+
+    let connector = tls::connector(roots).unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut conn = ClientTlsConnection::<Packet, _>::connect_tls(b, &connector, server_name)
+        .await
+        .unwrap();
+    assert_eq!(conn.peer_addr(), None);
+    conn.write_packet(&auth()).await.unwrap();
+    assert_eq!(server.await.unwrap(), auth());
+}
+
+#[tokio::test]
+async fn writes_are_flushed_through_small_buffers() {
+    let (acceptor, roots) = self_signed();
+    // Far smaller than the request, so its tail is still buffered after `write_all`
+    let (a, b) = tokio::io::duplex(1024);
+    let request = Packet::Authentication(AuthenticationPacket {
+        confirm_id: 1,
+        auth_code: "x".repeat(20_000),
+    });
+
+    let expected = request.clone();
+    let server = tokio::spawn(async move {
+        let mut conn = ServerTlsConnection::<Packet, _>::accept_tls(a, &acceptor)
+            .await
+            .unwrap();
+        assert_eq!(conn.wait_for_packet().await.unwrap(), expected);
+        conn.write_packet(&auth()).await.unwrap();
+        // Kept open, nothing else gets written that could push the answer out
+        conn
+    });
+
+    let connector = tls::connector(roots).unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut conn = ClientTlsConnection::<Packet, _>::connect_tls(b, &connector, server_name)
+        .await
+        .unwrap();
+    let answer = tokio::time::timeout(Duration::from_secs(5), async {
+        conn.write_packet(&request).await.unwrap();
+        conn.wait_for_packet().await.unwrap()
+    })
+    .await
+    .expect("request or answer stuck in a buffer");
+    assert_eq!(answer, auth());
+    drop(server.await.unwrap());
+}
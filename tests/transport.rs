@@ -0,0 +1,206 @@
+//! `TcpConnection` over streams other than TCP, mostly in memory without binding ports.
+
+use ngmp_protocol_impl::connection::{KeepaliveConfig, StreamTransport, TcpConnection};
+use ngmp_protocol_impl::framing::{encode_frame, StreamDecoder, DEFAULT_MAX_FRAME_LEN};
+use ngmp_protocol_impl::server_launcher::gameplay::VehicleTransformPacket;
+use ngmp_protocol_impl::server_launcher::generic::ConfirmationPacket;
+use ngmp_protocol_impl::server_launcher::Packet;
+use ngmp_protocol_impl::{ConnectionError, PacketHeader};
+
+use std::time::Duration;
+
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+fn confirmation(confirm_id: u16) -> Packet {
+    Packet::Confirmation(ConfirmationPacket { confirm_id })
+}
+
+fn transform(transform: &str) -> Packet {
+    Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 2,
+        transform: transform.to_string(),
+    })
+}
+
+/// Both ends of an in-memory pipe holding at most `capacity` bytes in either direction.
+fn duplex_pair(
+    capacity: usize,
+) -> (
+    TcpConnection<Packet, DuplexStream>,
+    TcpConnection<Packet, DuplexStream>,
+) {
+    let (a, b) = tokio::io::duplex(capacity);
+    (TcpConnection::new(a), TcpConnection::new(b))
+}
+
+#[tokio::test]
+async fn packets_over_duplex() {
+    let (mut a, mut b) = duplex_pair(4096);
+    assert_eq!(a.peer_addr(), None);
+
+    a.write_packet(&confirmation(1)).await.unwrap();
+    a.write_packet(&transform("a")).await.unwrap();
+    assert_eq!(b.wait_for_packet().await.unwrap(), confirmation(1));
+    assert_eq!(b.wait_for_packet().await.unwrap(), transform("a"));
+
+    b.write_packet(&confirmation(2)).await.unwrap();
+    assert_eq!(a.wait_for_packet().await.unwrap(), confirmation(2));
+}
+
+#[tokio::test]
+async fn frames_bigger_than_the_pipe() {
+    // Every frame has to be read in several pieces
+    let (mut a, mut b) = duplex_pair(16);
+    let big = transform(&"x".repeat(10_000));
+
+    let writer = tokio::spawn({
+        let big = big.clone();
+        async move {
+            for _ in 0..3 {
+                a.write_packet(&big).await.unwrap();
+            }
+            a
+        }
+    });
+    for _ in 0..3 {
+        assert_eq!(b.wait_for_packet().await.unwrap(), big);
+    }
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn frames_over_the_limit_are_rejected() {
+    let (mut a, mut b) = duplex_pair(4096);
+    let limit = 64;
+    b.set_max_frame_len(limit);
+
+    // Transform bodies are the two ids plus the string
+    let fits = transform(&"x".repeat(limit - 10));
+    a.write_packet(&fits).await.unwrap();
+    assert_eq!(b.wait_for_packet().await.unwrap(), fits);
+
+    // Only the header gets sent, the length alone is enough to reject it
+    let too_big = transform(&"x".repeat(limit));
+    let frame = encode_frame(&too_big).unwrap();
+    a.write_bytes(&frame[..PacketHeader::SIZE]).await.unwrap();
+    let e = b.wait_for_packet().await.unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<ConnectionError>(),
+        Some(ConnectionError::FrameTooLarge(len)) if *len == limit + 10
+    ));
+    // The stream is out of sync from here on
+    assert!(b.try_read_packet().await.is_err());
+}
+
+#[test]
+fn decoder_limit() {
+    let frame = encode_frame(&confirmation(1)).unwrap();
+    let mut decoder = StreamDecoder::with_max_frame_len(2);
+    decoder.extend(&frame);
+    assert_eq!(
+        decoder.next_frame().unwrap().unwrap().data,
+        &frame[PacketHeader::SIZE..]
+    );
+
+    decoder.set_max_frame_len(1);
+    decoder.extend(&frame);
+    assert!(matches!(
+        decoder.next_frame(),
+        Err(ConnectionError::FrameTooLarge(2))
+    ));
+    assert_eq!(StreamDecoder::new().max_frame_len(), DEFAULT_MAX_FRAME_LEN);
+}
+
+#[tokio::test]
+async fn try_read_without_blocking() {
+    let (mut a, mut b) = duplex_pair(4096);
+    assert!(b.try_read_packet().await.unwrap().is_none());
+
+    a.write_packet(&confirmation(1)).await.unwrap();
+    assert_eq!(b.try_read_packet().await.unwrap(), Some(confirmation(1)));
+    assert!(b.try_read_packet().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn closed_pipe_is_an_error() {
+    let (a, mut b) = duplex_pair(4096);
+    a.close_with(&confirmation(1)).await.unwrap();
+    assert_eq!(b.wait_for_packet().await.unwrap(), confirmation(1));
+
+    let e = b.wait_for_packet().await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<std::io::Error>().unwrap().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn heartbeats_over_duplex() {
+    let (mut a, mut b) = duplex_pair(4096);
+    a.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: Some(Duration::from_millis(10)),
+        idle_timeout: None,
+    });
+    let pinger = tokio::spawn(async move {
+        // Only heartbeats go out while waiting for a packet that never comes
+        let _ = tokio::time::timeout(Duration::from_millis(100), a.wait_for_packet()).await;
+        a.write_packet(&confirmation(1)).await.unwrap();
+        a
+    });
+    b.set_keepalive(KeepaliveConfig {
+        heartbeat_interval: None,
+        idle_timeout: Some(Duration::from_millis(50)),
+    });
+    assert_eq!(b.wait_for_packet().await.unwrap(), confirmation(1));
+    pinger.await.unwrap();
+}
+
+#[tokio::test]
+async fn boxed_streams() {
+    let (a, b) = tokio::io::duplex(4096);
+    let mut a = TcpConnection::<Packet, Box<dyn StreamTransport + Send>>::new(Box::new(a));
+    let mut b = TcpConnection::<Packet, _>::new(b);
+    a.write_packet(&confirmation(1)).await.unwrap();
+    assert_eq!(b.wait_for_packet().await.unwrap(), confirmation(1));
+}
+
+#[tokio::test]
+async fn raw_stream_with_explicit_peer() {
+    // Neither half of a split stream is a `StreamTransport`
+    let (a, b) = tokio::io::duplex(4096);
+    let (read, mut write) = tokio::io::split(a);
+    let peer = "127.0.0.1:1234".parse().unwrap();
+    let mut a =
+        TcpConnection::<Packet, _>::from_io(tokio::io::join(read, tokio::io::sink()), Some(peer));
+    assert_eq!(a.peer_addr(), Some(peer));
+
+    let mut b = TcpConnection::<Packet, _>::new(b);
+    b.write_packet(&confirmation(1)).await.unwrap();
+    assert_eq!(a.wait_for_packet().await.unwrap(), confirmation(1));
+    write.shutdown().await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn packets_over_unix_socket() {
+    use tokio::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join(format!("ngmp-transport-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = TcpConnection::<Packet, _>::new(stream);
+        let packet = conn.wait_for_packet().await.unwrap();
+        conn.write_packet(&packet).await.unwrap();
+    });
+
+    let mut conn = TcpConnection::<Packet, _>::new(UnixStream::connect(&path).await.unwrap());
+    assert_eq!(conn.peer_addr(), None);
+    conn.write_packet(&transform("a")).await.unwrap();
+    assert_eq!(conn.wait_for_packet().await.unwrap(), transform("a"));
+    server.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
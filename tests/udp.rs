@@ -0,0 +1,237 @@
+//! UDP connections over loopback.
+
+use ngmp_protocol_impl::connection::{UdpClient, UdpListener};
+use ngmp_protocol_impl::framing::{decode_datagram, encode_frame};
+use ngmp_protocol_impl::interest::InterestManager;
+use ngmp_protocol_impl::server_launcher::gameplay::{
+    VehicleDeletePacket, VehicleTransformPacket, VehicleUpdatePacket,
+};
+use ngmp_protocol_impl::server_launcher::Packet;
+
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+fn transform() -> Packet {
+    Packet::VehicleTransform(VehicleTransformPacket {
+        player_id: 1,
+        vehicle_id: 2,
+        transform: "{\"pos\":[0,0,0]}".to_string(),
+    })
+}
+
+fn delete() -> Packet {
+    Packet::VehicleDelete(VehicleDeletePacket {
+        player_id: 1,
+        vehicle_id: 2,
+    })
+}
+
+/// Every packet in the next datagram.
+async fn recv_packets(socket: &UdpSocket) -> Vec<Packet> {
+    let mut buf = [0u8; 1500];
+    let n = socket.recv(&mut buf).await.unwrap();
+    decode_datagram(&buf[..n]).unwrap()
+}
+
+async fn recv_packet(socket: &UdpSocket) -> Packet {
+    let mut packets = recv_packets(socket).await;
+    assert_eq!(packets.len(), 1);
+    packets.remove(0)
+}
+
+#[tokio::test]
+async fn broadcast_reaches_every_target() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let targets = [a.local_addr().unwrap(), b.local_addr().unwrap()];
+    let report = listener.broadcast(&transform(), targets).await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.sent, 2);
+
+    assert_eq!(recv_packet(&a).await, transform());
+    assert_eq!(recv_packet(&b).await, transform());
+}
+
+#[tokio::test]
+async fn broadcast_reports_failed_targets() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // An IPv4 socket can't send to an IPv6 address
+    let unreachable: SocketAddr = "[::1]:9".parse().unwrap();
+    let targets = [unreachable, a.local_addr().unwrap()];
+    let report = listener.broadcast(&transform(), targets).await.unwrap();
+    assert_eq!(report.sent, 1);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, unreachable);
+
+    assert_eq!(recv_packet(&a).await, transform());
+}
+
+#[tokio::test]
+async fn sender_works_while_listener_waits() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let sender = listener.sender();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    let receive = tokio::spawn(async move { listener.wait_for_packet().await.unwrap() });
+
+    // The listener is parked in wait_for_packet on another task the whole time
+    let tick = {
+        let sender = sender.clone();
+        tokio::spawn(async move { sender.write_packet(client_addr, &transform()).await })
+    };
+    tick.await.unwrap().unwrap();
+    assert_eq!(recv_packet(&client).await, transform());
+
+    let report = sender.broadcast(&transform(), [client_addr]).await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(recv_packet(&client).await, transform());
+
+    let frame = encode_frame(&transform()).unwrap();
+    client.send_to(&frame, listener_addr).await.unwrap();
+    let (packet, from) = receive.await.unwrap();
+    assert_eq!(packet, transform());
+    assert_eq!(from, client_addr);
+}
+
+#[tokio::test]
+async fn coalesced_datagram_yields_every_packet() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket_addr = socket.local_addr().unwrap();
+    let mut client =
+        UdpClient::<Packet>::connect(UdpSocket::bind("127.0.0.1:0").await.unwrap(), listener_addr)
+            .await
+            .unwrap();
+
+    let mut datagram = encode_frame(&transform()).unwrap().to_vec();
+    datagram.extend_from_slice(&encode_frame(&delete()).unwrap());
+    socket.send_to(&datagram, listener_addr).await.unwrap();
+    assert_eq!(
+        listener.wait_for_packet().await.unwrap(),
+        (transform(), socket_addr)
+    );
+    assert_eq!(
+        listener.wait_for_packet().await.unwrap(),
+        (delete(), socket_addr)
+    );
+
+    let client_addr = client.local_addr().unwrap();
+    listener.write_bytes(&client_addr, &datagram).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), transform());
+    assert_eq!(client.wait_for_packet().await.unwrap(), delete());
+}
+
+#[tokio::test]
+async fn datagram_with_trailing_garbage_is_dropped() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let mut datagram = encode_frame(&transform()).unwrap().to_vec();
+    datagram.extend_from_slice(b"VT");
+    socket.send_to(&datagram, listener_addr).await.unwrap();
+    assert!(listener.wait_for_packet().await.is_err());
+
+    // None of the frames in the bad datagram got through
+    socket
+        .send_to(&encode_frame(&delete()).unwrap(), listener_addr)
+        .await
+        .unwrap();
+    assert_eq!(listener.wait_for_packet().await.unwrap().0, delete());
+}
+
+#[tokio::test]
+async fn client_flush_coalesces_queued_packets() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        receiver.local_addr().unwrap(),
+    )
+    .await
+    .unwrap();
+
+    for _ in 0..10 {
+        client.queue_packet(&transform()).unwrap();
+    }
+    assert_eq!(client.flush().await.unwrap(), 1);
+    assert_eq!(recv_packets(&receiver).await, vec![transform(); 10]);
+
+    // Nothing left over for the next tick
+    assert_eq!(client.flush().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn listener_flush_batches_per_target() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+    listener.set_mtu(64);
+    listener
+        .queue_broadcast(&transform(), [a_addr, b_addr])
+        .unwrap();
+    listener.queue_packet(a_addr, &delete()).unwrap();
+    listener.queue_packet(b_addr, &delete()).unwrap();
+    let report = listener.flush().await;
+    assert!(report.is_ok());
+    assert_eq!(report.sent, 2);
+
+    assert_eq!(recv_packets(&a).await, [transform(), delete()]);
+    assert_eq!(recv_packets(&b).await, [transform(), delete()]);
+}
+
+#[tokio::test]
+async fn oversized_packet_arrives_through_small_mtu() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    listener.set_mtu(100);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = UdpClient::<Packet>::connect(socket, listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.set_mtu(100);
+
+    let big = Packet::VehicleUpdate(VehicleUpdatePacket {
+        player_id: 1,
+        vehicle_id: 2,
+        ms: 3,
+        runtime_data: "r".repeat(1000),
+    });
+    client.write_packet(big.clone()).await.unwrap();
+    client.write_packet(delete()).await.unwrap();
+    let (packet, addr) = listener.wait_for_packet().await.unwrap();
+    assert_eq!(packet, big);
+    assert_eq!(listener.wait_for_packet().await.unwrap().0, delete());
+
+    listener.write_packet(addr, big.clone()).await.unwrap();
+    assert_eq!(client.wait_for_packet().await.unwrap(), big);
+}
+
+#[tokio::test]
+async fn broadcast_only_reaches_interested_players() {
+    let mut listener = UdpListener::<Packet>::bind("127.0.0.1:0").await.unwrap();
+    let near = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let far = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (near_addr, far_addr) = (near.local_addr().unwrap(), far.local_addr().unwrap());
+
+    let mut interest = InterestManager::default();
+    interest.set_player(near_addr, [0.0, 0.0, 0.0]);
+    interest.set_player(far_addr, [10_000.0, 0.0, 0.0]);
+    interest.update_vehicle((1, 2), far_addr, [0.0, 0.0, 0.0]);
+
+    let report = listener
+        .broadcast_interested(&transform(), &mut interest, &(1, 2))
+        .await
+        .unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.sent, 1);
+    assert_eq!(recv_packet(&near).await, transform());
+}